@@ -0,0 +1,238 @@
+use crate::error::{bail_code, ErrorCode};
+
+use anyhow::{Context, Result};
+use dirs::home_dir;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+// The unlock agent keeps the master password of one vault in memory for a while, so
+// commands run without a terminal (credential helpers, scripts) can open the vault.
+// It serves a socket only the user can connect to, and exits when the time is up. Any
+// program the user runs can get the master password from it, as the commands do.
+
+fn socket_path(vault: &str) -> Result<PathBuf> {
+    let home_dir = home_dir().context("Could not find home directory")?;
+
+    Ok(home_dir.join(".rspass").join(format!("agent-{}.sock", vault)))
+}
+
+// Start an agent for a vault in the background, replacing a running one
+pub fn spawn(vault: &str, master_password: &str, seconds: u64) -> Result<()> {
+    if !cfg!(unix) {
+        bail_code!(ErrorCode::Other, "The unlock agent is only available on Unix systems");
+    }
+
+    stop(vault)?;
+    spawn_detached(&["--vault", vault, "agent-serve", "--timeout", &seconds.to_string()], master_password)
+}
+
+// Run rspass with `args` in the background, hand it `secret` on stdin and wait until it
+// prints "ready"
+pub fn spawn_detached(args: &[&str], secret: &str) -> Result<()> {
+    let mut command = Command::new(std::env::current_exe().context("Failed to locate rspass")?);
+    command
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+
+    // Keep the agent alive after the terminal that started it goes away
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    let mut child = command.spawn().context("Failed to start the agent")?;
+
+    {
+        use std::io::{BufRead, BufReader, Write};
+
+        let mut stdin = child.stdin.take().context("Agent has no stdin")?;
+        stdin.write_all(format!("{}\n", secret).as_bytes())?;
+        drop(stdin);
+
+        // The agent reports once it is listening
+        let mut ready = String::new();
+        BufReader::new(child.stdout.take().context("Agent has no stdout")?).read_line(&mut ready)?;
+
+        if ready.trim() != "ready" {
+            bail_code!(ErrorCode::Other, "The agent failed to start");
+        }
+    }
+
+    Ok(())
+}
+
+// Master password held by the agent of a vault, None when no agent is running
+pub fn fetch(vault: &str) -> Option<String> {
+    let reply = request(vault, "get").ok()?;
+
+    (!reply.is_empty()).then_some(reply)
+}
+
+// Stop the agent of a vault. Returns false when none was running.
+pub fn stop(vault: &str) -> Result<bool> {
+    Ok(request(vault, "stop").is_ok())
+}
+
+#[cfg(unix)]
+fn request(vault: &str, command: &str) -> Result<String> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = UnixStream::connect(socket_path(vault)?)?;
+    stream.write_all(format!("{}\n", command).as_bytes())?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+
+    if reply.ends_with('\n') {
+        reply.pop();
+    }
+
+    Ok(reply)
+}
+
+#[cfg(not(unix))]
+fn request(_vault: &str, _command: &str) -> Result<String> {
+    bail_code!(ErrorCode::Other, "The unlock agent is only available on Unix systems");
+}
+
+// Run the agent (hidden agent-serve command). The master password comes on stdin.
+#[cfg(unix)]
+pub fn serve(vault: &str, seconds: u64) -> Result<()> {
+    use std::fs;
+    use std::io::{self, BufRead, BufReader, Write};
+    use zeroize::Zeroize;
+
+    let mut master_password = read_secret()?;
+
+    let path = socket_path(vault)?;
+    let listener = bind_private(&path)?;
+
+    println!("ready");
+    io::stdout().flush()?;
+
+    let expiry_path = path.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_secs(seconds));
+        let _ = fs::remove_file(&expiry_path);
+        std::process::exit(0);
+    });
+
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else { continue };
+
+        // The socket mode already keeps others out, unless it was changed
+        if !same_user(&stream) {
+            continue;
+        }
+
+        // A client that never sends anything must not block everyone else
+        let _ = stream.set_read_timeout(Some(std::time::Duration::from_secs(2)));
+
+        let mut command = String::new();
+
+        if BufReader::new(&stream).read_line(&mut command).is_err() {
+            continue;
+        }
+
+        match command.trim() {
+            "get" => {
+                let mut reply = format!("{}\n", master_password);
+                let _ = stream.write_all(reply.as_bytes());
+                reply.zeroize();
+            }
+
+            "stop" => {
+                // Removed before answering, so a new agent can take its place right away
+                let _ = fs::remove_file(&path);
+                let _ = stream.write_all(b"stopped\n");
+                break;
+            }
+
+            _ => {}
+        }
+    }
+
+    master_password.zeroize();
+
+    Ok(())
+}
+
+// Whether the process at the other end runs as the same user as this one
+#[cfg(unix)]
+pub fn same_user(stream: &std::os::unix::net::UnixStream) -> bool {
+    use std::os::fd::AsRawFd;
+
+    peer_uid(stream.as_raw_fd()) == Some(unsafe { libc::geteuid() })
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(fd: std::os::fd::RawFd) -> Option<libc::uid_t> {
+    let mut credentials = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    let result = unsafe { libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_PEERCRED, &mut credentials as *mut _ as *mut libc::c_void, &mut length) };
+
+    (result == 0).then_some(credentials.uid)
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn peer_uid(fd: std::os::fd::RawFd) -> Option<libc::uid_t> {
+    let (mut uid, mut gid) = (0, 0);
+
+    (unsafe { libc::getpeereid(fd, &mut uid, &mut gid) } == 0).then_some(uid)
+}
+
+// Secret handed over by spawn_detached
+pub fn read_secret() -> Result<String> {
+    let mut secret = String::new();
+    std::io::stdin().read_line(&mut secret)?;
+
+    if secret.ends_with('\n') {
+        secret.pop();
+    }
+
+    Ok(secret)
+}
+
+// Listen on a socket only the current user can connect to, replacing a stale one
+#[cfg(unix)]
+pub fn bind_private(path: &std::path::Path) -> Result<std::os::unix::net::UnixListener> {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+
+    let _ = fs::remove_file(path);
+
+    // Nobody else may connect, not even between binding and setting the mode
+    let mask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(mask) };
+
+    let listener = listener.with_context(|| format!("Failed to listen on '{}'", path.display()))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+pub fn serve(_vault: &str, _seconds: u64) -> Result<()> {
+    bail_code!(ErrorCode::Other, "The unlock agent is only available on Unix systems");
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn peers_of_the_same_user() {
+        let (client, server) = UnixStream::pair().unwrap();
+
+        assert!(same_user(&client));
+        assert!(same_user(&server));
+    }
+}
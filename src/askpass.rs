@@ -0,0 +1,143 @@
+use crate::config::AskpassRule;
+use crate::error::{bail_code, ErrorCode};
+use crate::vault::{EntryKind, Vault};
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+// Field that answers with the username instead of the password
+const USERNAME_FIELD: &str = "username";
+
+// Entry and field a prompt is answered from
+pub struct Target {
+    pub entry: String,
+    pub field: Option<String>
+}
+
+// The first rule whose pattern is found in the prompt. Programs end their prompts
+// differently, so surrounding whitespace is ignored.
+pub fn target(prompt: &str, rules: &[AskpassRule]) -> Result<Option<Target>> {
+    let prompt = prompt.trim();
+
+    for rule in rules {
+        let pattern = Regex::new(&rule.prompt)
+            .with_context(|| format!("Invalid askpass prompt pattern '{}' in the config", rule.prompt))?;
+
+        if let Some(captures) = pattern.captures(prompt) {
+            let mut entry = String::new();
+            captures.expand(&rule.entry, &mut entry);
+
+            return Ok(Some(Target { entry, field: rule.field.clone() }));
+        }
+    }
+
+    Ok(None)
+}
+
+// The secret to print: the password, the username or a custom field of the entry
+pub fn secret(vault: &Vault, target: &Target) -> Result<String> {
+    let Some(entry) = vault.get_entry(&target.entry) else {
+        bail_code!(ErrorCode::NotFound, "No entry found for '{}'", target.entry);
+    };
+
+    let secret = match target.field.as_deref() {
+        None if entry.kind == EntryKind::Note => {
+            bail_code!(ErrorCode::InvalidInput, "'{}' is a note and has no password", target.entry);
+        }
+
+        None => Some(&entry.password),
+        Some(USERNAME_FIELD) => entry.username.as_ref(),
+        Some(field) => entry.custom_fields.get(field)
+    };
+
+    let Some(secret) = secret else {
+        bail_code!(ErrorCode::NotFound, "'{}' has no {}", target.entry, target.field.as_deref().unwrap_or("password"));
+    };
+
+    // The caller reads a single line
+    if secret.contains('\n') {
+        bail_code!(ErrorCode::InvalidInput, "The {} of '{}' spans several lines", target.field.as_deref().unwrap_or("password"), target.entry);
+    }
+
+    Ok(secret.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(prompt: &str, entry: &str, field: Option<&str>) -> AskpassRule {
+        AskpassRule { prompt: prompt.to_string(), entry: entry.to_string(), field: field.map(str::to_string) }
+    }
+
+    fn entry(prompt: &str, rules: &[AskpassRule]) -> Option<(String, Option<String>)> {
+        target(prompt, rules).unwrap().map(|target| (target.entry, target.field))
+    }
+
+    #[test]
+    fn first_matching_rule() {
+        let rules = [
+            rule("^Enter passphrase for key '.*/id_ed25519'", "ssh/ed25519", None),
+            rule("^Username for 'https://github.com'", "github", Some("username")),
+            rule("github", "github", None)
+        ];
+
+        assert_eq!(entry("Enter passphrase for key '/home/me/.ssh/id_ed25519': ", &rules), Some(("ssh/ed25519".to_string(), None)));
+        assert_eq!(entry("Username for 'https://github.com': ", &rules), Some(("github".to_string(), Some("username".to_string()))));
+        assert_eq!(entry("Password for 'https://me@github.com': ", &rules), Some(("github".to_string(), None)));
+        assert_eq!(entry("Password for 'https://gitlab.com': ", &rules), None);
+
+        // Surrounding whitespace is not part of the prompt
+        assert_eq!(entry("\n  Enter passphrase for key '/root/.ssh/id_ed25519':\n", &rules), Some(("ssh/ed25519".to_string(), None)));
+    }
+
+    #[test]
+    fn capture_groups() {
+        let rules = [
+            rule(r"^Password for '(?P<scheme>\w+)://(?:[^@]+@)?(?P<host>[^']+)'", "${host}/$scheme", None),
+            rule(r"^Enter passphrase for key '.*/([^/']+)'", "ssh/$1", None),
+            rule(r"^\[sudo\] password for (\w+)", "sudo-${1}x", None)
+        ];
+
+        assert_eq!(entry("Password for 'https://me@github.com': ", &rules).unwrap().0, "github.com/https");
+        assert_eq!(entry("Enter passphrase for key '/home/me/.ssh/id_rsa': ", &rules).unwrap().0, "ssh/id_rsa");
+        assert_eq!(entry("[sudo] password for alice: ", &rules).unwrap().0, "sudo-alicex");
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        let rules = [rule("(unclosed", "x", None)];
+
+        let e = target("anything", &rules).err().unwrap();
+        assert_eq!(e.to_string(), "Invalid askpass prompt pattern '(unclosed' in the config");
+
+        // Rules after a match are not looked at
+        assert!(target("ok", &[rule("ok", "x", None), rules[0].clone()]).unwrap().is_some());
+    }
+
+    #[test]
+    fn secrets() {
+        let mut vault = Vault::in_memory();
+        vault.add_password("github", "hunter2").unwrap();
+        vault.set_details("github", Some("octocat"), None).unwrap();
+        vault.set_custom_field("github", "otp-seed", "JBSWY3DP").unwrap();
+        vault.set_custom_field("github", "recovery", "one\ntwo").unwrap();
+        vault.set_note("wifi", "not a password").unwrap();
+
+        let secret = |entry: &str, field: Option<&str>| secret(&vault, &Target { entry: entry.to_string(), field: field.map(str::to_string) });
+
+        assert_eq!(secret("github", None).unwrap(), "hunter2");
+        assert_eq!(secret("github", Some("username")).unwrap(), "octocat");
+        assert_eq!(secret("github", Some("otp-seed")).unwrap(), "JBSWY3DP");
+
+        for (entry, field, code) in [
+            ("gitlab", None, ErrorCode::NotFound),
+            ("github", Some("missing"), ErrorCode::NotFound),
+            ("github", Some("recovery"), ErrorCode::InvalidInput),
+            ("wifi", None, ErrorCode::InvalidInput),
+            ("wifi", Some("username"), ErrorCode::NotFound)
+        ] {
+            assert_eq!(ErrorCode::of(&secret(entry, field).unwrap_err()), code, "{} {:?}", entry, field);
+        }
+    }
+}
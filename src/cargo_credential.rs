@@ -0,0 +1,325 @@
+use crate::vault::{EntryKind, Vault};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+use zeroize::Zeroize;
+
+// Version of cargo's credential provider protocol spoken here
+const VERSION: u32 = 1;
+
+#[derive(Deserialize)]
+struct Request {
+    v: u32,
+    registry: Registry,
+    #[serde(flatten)]
+    action: Action
+}
+
+#[derive(Deserialize)]
+struct Registry {
+    #[serde(rename = "index-url")]
+    index_url: String,
+    name: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Action {
+    Get,
+    Login {
+        token: Option<String>
+    },
+    Logout,
+    #[serde(other)]
+    Unknown
+}
+
+impl Drop for Action {
+    fn drop(&mut self) {
+        if let Action::Login { token } = self {
+            token.zeroize();
+        }
+    }
+}
+
+// The vault, opened on the first request that needs it
+struct Session<U, P> {
+    vault: Option<(Vault, String)>,
+    unlock: U,
+    prompt_token: P
+}
+
+// Speak the protocol with cargo: announce the supported versions, then answer requests
+// until cargo closes the input. Tokens are entries named after the registry index URL.
+pub fn run(
+    input: impl BufRead,
+    mut output: impl Write,
+    unlock: impl FnMut() -> Result<String>,
+    prompt_token: impl FnMut(&str) -> Result<String>
+) -> Result<()> {
+    writeln!(output, "{}", json!({ "v": [VERSION] }))?;
+    output.flush()?;
+
+    let mut session = Session { vault: None, unlock, prompt_token };
+
+    for line in input.lines() {
+        let mut line = line.context("Failed to read the request from cargo")?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request = serde_json::from_str::<Request>(&line).context("Invalid request from cargo");
+        line.zeroize();
+
+        let response = match request.and_then(|request| session.handle(&request)) {
+            Ok(response) => json!({ "Ok": response }),
+            Err(e) => json!({ "Err": error(&e) })
+        };
+
+        writeln!(output, "{}", response)?;
+        output.flush()?;
+    }
+
+    if let Some((_, master_password)) = session.vault.as_mut() {
+        master_password.zeroize();
+    }
+
+    Ok(())
+}
+
+// Errors cargo knows by kind; anything else is shown to the user with its causes
+#[derive(Debug)]
+enum Refusal {
+    NotFound,
+    OperationNotSupported
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Refusal::NotFound => write!(f, "not-found"),
+            Refusal::OperationNotSupported => write!(f, "operation-not-supported")
+        }
+    }
+}
+
+impl std::error::Error for Refusal {}
+
+fn error(e: &anyhow::Error) -> Value {
+    if let Some(refusal) = e.downcast_ref::<Refusal>() {
+        return json!({ "kind": refusal.to_string() });
+    }
+
+    let causes: Vec<String> = e.chain().skip(1).map(|cause| cause.to_string()).collect();
+
+    json!({ "kind": "other", "message": e.to_string(), "caused-by": causes })
+}
+
+impl<U, P> Session<U, P>
+where
+    U: FnMut() -> Result<String>,
+    P: FnMut(&str) -> Result<String>
+{
+    fn handle(&mut self, request: &Request) -> Result<Value> {
+        if request.v != VERSION {
+            anyhow::bail!("Unsupported credential provider protocol version {}", request.v);
+        }
+
+        let index_url = &request.registry.index_url;
+        let registry = request.registry.name.as_deref().unwrap_or(index_url);
+
+        match &request.action {
+            Action::Get => {
+                let (vault, _) = self.vault()?;
+
+                let Some(entry) = vault.get_entry(index_url).filter(|entry| entry.kind != EntryKind::Note) else {
+                    return Err(Refusal::NotFound.into());
+                };
+
+                Ok(json!({
+                    "kind": "get",
+                    "token": entry.password,
+                    "cache": "session",
+                    "operation_independent": true
+                }))
+            }
+
+            Action::Login { token } => {
+                let mut token = match token {
+                    Some(token) => token.clone(),
+                    None => (self.prompt_token)(&format!("Enter the token for {}: ", registry))?
+                };
+
+                let result = self.store(index_url, &token);
+                token.zeroize();
+                result?;
+
+                Ok(json!({ "kind": "login" }))
+            }
+
+            Action::Logout => {
+                let (vault, master_password) = self.vault()?;
+
+                if vault.get_entry(index_url).is_none_or(|entry| entry.kind == EntryKind::Note) {
+                    return Err(Refusal::NotFound.into());
+                }
+
+                vault.remove_password(index_url)?;
+                vault.save(master_password)?;
+                vault.purge_attachments()?;
+
+                Ok(json!({ "kind": "logout" }))
+            }
+
+            Action::Unknown => Err(Refusal::OperationNotSupported.into())
+        }
+    }
+
+    fn vault(&mut self) -> Result<&mut (Vault, String)> {
+        if self.vault.is_none() {
+            let mut master_password = (self.unlock)()?;
+
+            match Vault::load(&master_password) {
+                Ok(vault) => self.vault = Some((vault, master_password)),
+                Err(e) => {
+                    master_password.zeroize();
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(self.vault.as_mut().unwrap())
+    }
+
+    fn store(&mut self, index_url: &str, token: &str) -> Result<()> {
+        let (vault, master_password) = self.vault()?;
+
+        match vault.get_entry(index_url) {
+            Some(entry) if entry.kind == EntryKind::Note => {
+                anyhow::bail!("'{}' is a note, not a token", index_url);
+            }
+
+            Some(entry) if entry.password == token => return Ok(()),
+            Some(_) => vault.update_password(index_url, token)?,
+            None => vault.add_password(index_url, token)?
+        }
+
+        vault.save(master_password)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MASTER_PASSWORD};
+    use std::cell::Cell;
+
+    const CRATES_IO: &str = "https://github.com/rust-lang/crates.io-index";
+
+    fn request(kind: &str, extra: &str) -> String {
+        format!(r#"{{"v":1,"registry":{{"index-url":"{}","name":"crates-io"}},"kind":"{}"{}}}"#, CRATES_IO, kind, extra)
+    }
+
+    // A session as cargo runs it: the requests cargo writes, and the lines rspass writes
+    // back, the first being the version announcement. Also gives the number of unlocks.
+    fn transcript(requests: &[String]) -> (Vec<Value>, usize) {
+        let unlocks = Cell::new(0);
+        let input = requests.join("\n");
+        let mut output = Vec::new();
+
+        run(input.as_bytes(), &mut output, || {
+            unlocks.set(unlocks.get() + 1);
+            Ok(MASTER_PASSWORD.to_string())
+        }, |_| anyhow::bail!("No terminal")).unwrap();
+
+        let lines = String::from_utf8(output).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+        (lines, unlocks.get())
+    }
+
+    #[test]
+    fn handshake() {
+        let _vault = testing::fresh_vault();
+        let (lines, unlocks) = transcript(&[]);
+
+        assert_eq!(lines, [json!({ "v": [1] })]);
+        assert_eq!(unlocks, 0);
+    }
+
+    #[test]
+    fn get() {
+        let _vault = testing::fresh_vault();
+        testing::change(|vault| vault.add_password(CRATES_IO, "cio-token").unwrap());
+
+        let (lines, unlocks) = transcript(&[
+            request("get", r#","operation":"read","args":[]"#),
+            request("get", r#","operation":"publish","name":"rspass","vers":"1.0.0","cksum":"abc""#)
+        ]);
+
+        let token = json!({ "Ok": { "kind": "get", "token": "cio-token", "cache": "session", "operation_independent": true } });
+        assert_eq!(lines[1..], [token.clone(), token]);
+        assert_eq!(unlocks, 1);
+    }
+
+    #[test]
+    fn refusals() {
+        let _vault = testing::fresh_vault();
+        testing::change(|vault| vault.set_note("https://notes.example/index", "not a token").unwrap());
+
+        let (lines, _) = transcript(&[
+            request("get", ""),
+            request("get", "").replace(CRATES_IO, "https://notes.example/index"),
+            request("unknown-kind", ""),
+            request("get", "").replace(r#""v":1"#, r#""v":2"#),
+            "not json".to_string()
+        ]);
+
+        assert_eq!(lines[1], json!({ "Err": { "kind": "not-found" } }));
+        assert_eq!(lines[2], json!({ "Err": { "kind": "not-found" } }));
+        assert_eq!(lines[3], json!({ "Err": { "kind": "operation-not-supported" } }));
+        assert_eq!(lines[4]["Err"]["message"], "Unsupported credential provider protocol version 2");
+        assert_eq!(lines[5]["Err"]["kind"], "other");
+        assert_eq!(lines[5]["Err"]["message"], "Invalid request from cargo");
+    }
+
+    #[test]
+    fn login_and_logout() {
+        let _vault = testing::fresh_vault();
+
+        let (lines, _) = transcript(&[
+            request("login", r#","token":"first""#),
+            request("login", r#","token":"second""#),
+            request("get", "")
+        ]);
+
+        assert_eq!(lines[1..], [
+            json!({ "Ok": { "kind": "login" } }),
+            json!({ "Ok": { "kind": "login" } }),
+            json!({ "Ok": { "kind": "get", "token": "second", "cache": "session", "operation_independent": true } })
+        ]);
+        assert_eq!(Vault::load(MASTER_PASSWORD).unwrap().get_password(CRATES_IO).map(String::as_str), Some("second"));
+
+        // Without a token in the request it is asked for, which fails without a terminal
+        let (lines, _) = transcript(&[request("login", "")]);
+        assert_eq!(lines[1]["Err"]["message"], "No terminal");
+
+        let (lines, _) = transcript(&[request("logout", ""), request("logout", "")]);
+
+        assert_eq!(lines[1..], [json!({ "Ok": { "kind": "logout" } }), json!({ "Err": { "kind": "not-found" } })]);
+        assert!(Vault::load(MASTER_PASSWORD).unwrap().get_entry(CRATES_IO).is_none());
+    }
+
+    #[test]
+    fn wrong_master_password() {
+        let _vault = testing::fresh_vault();
+        let mut output = Vec::new();
+
+        run(request("get", "").as_bytes(), &mut output, || Ok("wrong".to_string()), |_| anyhow::bail!("No terminal")).unwrap();
+
+        let reply: Value = serde_json::from_str(String::from_utf8(output).unwrap().lines().nth(1).unwrap()).unwrap();
+        assert_eq!(reply["Err"]["kind"], "other");
+    }
+}
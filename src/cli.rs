@@ -1,9 +1,29 @@
+use crate::cloud::CredentialType;
+use crate::docker_credential;
+use crate::git_credential;
+use crate::listing::{Column, SortKey};
+use crate::native_host::Browser;
+use crate::otp::OtpAlgorithm;
+use crate::output::Format;
+use crate::search::SearchMode;
+use crate::vault::{EntryKind, UrlMatch};
+
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+// Rotation intervals of at least a day, as the vault accepts them
+fn rotation_days() -> clap::builder::RangedI64ValueParser<u32> {
+    clap::value_parser!(u32).range(1..)
+}
 
 #[derive(Parser)]
 pub struct Args {
     #[command(subcommand)]
-    pub command: Commands
+    pub command: Commands,
+    #[arg(long, global = true, value_enum, default_value_t = Format::Human)]
+    pub format: Format, // Output format (human, json, tsv)
+    #[arg(long, global = true)]
+    pub vault: Option<String> // Named vault to use instead of the default one
 }
 
 #[derive(Subcommand)]
@@ -15,17 +35,229 @@ pub enum Commands {
     Add {
         service: String, // Service name (github, email, ...)
         #[arg(short, long)]
-        password: Option<String> // Password to store
+        password: Option<String>, // Password to store
+        #[arg(long, value_parser = rotation_days())]
+        rotate_days: Option<u32>, // Rotation interval in days
+        #[arg(short, long)]
+        username: Option<String>,
+        #[arg(long)]
+        folder: Option<String>, // Folder path such as work/servers
+        #[arg(short, long)]
+        tag: Vec<String>, // Tags, repeat for more than one
+        #[arg(long)]
+        url: Vec<String>, // Sites the entry is for, repeat for more than one
+        #[arg(long, value_enum)]
+        url_match: Option<UrlMatch> // How the URLs are matched (default: domain)
     },
     
     // Retrieve a password for a service
     Get {
-        service: String
+        service: String,
+        #[arg(short, long)]
+        clip: bool, // Copy to the clipboard instead of printing
+        #[arg(long)]
+        clear_after: Option<u64> // Seconds before the clipboard is cleared (overrides clipboard-timeout)
     },
     
     // List all stored service names
-    List,
+    List {
+        #[arg(long, value_enum, default_value_t = SortKey::Name)]
+        sort: SortKey,
+        #[arg(short, long)]
+        reverse: bool,
+        #[arg(short, long)]
+        tag: Vec<String>, // Only entries with all of these tags
+        #[arg(long)]
+        folder: Option<String>, // Only entries in this folder or below
+        #[arg(long = "type", value_enum)]
+        kind: Option<EntryKind>,
+        #[arg(short, long, value_enum, value_delimiter = ',')]
+        columns: Vec<Column> // Extra columns, e.g. username,age
+    },
+
+    // Run a command with secrets in its environment
+    Exec {
+        #[arg(short, long, value_name = "NAME=SERVICE")]
+        env: Vec<String>, // Variable to set from an entry, repeat for more than one
+        #[arg(short, long)]
+        mask: bool, // Replace secret values in the command's output
+        #[arg(last = true, required = true)]
+        command: Vec<String>
+    },
+
+    // Fill rspass:// references and {{ rspass "entry" "field" }} placeholders in a template
+    Inject {
+        #[arg(short, long)]
+        input: Option<PathBuf>, // Template file, stdin when missing
+        #[arg(short, long)]
+        output: Option<PathBuf> // Output file (created with mode 0600), stdout when missing
+    },
+
+    // Rename an entry or move it to another folder or vault
+    Mv {
+        from: String,
+        to: Option<String>, // New name, the same name when missing
+        #[arg(long)]
+        folder: Option<String>, // Folder to move the entry to, empty for none
+        #[arg(long)]
+        to_vault: Option<String>, // Named vault to move the entry to
+        #[arg(short, long)]
+        force: bool // Overwrite an existing entry
+    },
+
+    // Copy an entry, optionally to another folder or vault
+    Cp {
+        from: String,
+        to: Option<String>,
+        #[arg(long)]
+        folder: Option<String>,
+        #[arg(long)]
+        to_vault: Option<String>,
+        #[arg(short, long)]
+        force: bool
+    },
+
+    // Apply the adds, updates, removals and tag changes of a YAML manifest at once
+    Apply {
+        manifest: PathBuf,
+        #[arg(short = 'n', long)]
+        dry_run: bool // Show the changes without saving them
+    },
+
+    // Credential helper for git: reads the request from stdin (credential.helper = "!rspass git-credential")
+    GitCredential {
+        #[arg(value_enum)]
+        operation: git_credential::Operation
+    },
+
+    // Answer a prompt for SUDO_ASKPASS, SSH_ASKPASS or GIT_ASKPASS, also run as rspass-askpass
+    Askpass {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        prompt: Vec<String> // The prompt, as the program passes it
+    },
+
+    // Native messaging host for the browser extension, started by the browser
+    NativeHost {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, hide = true)]
+        caller: Vec<String> // Extension origin or manifest path, as the browser passes them
+    },
+
+    // Print the native messaging host manifest for a browser, or install it with --install
+    NativeHostManifest {
+        #[arg(long, value_enum)]
+        browser: Browser,
+        #[arg(long = "extension-id", required = true)]
+        extension_ids: Vec<String>, // Extensions allowed to start the host
+        #[arg(long)]
+        install: bool // Write it where the browser looks for it
+    },
+
+    // Serve a REST API for local scripts and tools, authenticated with a bearer token
+    Serve {
+        #[arg(short, long, default_value_t = 7863)]
+        port: u16, // Listens on 127.0.0.1 only
+        #[arg(long, conflicts_with = "port")]
+        socket: Option<PathBuf>, // Listen on a Unix socket instead
+        #[arg(long)]
+        token_file: Option<PathBuf>, // Defaults to ~/.rspass/serve-<vault>.token
+        #[arg(long)]
+        audit_log: Option<PathBuf>, // Defaults to ~/.rspass/serve-<vault>.log
+        #[arg(long, default_value_t = 15)]
+        timeout: u64, // Minutes until the vault is locked again, 0 for never
+        #[arg(long)]
+        unlock: bool // Ask for the master password now instead of waiting for /unlock
+    },
+
+    // Provide the Secret Service D-Bus API (org.freedesktop.secrets) for desktop apps
+    SecretService {
+        #[arg(long)]
+        address: Option<String> // D-Bus address of the bus, defaults to the session bus
+    },
+
+    // Print credentials from an entry's fields for AWS credential_process or kubectl
+    CloudCredentials {
+        service: String,
+        #[arg(short = 't', long = "type", value_enum, default_value_t = CredentialType::Aws)]
+        kind: CredentialType
+    },
+
+    // Credential helper for docker, also run as docker-credential-rspass (a link to rspass)
+    DockerCredential {
+        #[arg(value_enum)]
+        operation: docker_credential::Operation
+    },
+
+    // Credential provider for cargo, which runs it as "rspass --cargo-plugin"
+    CargoCredential {
+        #[arg(long)]
+        cargo_plugin: bool // Added by cargo when it runs a provider
+    },
+
+    // Manage SSH keys kept in the vault
+    SshKey {
+        #[command(subcommand)]
+        command: SshKeyCommands
+    },
+
+    // Serve the SSH keys of the vault to ssh over the ssh-agent protocol
+    SshAgent {
+        #[arg(short = 'a', long)]
+        socket: Option<PathBuf>, // Defaults to ~/.rspass/ssh-agent-<vault>.sock
+        #[arg(long)]
+        foreground: bool, // Serve in this process instead of in the background
+        #[arg(short, long)]
+        kill: bool, // Stop the agent serving the socket
+        #[arg(long, hide = true)]
+        stdin: bool // Master password on stdin, when started in the background
+    },
+
+    // Keep the vault unlocked in the background, for commands run without a terminal
+    Unlock {
+        #[arg(long, default_value_t = 15)]
+        timeout: u64 // Minutes until the vault is locked again
+    },
+
+    // Lock a vault unlocked with 'unlock'
+    Lock,
+
+    // Edit all fields of an entry in $EDITOR
+    Edit {
+        service: String
+    },
+
+    // Set the username, folder and tags of an entry
+    Details {
+        service: String,
+        #[arg(short, long)]
+        username: Option<String>, // Empty to clear
+        #[arg(long)]
+        folder: Option<String>, // Empty to clear
+        #[arg(short, long)]
+        tag: Vec<String>, // Tags to add
+        #[arg(long)]
+        untag: Vec<String>, // Tags to remove
+        #[arg(long)]
+        url: Vec<String>, // URLs to add
+        #[arg(long)]
+        remove_url: Vec<String>, // URLs to remove
+        #[arg(long, value_enum)]
+        url_match: Option<UrlMatch>
+    },
+
+    // Find entries by name and other fields
+    Search {
+        query: String,
+        #[arg(short, long, value_enum, default_value_t = SearchMode::Fuzzy)]
+        mode: SearchMode, // How the query is matched
+        #[arg(short = 's', long)]
+        case_sensitive: bool
+    },
     
+    // Find the entries for a site by their URLs
+    FindUrl {
+        url: String
+    },
+
     // Remove a password for a service
     Remove {
         service: String
@@ -38,6 +270,231 @@ pub enum Commands {
         password: Option<String>
     },
 
+    // Show previous passwords of a service or restore one of them
+    History {
+        service: String,
+        #[arg(long)]
+        show: bool, // Print the previous passwords themselves
+        #[arg(long)]
+        restore: Option<usize> // Version to make current again (1 is the most recent)
+    },
+
+    // Show or change settings
+    Config {
+        key: Option<String>, // Setting name (history-limit, attachment-max-mb, clipboard-timeout)
+        value: Option<String> // New value
+    },
+
+    // Show a one-time code for a service, or store its TOTP/HOTP secret
+    Otp {
+        service: String,
+        #[arg(long)]
+        set: Option<String>, // otpauth:// URI or base32 secret
+        #[arg(long)]
+        hotp: bool, // Treat a raw secret as counter-based (HOTP)
+        #[arg(long)]
+        counter: Option<u64>, // Next HOTP counter value
+        #[arg(long)]
+        digits: Option<u32>,
+        #[arg(long)]
+        period: Option<u64>, // TOTP time step in seconds
+        #[arg(long)]
+        algorithm: Option<OtpAlgorithm>, // SHA1, SHA256 or SHA512
+        #[arg(long)]
+        remove: bool // Remove the stored secret
+    },
+
+    // Create or replace a secure note, or set the note of an existing entry
+    Note {
+        service: String,
+        #[arg(short, long)]
+        file: Option<PathBuf> // Read the note from a file instead of stdin
+    },
+
+    // Manage encrypted file attachments
+    Attach {
+        #[command(subcommand)]
+        command: AttachCommands
+    },
+
+    // Manage custom fields such as access keys
+    Field {
+        #[command(subcommand)]
+        command: FieldCommands
+    },
+
+    // Set or clear the rotation interval of a service
+    Rotation {
+        service: String,
+        #[arg(value_parser = rotation_days())]
+        days: Option<u32> // Rotation interval in days, omit to clear it
+    },
+
+    // Show services whose password is overdue or close to due for rotation
+    Due {
+        #[arg(long, default_value_t = 14)]
+        within: u32 // Also show passwords due within this many days
+    },
+
+    // Audit stored passwords
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommands
+    },
+
     // Open UI version
-    UI
+    UI,
+
+    // Unlock agent started by 'unlock', reads the master password from stdin
+    #[command(hide = true)]
+    AgentServe {
+        #[arg(long)]
+        timeout: u64 // Seconds
+    },
+
+    // Clear the clipboard after a delay if it still holds the copied value (used by get --clip)
+    #[command(hide = true)]
+    ClipClear {
+        #[arg(long)]
+        after: u64
+    }
+}
+
+#[derive(Subcommand)]
+pub enum SshKeyCommands {
+    // Import an OpenSSH or PEM private key file
+    Import {
+        service: String,
+        file: PathBuf,
+        #[arg(long)]
+        confirm: bool, // Ask before the agent signs with the key
+        #[arg(long)]
+        lifetime: Option<u64> // Seconds the agent offers the key after it starts
+    },
+
+    // Generate a new Ed25519 key
+    Generate {
+        service: String,
+        #[arg(short = 'C', long)]
+        comment: Option<String>, // Defaults to the entry name
+        #[arg(long)]
+        confirm: bool,
+        #[arg(long)]
+        lifetime: Option<u64>
+    },
+
+    // Print the public key in authorized_keys format
+    Public {
+        service: String
+    },
+
+    // Change the agent constraints of a key
+    Set {
+        service: String,
+        #[arg(long)]
+        confirm: Option<bool>,
+        #[arg(long)]
+        lifetime: Option<u64> // 0 removes the limit
+    }
+}
+
+#[derive(Subcommand)]
+pub enum FieldCommands {
+    // Set a field, asking for the value when it is not given
+    Set {
+        service: String,
+        name: String,
+        value: Option<String>
+    },
+
+    // Print the value of a field
+    Get {
+        service: String,
+        name: String
+    },
+
+    // Remove a field
+    Rm {
+        service: String,
+        name: String
+    },
+
+    // List the field names of an entry
+    List {
+        service: String
+    }
+}
+
+#[derive(Subcommand)]
+pub enum AttachCommands {
+    // Attach a file to an entry
+    Add {
+        service: String,
+        file: PathBuf,
+        #[arg(long)]
+        name: Option<String> // Attachment name (defaults to the file name)
+    },
+
+    // Decrypt an attachment into a file
+    Get {
+        service: String,
+        name: String,
+        #[arg(short, long)]
+        output: Option<PathBuf>, // Defaults to the attachment name in the current directory
+        #[arg(long)]
+        force: bool // Overwrite an existing file
+    },
+
+    // Remove an attachment
+    Rm {
+        service: String,
+        name: String
+    },
+
+    // List the attachments of an entry
+    List {
+        service: String
+    }
+}
+
+#[derive(Subcommand)]
+pub enum AuditCommands {
+    // Check stored passwords against a local Have I Been Pwned dump
+    Breached {
+        #[arg(long)]
+        hibp_dir: PathBuf // Directory with range files or a built index
+    },
+
+    // List passwords that have not been changed for a while
+    Age {
+        #[arg(long, default_value_t = 90)]
+        days: u32 // Minimum age in days
+    },
+
+    // Build a binary index from a raw Have I Been Pwned dump
+    BuildIndex {
+        input: PathBuf, // Ordered hash file or directory of range files
+        #[arg(long)]
+        hibp_dir: Option<PathBuf> // Where to write the index (defaults next to the input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Commands, clap::Error> {
+        Args::try_parse_from([&["rspass"], args].concat()).map(|args| args.command)
+    }
+
+    #[test]
+    fn rotation_days_start_at_one() {
+        assert!(matches!(parse(&["add", "github", "--rotate-days", "90"]), Ok(Commands::Add { rotate_days: Some(90), .. })));
+        assert!(matches!(parse(&["rotation", "github", "1"]), Ok(Commands::Rotation { days: Some(1), .. })));
+        assert!(matches!(parse(&["rotation", "github"]), Ok(Commands::Rotation { days: None, .. })));
+
+        for args in [&["add", "github", "--rotate-days", "0"][..], &["rotation", "github", "0"], &["rotation", "github", "-1"]] {
+            assert!(parse(args).is_err());
+        }
+    }
 }
@@ -0,0 +1,249 @@
+use crate::error::{bail_code, ErrorCode};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Access to a clipboard, so the clearing logic works with any provider
+pub trait Clipboard {
+    fn get(&mut self) -> Result<String>;
+    fn set(&mut self, text: &str) -> Result<()>;
+
+    fn clear(&mut self) -> Result<()> {
+        self.set("")
+    }
+}
+
+// Fingerprint of a copied value, so the clearing helper never sees the value itself
+pub fn fingerprint(text: &str) -> String {
+    hex::encode(Sha256::digest(text.as_bytes()))
+}
+
+// Clear the clipboard, but only if it still holds the value with this fingerprint
+pub fn clear_if_unchanged(clipboard: &mut dyn Clipboard, expected: &str) -> Result<bool> {
+    let current = clipboard.get()?;
+
+    // Some tools append a line break when reading the clipboard
+    let trimmed = current.strip_suffix('\n').map(|text| text.strip_suffix('\r').unwrap_or(text));
+
+    if fingerprint(&current) == expected || trimmed.is_some_and(|text| fingerprint(text) == expected) {
+        clipboard.clear()?;
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+// System clipboard through the platform's command-line tools. Unlike an in-process
+// X11 selection, the copied value outlives the short-lived rspass process.
+pub struct CommandClipboard {
+    copy: &'static [&'static str],
+    paste: &'static [&'static str],
+    clear: Option<&'static [&'static str]>
+}
+
+impl CommandClipboard {
+    // Pick the clipboard tool available on this system
+    pub fn detect() -> Result<Self> {
+        #[cfg(target_os = "macos")]
+        {
+            Ok(CommandClipboard {
+                copy: &["pbcopy"],
+                paste: &["pbpaste"],
+                clear: None
+            })
+        }
+
+        #[cfg(target_os = "windows")]
+        {
+            Ok(CommandClipboard {
+                copy: &["powershell", "-NoProfile", "-Command", "[Console]::In.ReadToEnd() | Set-Clipboard"],
+                paste: &["powershell", "-NoProfile", "-Command", "Get-Clipboard -Raw"],
+                clear: Some(&["powershell", "-NoProfile", "-Command", "Set-Clipboard -Value $null"])
+            })
+        }
+
+        #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+        {
+            let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+            let x11 = std::env::var_os("DISPLAY").is_some();
+
+            if wayland && is_installed("wl-copy") {
+                Ok(CommandClipboard {
+                    copy: &["wl-copy"],
+                    paste: &["wl-paste", "--no-newline"],
+                    clear: Some(&["wl-copy", "--clear"])
+                })
+            } else if x11 && is_installed("xclip") {
+                Ok(CommandClipboard {
+                    copy: &["xclip", "-selection", "clipboard"],
+                    paste: &["xclip", "-selection", "clipboard", "-o"],
+                    clear: None
+                })
+            } else if x11 && is_installed("xsel") {
+                Ok(CommandClipboard {
+                    copy: &["xsel", "--clipboard", "--input"],
+                    paste: &["xsel", "--clipboard", "--output"],
+                    clear: Some(&["xsel", "--clipboard", "--delete"])
+                })
+            } else {
+                bail_code!(ErrorCode::ClipboardUnavailable, "No clipboard available. Install wl-clipboard, xclip or xsel and run inside a graphical session.")
+            }
+        }
+    }
+}
+
+impl Clipboard for CommandClipboard {
+    fn get(&mut self) -> Result<String> {
+        let output = Command::new(self.paste[0])
+            .args(&self.paste[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .with_context(|| format!("Failed to run '{}'", self.paste[0]))?;
+
+        // Tools exit with an error when the clipboard is empty
+        if !output.status.success() {
+            return Ok(String::new());
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn set(&mut self, text: &str) -> Result<()> {
+        let mut child = Command::new(self.copy[0])
+            .args(&self.copy[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to run '{}'", self.copy[0]))?;
+
+        child.stdin.take().context("Clipboard tool has no stdin")?.write_all(text.as_bytes())?;
+
+        if !child.wait()?.success() {
+            anyhow::bail!("'{}' failed to set the clipboard", self.copy[0]);
+        }
+
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        let Some(clear) = self.clear else {
+            return self.set("");
+        };
+
+        let status = Command::new(clear[0])
+            .args(&clear[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .with_context(|| format!("Failed to run '{}'", clear[0]))?;
+
+        if !status.success() {
+            anyhow::bail!("'{}' failed to clear the clipboard", clear[0]);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn is_installed(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+// Start a detached helper that clears the clipboard after `seconds`
+pub fn spawn_clear_helper(value: &str, seconds: u64) -> Result<()> {
+    let mut command = Command::new(std::env::current_exe().context("Failed to locate rspass")?);
+    command
+        .args(["clip-clear", "--after", &seconds.to_string()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    // Keep the helper alive after the terminal that started it goes away
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x00000008;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+
+        command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+    }
+
+    let mut child = command.spawn().context("Failed to start the clipboard helper")?;
+
+    // Passed over stdin so it does not show up in the process list
+    writeln!(child.stdin.take().context("Clipboard helper has no stdin")?, "{}", fingerprint(value))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // In-memory clipboard. Some providers hand the value back with a line break added.
+    #[derive(Default)]
+    struct MemoryClipboard {
+        text: String,
+        newline: bool
+    }
+
+    impl Clipboard for MemoryClipboard {
+        fn get(&mut self) -> Result<String> {
+            match self.newline {
+                true => Ok(format!("{}\n", self.text)),
+                false => Ok(self.text.clone())
+            }
+        }
+
+        fn set(&mut self, text: &str) -> Result<()> {
+            self.text = text.to_string();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn clears_unchanged_value() {
+        let mut clipboard = MemoryClipboard::default();
+        clipboard.set("hunter2").unwrap();
+
+        assert!(clear_if_unchanged(&mut clipboard, &fingerprint("hunter2")).unwrap());
+        assert_eq!(clipboard.text, "");
+    }
+
+    #[test]
+    fn keeps_replaced_value() {
+        let mut clipboard = MemoryClipboard::default();
+        clipboard.set("hunter2").unwrap();
+        clipboard.set("copied later").unwrap();
+
+        assert!(!clear_if_unchanged(&mut clipboard, &fingerprint("hunter2")).unwrap());
+        assert_eq!(clipboard.text, "copied later");
+    }
+
+    #[test]
+    fn ignores_added_line_break() {
+        let mut clipboard = MemoryClipboard { newline: true, ..Default::default() };
+        clipboard.set("hunter2").unwrap();
+
+        assert!(clear_if_unchanged(&mut clipboard, &fingerprint("hunter2")).unwrap());
+        assert_eq!(clipboard.text, "");
+
+        // The value itself can end with a line break too
+        clipboard.set("hunter2\n").unwrap();
+        assert!(clear_if_unchanged(&mut clipboard, &fingerprint("hunter2\n")).unwrap());
+    }
+}
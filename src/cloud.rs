@@ -0,0 +1,167 @@
+use crate::error::{bail_code, ErrorCode};
+use crate::vault::{Entry, EntryKind};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+
+// apiVersion used when kubectl does not say which one it wants
+const KUBERNETES_API_VERSION: &str = "client.authentication.k8s.io/v1";
+
+// Programs that run a command to get credentials, and the JSON each of them expects
+#[derive(ValueEnum, Clone, Copy, PartialEq, Default)]
+pub enum CredentialType {
+    #[default]
+    Aws, // credential_process in ~/.aws/config
+    Kubectl // exec credential plugin in a kubeconfig
+}
+
+// Credentials of an entry in the shape the program expects
+pub fn credentials(service: &str, entry: &Entry, kind: CredentialType) -> Result<Value> {
+    // Notes and SSH keys would hand out their text or private key in place of a secret
+    if entry.kind != EntryKind::Login && entry.kind != EntryKind::Registry {
+        bail_code!(ErrorCode::InvalidInput, "'{}' is not a login and has no credentials", service);
+    }
+
+    match kind {
+        CredentialType::Aws => aws(service, entry),
+        CredentialType::Kubectl => kubectl(entry)
+    }
+}
+
+fn aws(service: &str, entry: &Entry) -> Result<Value> {
+    // Without custom fields, the username and password are taken as the key pair
+    let Some(access_key_id) = field(entry, "AccessKeyId").or(entry.username.as_deref()) else {
+        bail_code!(ErrorCode::NotFound, "'{}' has no AccessKeyId field or username", service);
+    };
+
+    let secret_access_key = field(entry, "SecretAccessKey").unwrap_or(&entry.password);
+
+    let mut credentials = Map::new();
+    credentials.insert("Version".to_string(), json!(1));
+    credentials.insert("AccessKeyId".to_string(), json!(access_key_id));
+    credentials.insert("SecretAccessKey".to_string(), json!(secret_access_key));
+
+    if let Some(token) = field(entry, "SessionToken") {
+        credentials.insert("SessionToken".to_string(), json!(token));
+    }
+
+    // Long-term keys have no expiration, so the AWS CLI does not ask again
+    if let Some(expiration) = expiration(entry)? {
+        credentials.insert("Expiration".to_string(), json!(expiration));
+    }
+
+    Ok(Value::Object(credentials))
+}
+
+fn kubectl(entry: &Entry) -> Result<Value> {
+    let mut status = Map::new();
+
+    match (field(entry, "ClientCertificateData"), field(entry, "ClientKeyData")) {
+        (Some(certificate), Some(key)) => {
+            status.insert("clientCertificateData".to_string(), json!(certificate));
+            status.insert("clientKeyData".to_string(), json!(key));
+        }
+
+        (None, None) => {
+            status.insert("token".to_string(), json!(field(entry, "Token").unwrap_or(&entry.password)));
+        }
+
+        _ => bail_code!(ErrorCode::InvalidInput, "ClientCertificateData and ClientKeyData have to be given together")
+    }
+
+    if let Some(expiration) = expiration(entry)? {
+        status.insert("expirationTimestamp".to_string(), json!(expiration));
+    }
+
+    Ok(json!({
+        "apiVersion": requested_api_version(),
+        "kind": "ExecCredential",
+        "status": status
+    }))
+}
+
+// kubectl passes the ExecCredential it wants in KUBERNETES_EXEC_INFO when the kubeconfig
+// sets provideClusterInfo, or at least its apiVersion
+fn requested_api_version() -> String {
+    std::env::var("KUBERNETES_EXEC_INFO").ok()
+        .and_then(|info| serde_json::from_str::<Value>(&info).ok())
+        .and_then(|info| info.get("apiVersion")?.as_str().map(|version| version.to_string()))
+        .unwrap_or_else(|| KUBERNETES_API_VERSION.to_string())
+}
+
+fn expiration(entry: &Entry) -> Result<Option<String>> {
+    let Some(expiration) = field(entry, "Expiration") else {
+        return Ok(None);
+    };
+
+    let expiration = DateTime::parse_from_rfc3339(expiration)
+        .with_context(|| format!("Expiration '{}' is not an RFC 3339 time such as 2030-01-31T12:00:00Z", expiration))?;
+
+    Ok(Some(expiration.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::Secs, true)))
+}
+
+// Custom field by name, ignoring case, separators and an "aws" prefix, so "AccessKeyId",
+// "access_key_id" and "aws_access_key_id" are the same field
+fn field<'a>(entry: &'a Entry, name: &str) -> Option<&'a str> {
+    let normalize = |name: &str| {
+        let name: String = name.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase();
+        name.strip_prefix("aws").map(|name| name.to_string()).unwrap_or(name)
+    };
+
+    let wanted = normalize(name);
+
+    entry.custom_fields.iter()
+        .find(|(name, _)| normalize(name) == wanted)
+        .map(|(_, value)| value.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::Vault;
+
+    fn entry(fields: &[(&str, &str)]) -> Entry {
+        let mut vault = Vault::in_memory();
+        vault.add_password("aws", "secret").unwrap();
+        vault.set_details("aws", Some("AKIDUSER"), None).unwrap();
+
+        for (name, value) in fields {
+            vault.set_custom_field("aws", name, value).unwrap();
+        }
+
+        vault.get_entry("aws").unwrap().clone()
+    }
+
+    #[test]
+    fn aws_fields() {
+        let keys = entry(&[("aws_access_key_id", "AKIDFIELD"), ("SecretAccessKey", "field secret"), ("Expiration", "2030-01-31T13:00:00+01:00")]);
+
+        assert_eq!(credentials("aws", &keys, CredentialType::Aws).unwrap(), json!({
+            "Version": 1, "AccessKeyId": "AKIDFIELD", "SecretAccessKey": "field secret", "Expiration": "2030-01-31T12:00:00Z"
+        }));
+
+        // The username and password stand in for missing fields
+        assert_eq!(credentials("aws", &entry(&[]), CredentialType::Aws).unwrap(), json!({
+            "Version": 1, "AccessKeyId": "AKIDUSER", "SecretAccessKey": "secret"
+        }));
+    }
+
+    #[test]
+    fn only_logins_have_credentials() {
+        let mut login = entry(&[]);
+
+        for kind in [EntryKind::Note, EntryKind::SshKey] {
+            login.kind = kind;
+
+            for credential_type in [CredentialType::Aws, CredentialType::Kubectl] {
+                let e = credentials("aws", &login, credential_type).unwrap_err();
+                assert_eq!(ErrorCode::of(&e), ErrorCode::InvalidInput);
+            }
+        }
+
+        login.kind = EntryKind::Registry;
+        assert!(credentials("aws", &login, CredentialType::Aws).is_ok());
+    }
+}
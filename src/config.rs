@@ -0,0 +1,126 @@
+use crate::error::{bail_code, ErrorCode};
+
+use anyhow::{Context, Result};
+use dirs::home_dir;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub history_limit: usize, // Previous passwords kept per service
+    pub attachment_max_mb: u64, // Largest file that can be attached
+    pub clipboard_timeout: u64, // Seconds before copied secrets are cleared, 0 to keep them
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub git_credentials: Vec<GitRule>, // Which entries git-credential uses, first match wins
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub askpass: Vec<AskpassRule> // Which entries askpass answers prompts with, first match wins
+}
+
+// Maps git remotes to entries. Host and path are glob patterns; the entry name may use
+// {protocol}, {host} and {path}.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GitRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    #[serde(default = "any_host")]
+    pub host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>, // Only sent by git when credential.useHttpPath is set
+    pub entry: String,
+    #[serde(default)]
+    pub writable: bool // Let git store and erase credentials in the entry
+}
+
+// Maps askpass prompts to entries. The prompt is a regular expression; the entry name may
+// use its capture groups as $1 or ${name}.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AskpassRule {
+    pub prompt: String,
+    pub entry: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String> // "username" or a custom field instead of the password
+}
+
+fn any_host() -> String {
+    "*".to_string()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            history_limit: 10,
+            attachment_max_mb: 10,
+            clipboard_timeout: 30,
+            git_credentials: Vec::new(),
+            askpass: Vec::new()
+        }
+    }
+}
+
+impl Config {
+    // Get the config file path next to the vault
+    fn get_config_path() -> Result<PathBuf> {
+        let home_dir = home_dir().context("Could not find home directory")?;
+
+        Ok(home_dir.join(".rspass").join("config.json"))
+    }
+
+    // Load the config, falling back to defaults when there is none
+    pub fn load() -> Result<Self> {
+        let config_path = Self::get_config_path()?;
+
+        if !config_path.exists() {
+            return Ok(Config::default());
+        }
+
+        let content = fs::read(&config_path).context("Failed to read config file")?;
+
+        serde_json::from_slice(&content).context("Failed to parse config file")
+    }
+
+    // Save config
+    pub fn save(&self) -> Result<()> {
+        let config_path = Self::get_config_path()?;
+
+        if let Some(dir) = config_path.parent() {
+            fs::create_dir_all(dir).context("Failed to create the directory")?;
+        }
+
+        let json = serde_json::to_vec_pretty(self).context("Failed to serialize config")?;
+
+        fs::write(&config_path, json).context("Failed to write config file")?;
+
+        Ok(())
+    }
+
+    // Get a setting by its command-line name
+    pub fn get(&self, key: &str) -> Result<String> {
+        match key {
+            "history-limit" => Ok(self.history_limit.to_string()),
+            "attachment-max-mb" => Ok(self.attachment_max_mb.to_string()),
+            "clipboard-timeout" => Ok(self.clipboard_timeout.to_string()),
+            _ => bail_code!(ErrorCode::InvalidInput, "Unknown setting '{}'", key)
+        }
+    }
+
+    // Set a setting by its command-line name
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "history-limit" => self.history_limit = value.parse().context("history-limit must be a number")?,
+            "attachment-max-mb" => self.attachment_max_mb = value.parse().context("attachment-max-mb must be a number")?,
+            "clipboard-timeout" => self.clipboard_timeout = value.parse().context("clipboard-timeout must be a number of seconds")?,
+            _ => bail_code!(ErrorCode::InvalidInput, "Unknown setting '{}'", key)
+        }
+
+        Ok(())
+    }
+
+    // Names of all settings
+    pub fn keys() -> &'static [&'static str] {
+        &["history-limit", "attachment-max-mb", "clipboard-timeout"]
+    }
+}
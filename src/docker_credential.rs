@@ -0,0 +1,121 @@
+use crate::error::{bail_code, CodedError, ErrorCode};
+use crate::vault::{EntryKind, Vault};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use zeroize::Zeroize;
+
+// Docker shows this message as "no credentials" instead of as an error
+const NOT_FOUND: &str = "credentials not found in native keychain";
+
+// What docker asks a credential helper to do
+#[derive(ValueEnum, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Get,
+    Store,
+    Erase,
+    List
+}
+
+#[derive(Serialize, Deserialize)]
+struct Credentials {
+    #[serde(rename = "ServerURL")]
+    server_url: String,
+    #[serde(rename = "Username")]
+    username: String,
+    #[serde(rename = "Secret")]
+    secret: String
+}
+
+impl Drop for Credentials {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+// Handle one request read from stdin. Registry logins are entries of their own type,
+// named after the server URL docker gives.
+pub fn run(operation: Operation, unlock: impl FnOnce() -> Result<String>) -> Result<()> {
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input).context("Failed to read the request from docker")?;
+
+    let mut master_password = unlock()?;
+    let result = Vault::load(&master_password).and_then(|mut vault| handle(operation, &input, &mut vault, &master_password));
+
+    input.zeroize();
+    master_password.zeroize();
+
+    result
+}
+
+fn handle(operation: Operation, input: &str, vault: &mut Vault, master_password: &str) -> Result<()> {
+    match operation {
+        Operation::Get => {
+            let server = find(vault, input.trim())?;
+            let entry = vault.get_entry(&server).context(NOT_FOUND)?;
+
+            let credentials = Credentials {
+                server_url: server.clone(),
+                username: entry.username.clone().unwrap_or_default(),
+                secret: entry.password.clone()
+            };
+
+            println!("{}", serde_json::to_string(&credentials)?);
+        }
+
+        Operation::Store => {
+            let credentials: Credentials = serde_json::from_str(input).context("Invalid credentials from docker")?;
+
+            if credentials.server_url.trim().is_empty() {
+                bail_code!(ErrorCode::InvalidInput, "no credentials server URL");
+            }
+
+            if vault.set_registry(&credentials.server_url, &credentials.username, &credentials.secret)? {
+                vault.save(master_password)?;
+            }
+        }
+
+        Operation::Erase => {
+            let server = find(vault, input.trim())?;
+            vault.remove_password(&server)?;
+            vault.save(master_password)?;
+            vault.purge_attachments()?;
+        }
+
+        Operation::List => {
+            let servers: BTreeMap<&String, &str> = vault.list_services().into_iter()
+                .filter_map(|server| vault.get_entry(server).map(|entry| (server, entry)))
+                .filter(|(_, entry)| entry.kind == EntryKind::Registry)
+                .map(|(server, entry)| (server, entry.username.as_deref().unwrap_or_default()))
+                .collect();
+
+            println!("{}", serde_json::to_string(&servers)?);
+        }
+    }
+
+    Ok(())
+}
+
+// Registry entry for a server. Docker is not consistent about schemes and trailing slashes
+// ("https://index.docker.io/v1/" or "index.docker.io/v1"), so those are ignored when the
+// name does not match exactly.
+fn find(vault: &Vault, server: &str) -> Result<String> {
+    let is_registry = |name: &str| vault.get_entry(name).is_some_and(|entry| entry.kind == EntryKind::Registry);
+
+    if is_registry(server) {
+        return Ok(server.to_string());
+    }
+
+    let normalize = |url: &str| {
+        let url = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://")).unwrap_or(url);
+        url.trim_end_matches('/').to_ascii_lowercase()
+    };
+
+    vault.list_services().into_iter()
+        .find(|name| is_registry(name) && normalize(name) == normalize(server))
+        .cloned()
+        .ok_or_else(|| CodedError::new(ErrorCode::NotFound, NOT_FOUND.to_string()).into())
+}
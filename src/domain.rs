@@ -0,0 +1,99 @@
+use publicsuffix::{List, Psl};
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use url::Url;
+
+// Mozilla's Public Suffix List (https://publicsuffix.org), built into the binary so lookups
+// work offline. Update it by replacing the file.
+const PUBLIC_SUFFIX_LIST: &str = include_str!("../data/public_suffix_list.dat");
+
+static LIST: OnceLock<List> = OnceLock::new();
+
+fn list() -> &'static List {
+    LIST.get_or_init(|| PUBLIC_SUFFIX_LIST.parse().expect("The built-in Public Suffix List is invalid"))
+}
+
+// Address of a site. Bare host names get https, but remember that no scheme was given.
+pub struct SiteUrl {
+    pub url: Url,
+    pub explicit_scheme: bool
+}
+
+// Site address from a URL, or from a bare host name with an optional path such as
+// "github.com/login". Hosts are lowercase and IDNs in punycode, as browsers send them.
+pub fn site_url(text: &str) -> Option<SiteUrl> {
+    let text = text.trim();
+
+    let (url, explicit_scheme) = match Url::parse(text) {
+        Ok(url) if url.has_host() => (url, true),
+        _ => (Url::parse(&format!("https://{}", text)).ok()?, false)
+    };
+
+    let host = url.host_str()?;
+
+    // Bare words such as "github" are entry names, not hosts
+    if !explicit_scheme && !host.contains('.') && host != "localhost" && !host.starts_with('[') {
+        return None;
+    }
+
+    Some(SiteUrl { url, explicit_scheme })
+}
+
+// Host of a site address, without a trailing dot
+pub fn host(text: &str) -> Option<String> {
+    let site = site_url(text)?;
+
+    Some(site.url.host_str()?.trim_end_matches('.').to_string())
+}
+
+// Registrable domain of a host: the public suffix and one label before it, so
+// "login.github.com" gives "github.com" and "a.b.co.uk" gives "b.co.uk". IP addresses and
+// public suffixes themselves have none.
+pub fn registrable_domain(host: &str) -> Option<String> {
+    let host = host.trim_end_matches('.').to_lowercase();
+
+    if host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_ok() {
+        return None;
+    }
+
+    let domain = list().domain(host.as_bytes())?;
+
+    Some(String::from_utf8_lossy(domain.as_bytes()).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_list_parses() {
+        let list: List = PUBLIC_SUFFIX_LIST.parse().unwrap();
+
+        assert_eq!(list.suffix(b"example.co.uk").unwrap().as_bytes(), b"co.uk");
+        assert!(list.suffix(b"example.com").unwrap().is_known());
+    }
+
+    #[test]
+    fn registrable_domains() {
+        assert_eq!(registrable_domain("login.github.com").as_deref(), Some("github.com"));
+        assert_eq!(registrable_domain("a.b.co.uk").as_deref(), Some("b.co.uk"));
+        assert_eq!(registrable_domain("Example.COM.").as_deref(), Some("example.com"));
+        assert_eq!(registrable_domain("co.uk"), None);
+        assert_eq!(registrable_domain("192.168.1.1"), None);
+        assert_eq!(registrable_domain("[::1]"), None);
+    }
+
+    #[test]
+    fn site_urls() {
+        let site = site_url("github.com/login").unwrap();
+        assert_eq!(site.url.as_str(), "https://github.com/login");
+        assert!(!site.explicit_scheme);
+
+        assert!(site_url("http://github.com").unwrap().explicit_scheme);
+        assert!(site_url("localhost").is_some());
+        assert!(site_url("github").is_none());
+
+        assert_eq!(host("https://Bücher.example/").as_deref(), Some("xn--bcher-kva.example"));
+        assert_eq!(host("example.com.").as_deref(), Some("example.com"));
+    }
+}
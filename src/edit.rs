@@ -0,0 +1,264 @@
+use crate::error::{bail_code, ErrorCode};
+use crate::vault::{EntryFields, Vault};
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use zeroize::Zeroize;
+
+const HEADER: &str = "\
+# Editing '{service}'. Save and close the editor to apply the changes.
+# Empty values clear a field. Lines starting with # are ignored.
+";
+
+// Temporary file that is overwritten and removed when dropped, also when editing fails
+struct TempFile {
+    path: PathBuf
+}
+
+impl TempFile {
+    // Create a file only the current user can read, in memory-backed storage when there is some
+    fn create(contents: &[u8]) -> Result<Self> {
+        let shm = Path::new("/dev/shm");
+        let dir = if shm.is_dir() { shm.to_path_buf() } else { std::env::temp_dir() };
+        let path = dir.join(format!("rspass-{}.yaml", hex::encode(rand::random::<[u8; 8]>())));
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options.open(&path).with_context(|| format!("Failed to create '{}'", path.display()))?;
+        let temp = TempFile { path };
+
+        file.write_all(contents)?;
+        file.sync_all()?;
+
+        Ok(temp)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // Editors may have replaced the file, so overwrite whatever is there now
+        if let Ok(metadata) = fs::metadata(&self.path) {
+            if let Ok(mut file) = fs::OpenOptions::new().write(true).open(&self.path) {
+                let _ = file.write_all(&vec![0u8; metadata.len() as usize]);
+                let _ = file.sync_all();
+            }
+        }
+
+        let _ = fs::remove_file(&self.path);
+
+        // Swap and backup files some editors leave next to it
+        if let (Some(dir), Some(name)) = (self.path.parent(), self.path.file_name()) {
+            let name = name.to_string_lossy();
+
+            for leftover in [format!(".{}.swp", name), format!("{}~", name)] {
+                let _ = fs::remove_file(dir.join(leftover));
+            }
+        }
+    }
+}
+
+// Edit an entry in $VISUAL or $EDITOR. Returns false when nothing was changed.
+pub fn edit_entry(vault: &mut Vault, service: &str) -> Result<bool> {
+    let Some(entry) = vault.get_entry(service) else {
+        bail_code!(ErrorCode::NotFound, "No password found for '{}'", service);
+    };
+
+    let mut original = entry.fields();
+    let mut document = HEADER.replace("{service}", service) + &serde_yaml::to_string(&original)?;
+    let temp = TempFile::create(document.as_bytes());
+    document.zeroize();
+    let temp = temp?;
+
+    let result = loop {
+        run_editor(&temp.path)?;
+
+        let mut text = fs::read_to_string(&temp.path).context("Failed to read the edited file")?;
+        let parsed: Result<EntryFields> = serde_yaml::from_str(&text).context("Invalid document");
+        text.zeroize();
+
+        let applied = parsed.and_then(|mut fields| {
+            let changed = fields != original;
+            let result = if changed { vault.apply_fields(service, &fields) } else { Ok(()) };
+            fields.zeroize();
+
+            result.map(|_| changed)
+        });
+
+        match applied {
+            Ok(changed) => break Ok(changed),
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+
+                if !ask_yes_no("Edit again? [Y/n] ")? {
+                    break Err(e.context("Edit cancelled, nothing was changed"));
+                }
+            }
+        }
+    };
+
+    original.zeroize();
+
+    result
+}
+
+fn run_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var("VISUAL").ok().filter(|e| !e.trim().is_empty())
+        .or_else(|| std::env::var("EDITOR").ok().filter(|e| !e.trim().is_empty()))
+        .unwrap_or_else(|| if cfg!(windows) { "notepad".to_string() } else { "vi".to_string() });
+
+    // The editor setting may carry arguments, such as "code --wait"
+    #[cfg(unix)]
+    let mut command = {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(format!("{} \"$1\"", editor)).arg("sh").arg(path);
+        command
+    };
+
+    #[cfg(not(unix))]
+    let mut command = {
+        let mut parts = editor.split_whitespace();
+        let mut command = Command::new(parts.next().unwrap_or("notepad"));
+        command.args(parts).arg(path);
+        command
+    };
+
+    let status = {
+        let _signals = SignalGuard::new();
+        command.status().with_context(|| format!("Failed to start the editor '{}'", editor))?
+    };
+
+    if SignalGuard::interrupted() {
+        bail_code!(ErrorCode::Other, "Interrupted, nothing was changed");
+    }
+
+    if !status.success() {
+        bail_code!(ErrorCode::Other, "The editor exited with {}, nothing was changed", status);
+    }
+
+    Ok(())
+}
+
+fn ask_yes_no(prompt: &str) -> Result<bool> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(!answer.trim().eq_ignore_ascii_case("n") && !answer.trim().eq_ignore_ascii_case("no"))
+}
+
+// While the editor runs, Ctrl-C and hangups must not kill rspass before it has removed the
+// temporary file. A handler (unlike ignoring the signal) is reset for the editor itself.
+// The handlers rspass had before are put back afterwards.
+struct SignalGuard {
+    #[cfg(unix)]
+    previous: Vec<(libc::c_int, libc::sigaction)>
+}
+
+#[cfg(unix)]
+const GUARDED_SIGNALS: [libc::c_int; 4] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT];
+
+#[cfg(unix)]
+static INTERRUPTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn record_signal(_: libc::c_int) {
+    INTERRUPTED.store(true, std::sync::atomic::Ordering::SeqCst);
+}
+
+impl SignalGuard {
+    #[cfg(unix)]
+    fn new() -> Self {
+        let mut previous = Vec::new();
+
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = record_signal as *const () as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+
+            for signal in GUARDED_SIGNALS {
+                let mut old: libc::sigaction = std::mem::zeroed();
+
+                if libc::sigaction(signal, &action, &mut old) == 0 {
+                    previous.push((signal, old));
+                }
+            }
+        }
+
+        SignalGuard { previous }
+    }
+
+    #[cfg(not(unix))]
+    fn new() -> Self {
+        SignalGuard {}
+    }
+
+    fn interrupted() -> bool {
+        #[cfg(unix)]
+        return INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst);
+
+        #[cfg(not(unix))]
+        return false;
+    }
+}
+
+impl Drop for SignalGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            for (signal, old) in &self.previous {
+                libc::sigaction(*signal, old, std::ptr::null_mut());
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    // Handler currently set for a signal
+    fn handler(signal: libc::c_int) -> libc::sighandler_t {
+        unsafe {
+            let mut current: libc::sigaction = std::mem::zeroed();
+            libc::sigaction(signal, std::ptr::null(), &mut current);
+
+            current.sa_sigaction
+        }
+    }
+
+    #[test]
+    fn signal_guard_restores_handlers() {
+        let before: Vec<_> = GUARDED_SIGNALS.iter().map(|signal| handler(*signal)).collect();
+
+        // SIGQUIT ignored, as a caller might have it
+        unsafe { libc::signal(libc::SIGQUIT, libc::SIG_IGN) };
+
+        {
+            let _signals = SignalGuard::new();
+
+            for signal in GUARDED_SIGNALS {
+                assert_eq!(handler(signal), record_signal as *const () as libc::sighandler_t);
+            }
+        }
+
+        assert_eq!(handler(libc::SIGQUIT), libc::SIG_IGN);
+
+        unsafe { libc::signal(libc::SIGQUIT, before[3]) };
+
+        for (signal, before) in GUARDED_SIGNALS.iter().zip(before) {
+            assert_eq!(handler(*signal), before);
+        }
+    }
+}
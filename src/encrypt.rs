@@ -1,3 +1,5 @@
+use crate::error::{bail_code, ErrorCode};
+
 use aes_gcm::{
     aead::{Aead, AeadCore, KeyInit, OsRng},
     Aes256Gcm
@@ -37,6 +39,52 @@ impl EncryptionKey {
         Ok(EncryptionKey(key))
     }
     
+    // Generate a random key for data that is not protected by the master password directly
+    pub fn generate() -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+
+        EncryptionKey(key)
+    }
+
+    // Restore a key from its raw bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let key: [u8; 32] = bytes.try_into().map_err(|_| anyhow::anyhow!("Invalid key length"))?;
+
+        Ok(EncryptionKey(key))
+    }
+
+    // Encrypt data with AES-GCM, prefixing the ciphertext with its nonce
+    pub fn seal(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let cipher = Aes256Gcm::new(self.as_ref().into());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+        let ciphertext = cipher
+            .encrypt(&nonce, data)
+            .map_err(|e| anyhow::anyhow!(e.to_string()))
+            .context("Encryption failed")?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+
+        Ok(sealed)
+    }
+
+    // Decrypt data produced by `seal`
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < 12 {
+            anyhow::bail!("Encrypted data is truncated");
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(12);
+        let cipher = Aes256Gcm::new(self.as_ref().into());
+
+        cipher
+            .decrypt(nonce.into(), ciphertext)
+            .map_err(|e| anyhow::anyhow!(e.to_string()))
+            .context("Decryption failed - wrong key or corrupted data")
+    }
+
     // Generate a random salt for key derivation
     pub fn generate_salt() -> [u8; 32] {
         let mut salt = [0u8; 32];
@@ -118,7 +166,7 @@ impl EncryptedData {
     pub fn decrypt(&self, password: &str) -> Result<Vec<u8>> {
         // Verify password
         if !EncryptionKey::verify_password(password, &self.password_hash)? {
-            anyhow::bail!("Invalid master password");
+            bail_code!(ErrorCode::InvalidMasterPassword, "Invalid master password");
         }
         
         // Derive key
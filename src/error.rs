@@ -0,0 +1,66 @@
+use serde::Serialize;
+use std::fmt;
+
+// Stable error codes reported by the machine-readable output formats
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    Usage,
+    NoVault,
+    VaultExists,
+    InvalidMasterPassword,
+    VaultLocked,
+    Unauthorized,
+    NotFound,
+    AlreadyExists,
+    InvalidInput,
+    ClipboardUnavailable,
+    Corrupted,
+    Io,
+    Other
+}
+
+impl ErrorCode {
+    // Code of the first tagged error in the chain
+    pub fn of(error: &anyhow::Error) -> Self {
+        if let Some(coded) = error.chain().find_map(|e| e.downcast_ref::<CodedError>()) {
+            return coded.code;
+        }
+
+        if error.chain().any(|e| e.is::<std::io::Error>()) {
+            return ErrorCode::Io;
+        }
+
+        ErrorCode::Other
+    }
+}
+
+// Error message tagged with its code
+#[derive(Debug)]
+pub struct CodedError {
+    pub code: ErrorCode,
+    message: String
+}
+
+impl CodedError {
+    pub fn new(code: ErrorCode, message: String) -> Self {
+        CodedError { code, message }
+    }
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+// Like anyhow::bail!, but tags the error with an ErrorCode
+macro_rules! bail_code {
+    ($code:expr, $($arg:tt)*) => {
+        return Err(anyhow::Error::new($crate::error::CodedError::new($code, format!($($arg)*))))
+    };
+}
+
+pub(crate) use bail_code;
@@ -0,0 +1,239 @@
+use crate::error::{bail_code, ErrorCode};
+
+use anyhow::{Context, Result};
+use std::io::{self, Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+
+const MASK: &[u8] = b"*****";
+
+// Parse a NAME=service mapping
+pub fn parse_mapping(mapping: &str) -> Result<(String, String)> {
+    let Some((name, service)) = mapping.split_once('=') else {
+        bail_code!(ErrorCode::InvalidInput, "Expected NAME=service, got '{}'", mapping);
+    };
+
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if !valid || service.is_empty() {
+        bail_code!(ErrorCode::InvalidInput, "Expected NAME=service, got '{}'", mapping);
+    }
+
+    Ok((name.to_string(), service.to_string()))
+}
+
+// Run a command with the variables set and return its exit code. With `mask`, the
+// command's output goes through a pipe and every secret value in it is replaced.
+pub fn run(command: &[String], env: &[(String, String)], mask: bool) -> Result<i32> {
+    let Some((program, args)) = command.split_first() else {
+        bail_code!(ErrorCode::Usage, "No command given");
+    };
+
+    let mut child = Command::new(program);
+    child.args(args).envs(env.iter().map(|(name, value)| (name, value)));
+
+    if !mask {
+        let status = child.status().with_context(|| format!("Failed to run '{}'", program))?;
+
+        return Ok(exit_code(status));
+    }
+
+    let mut child = child
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run '{}'", program))?;
+
+    let secrets: Vec<Vec<u8>> = env.iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(_, value)| value.as_bytes().to_vec())
+        .collect();
+
+    let stdout = child.stdout.take().context("Command has no stdout")?;
+    let stderr = child.stderr.take().context("Command has no stderr")?;
+
+    let stdout_secrets = secrets.clone();
+    let stdout_thread = thread::spawn(move || mask_stream(stdout, io::stdout(), &stdout_secrets));
+    let stderr_thread = thread::spawn(move || mask_stream(stderr, io::stderr(), &secrets));
+
+    let status = child.wait()?;
+
+    // A closed terminal is not worth an error once the command has finished
+    let _ = stdout_thread.join();
+    let _ = stderr_thread.join();
+
+    Ok(exit_code(status))
+}
+
+// Copy `reader` to `writer`, replacing secrets. Output is passed on as soon as it arrives,
+// except for a tail that could be the start of a secret split across two reads.
+fn mask_stream(mut reader: impl Read, mut writer: impl Write, secrets: &[Vec<u8>]) -> io::Result<()> {
+    let mut pending = Vec::new();
+    let mut chunk = [0u8; 8192];
+
+    loop {
+        let read = reader.read(&mut chunk)?;
+
+        if read == 0 {
+            writer.write_all(&replace_secrets(&pending, secrets))?;
+            return writer.flush();
+        }
+
+        pending.extend_from_slice(&chunk[..read]);
+
+        let masked = replace_secrets(&pending, secrets);
+        let keep = partial_secret_len(&masked, secrets);
+
+        writer.write_all(&masked[..masked.len() - keep])?;
+        writer.flush()?;
+
+        pending = masked[masked.len() - keep..].to_vec();
+    }
+}
+
+fn replace_secrets(data: &[u8], secrets: &[Vec<u8>]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut index = 0;
+
+    while index < data.len() {
+        // Longest secret first, so one that contains another is masked whole
+        let found = secrets.iter()
+            .filter(|secret| data[index..].starts_with(secret))
+            .map(|secret| secret.len())
+            .max();
+
+        match found {
+            Some(length) => {
+                result.extend_from_slice(MASK);
+                index += length;
+            }
+
+            None => {
+                result.push(data[index]);
+                index += 1;
+            }
+        }
+    }
+
+    result
+}
+
+// Length of the longest end of `data` that is the beginning of a secret
+fn partial_secret_len(data: &[u8], secrets: &[Vec<u8>]) -> usize {
+    secrets.iter()
+        .flat_map(|secret| (1..secret.len().min(data.len() + 1)).rev().find(|&length| data.ends_with(&secret[..length])))
+        .max()
+        .unwrap_or_default()
+}
+
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        // Same convention as the shell for commands killed by a signal
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+
+    status.code().unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Gives its chunks one read at a time
+    struct Chunks(Vec<&'static [u8]>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+
+            let chunk = self.0.remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+
+            Ok(chunk.len())
+        }
+    }
+
+    fn masked(chunks: Vec<&'static [u8]>, secrets: &[&str]) -> String {
+        let secrets: Vec<Vec<u8>> = secrets.iter().map(|secret| secret.as_bytes().to_vec()).collect();
+        let mut output = Vec::new();
+
+        mask_stream(Chunks(chunks), &mut output, &secrets).unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn mappings() {
+        assert_eq!(parse_mapping("DB_PASSWORD=db/prod").unwrap(), ("DB_PASSWORD".to_string(), "db/prod".to_string()));
+        assert_eq!(parse_mapping("_X=a=b").unwrap(), ("_X".to_string(), "a=b".to_string()));
+
+        for mapping in ["DB_PASSWORD", "=db", "1X=db", "DB-PASSWORD=db", "X="] {
+            assert_eq!(ErrorCode::of(&parse_mapping(mapping).unwrap_err()), ErrorCode::InvalidInput, "{}", mapping);
+        }
+    }
+
+    #[test]
+    fn masks_secrets() {
+        assert_eq!(masked(vec![b"token hunter2 and hunter2\n"], &["hunter2"]), "token ***** and *****\n");
+        assert_eq!(masked(vec![b"nothing secret\n"], &["hunter2"]), "nothing secret\n");
+
+        // A secret that contains another is masked whole
+        assert_eq!(masked(vec![b"pass: hunter2-backup"], &["hunter2", "hunter2-backup"]), "pass: *****");
+    }
+
+    #[test]
+    fn secret_split_across_reads() {
+        assert_eq!(masked(vec![b"token hun", b"ter2 done\n"], &["hunter2"]), "token ***** done\n");
+        assert_eq!(masked(vec![b"h", b"u", b"nter", b"2"], &["hunter2"]), "*****");
+
+        // A start that turns out not to be the secret is passed on
+        assert_eq!(masked(vec![b"hunt", b"ing\n"], &["hunter2"]), "hunting\n");
+        assert_eq!(masked(vec![b"ends with hunt"], &["hunter2"]), "ends with hunt");
+    }
+
+    // Keeps every write apart
+    struct Writes(Vec<Vec<u8>>);
+
+    impl Write for &mut Writes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !buf.is_empty() {
+                self.0.push(buf.to_vec());
+            }
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output_is_not_held_back() {
+        let mut writes = Writes(Vec::new());
+
+        // Only what could be the start of the secret waits for the next read
+        mask_stream(Chunks(vec![b"line one\nhun", b"ting\n"]), &mut writes, &[b"hunter2".to_vec()]).unwrap();
+
+        assert_eq!(writes.0, [b"line one\n".to_vec(), b"hunting\n".to_vec()]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exit_codes() {
+        let shell = |script: &str| vec!["sh".to_string(), "-c".to_string(), script.to_string()];
+
+        assert_eq!(run(&shell("exit 3"), &[], false).unwrap(), 3);
+        assert_eq!(run(&shell("exit 4"), &[], true).unwrap(), 4);
+        assert_eq!(run(&shell("kill -TERM $$"), &[], false).unwrap(), 128 + libc::SIGTERM);
+        assert_eq!(ErrorCode::of(&run(&[], &[], false).unwrap_err()), ErrorCode::Usage);
+    }
+}
@@ -0,0 +1,74 @@
+use crate::error::{bail_code, ErrorCode};
+
+use anyhow::Result;
+use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!#$%&()*+,-./:;<=>?@[]^_{|}~";
+
+// Rules for a generated password
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub length: usize,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digits: bool,
+    pub symbols: bool,
+    pub exclude: String // Characters never to use, e.g. look-alikes such as "l1O0"
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            length: 24,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            exclude: String::new()
+        }
+    }
+}
+
+// Random password with at least one character of every enabled class
+pub fn generate(policy: &Policy) -> Result<String> {
+    let classes: Vec<Vec<char>> = [
+        (policy.lowercase, LOWERCASE),
+        (policy.uppercase, UPPERCASE),
+        (policy.digits, DIGITS),
+        (policy.symbols, SYMBOLS)
+    ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, chars)| chars.chars().filter(|c| !policy.exclude.contains(*c)).collect::<Vec<_>>())
+        .filter(|chars| !chars.is_empty())
+        .collect();
+
+    if classes.is_empty() {
+        bail_code!(ErrorCode::InvalidInput, "The password policy leaves no characters to use");
+    }
+
+    if policy.length < classes.len().max(8) {
+        bail_code!(ErrorCode::InvalidInput, "Generated passwords need at least {} characters", classes.len().max(8));
+    }
+
+    let all: Vec<char> = classes.concat();
+    let mut rng = OsRng;
+
+    let mut password: Vec<char> = classes.iter()
+        .filter_map(|chars| chars.choose(&mut rng).copied())
+        .collect();
+
+    while password.len() < policy.length {
+        password.extend(all.choose(&mut rng));
+    }
+
+    password.shuffle(&mut rng);
+
+    Ok(password.into_iter().collect())
+}
@@ -0,0 +1,357 @@
+use crate::error::{bail_code, ErrorCode};
+
+use anyhow::{Context, Result};
+use sha1::{Digest, Sha1};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Name of the binary index inside the HIBP directory
+pub const INDEX_FILE_NAME: &str = "pwned.idx";
+
+const INDEX_MAGIC: &[u8; 8] = b"RSPHIBP1";
+const HASH_LEN: usize = 20;
+const RECORD_LEN: usize = HASH_LEN + 4; // SHA-1 hash followed by a big-endian u32 count
+const PREFIX_LEN: usize = 5;
+
+// Local copy of the Have I Been Pwned password dump
+pub struct HibpSource {
+    dir: PathBuf,
+    index: Option<(File, u64)> // index file and number of records
+}
+
+impl HibpSource {
+    // Open a directory holding range files and/or a binary index
+    pub fn open(dir: &Path) -> Result<Self> {
+        if !dir.is_dir() {
+            bail_code!(ErrorCode::NotFound, "HIBP directory '{}' does not exist", dir.display());
+        }
+
+        let index_path = dir.join(INDEX_FILE_NAME);
+        let index = if index_path.exists() {
+            let mut file = File::open(&index_path).context("Failed to open HIBP index")?;
+
+            let mut magic = [0u8; 8];
+            file.read_exact(&mut magic).context("Failed to read HIBP index header")?;
+
+            if &magic != INDEX_MAGIC {
+                bail_code!(ErrorCode::InvalidInput, "'{}' is not an rspass HIBP index", index_path.display());
+            }
+
+            let len = file.metadata()?.len() - INDEX_MAGIC.len() as u64;
+
+            if !len.is_multiple_of(RECORD_LEN as u64) {
+                bail_code!(ErrorCode::InvalidInput, "HIBP index '{}' is truncated", index_path.display());
+            }
+
+            Some((file, len / RECORD_LEN as u64))
+        } else {
+            None
+        };
+
+        Ok(HibpSource {
+            dir: dir.to_path_buf(),
+            index
+        })
+    }
+
+    // Look up how many times a password appears in the breach data. Fails with NotFound
+    // when the range file for the password is missing, as in a partial download.
+    pub fn lookup(&mut self, password: &str) -> Result<Option<u64>> {
+        let hash: [u8; HASH_LEN] = Sha1::digest(password.as_bytes()).into();
+
+        if self.index.is_some() {
+            self.lookup_index(&hash)
+        } else {
+            self.lookup_range(&hash)
+        }
+    }
+
+    // Binary search the index without loading it into memory
+    fn lookup_index(&mut self, hash: &[u8; HASH_LEN]) -> Result<Option<u64>> {
+        let (file, count) = self.index.as_mut().context("No HIBP index loaded")?;
+        let mut record = [0u8; RECORD_LEN];
+        let (mut low, mut high) = (0u64, *count);
+
+        while low < high {
+            let mid = low + (high - low) / 2;
+
+            file.seek(SeekFrom::Start(INDEX_MAGIC.len() as u64 + mid * RECORD_LEN as u64))?;
+            file.read_exact(&mut record).context("Failed to read HIBP index")?;
+
+            match record[..HASH_LEN].cmp(&hash[..]) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => {
+                    let mut breaches = [0u8; 4];
+                    breaches.copy_from_slice(&record[HASH_LEN..]);
+
+                    return Ok(Some(u32::from_be_bytes(breaches) as u64));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Scan the range file named after the first five hex digits of the hash
+    fn lookup_range(&self, hash: &[u8; HASH_LEN]) -> Result<Option<u64>> {
+        let hex_hash = hex::encode_upper(hash);
+        let (prefix, suffix) = hex_hash.split_at(PREFIX_LEN);
+
+        let path = [prefix.to_string(), prefix.to_lowercase()]
+            .iter()
+            .map(|name| self.dir.join(format!("{}.txt", name)))
+            .find(|path| path.exists());
+
+        let Some(path) = path else {
+            bail_code!(ErrorCode::NotFound, "No HIBP range file for prefix {} in '{}'", prefix, self.dir.display());
+        };
+
+        let file = File::open(&path).with_context(|| format!("Failed to open '{}'", path.display()))?;
+
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+
+            if let Some((line_suffix, count)) = parse_line(&line) {
+                if line_suffix.eq_ignore_ascii_case(suffix) {
+                    return Ok(Some(count));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+// Split a "HASH:COUNT" line from the dump
+fn parse_line(line: &str) -> Option<(&str, u64)> {
+    let (hash, count) = line.trim().split_once(':')?;
+
+    Some((hash, count.trim().parse().ok()?))
+}
+
+// Build a binary index from an ordered hash file or a directory of range files
+pub fn build_index(input: &Path, output_dir: &Path) -> Result<u64> {
+    fs::create_dir_all(output_dir).context("Failed to create the HIBP directory")?;
+
+    let index_path = output_dir.join(INDEX_FILE_NAME);
+    let tmp_path = output_dir.join(format!("{}.tmp", INDEX_FILE_NAME));
+
+    // A failed build leaves the old index, if any, and no partial one
+    let result = write_index(input, &tmp_path)
+        .and_then(|count| fs::rename(&tmp_path, &index_path).context("Failed to move HIBP index into place").map(|_| count));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+
+    result
+}
+
+fn write_index(input: &Path, path: &Path) -> Result<u64> {
+    let mut writer = IndexWriter {
+        out: BufWriter::new(File::create(path).context("Failed to create HIBP index")?),
+        last: None,
+        count: 0
+    };
+    writer.out.write_all(INDEX_MAGIC)?;
+
+    if input.is_dir() {
+        for prefix in 0..=0xFFFFFu32 {
+            let name = format!("{:05X}", prefix);
+
+            let path = [name.clone(), name.to_lowercase()]
+                .iter()
+                .map(|name| input.join(format!("{}.txt", name)))
+                .find(|path| path.exists());
+
+            if let Some(path) = path {
+                writer.add_file(&path, &name)?;
+            }
+        }
+    } else {
+        writer.add_file(input, "")?;
+    }
+
+    let count = writer.count;
+
+    writer.out.into_inner().map_err(|e| e.into_error()).context("Failed to write HIBP index")?.sync_all()?;
+
+    Ok(count)
+}
+
+struct IndexWriter {
+    out: BufWriter<File>,
+    last: Option<[u8; HASH_LEN]>,
+    count: u64
+}
+
+impl IndexWriter {
+    // Append every "HASH:COUNT" line of a file, prepending the range prefix if any
+    fn add_file(&mut self, path: &Path, prefix: &str) -> Result<()> {
+        let file = File::open(path).with_context(|| format!("Failed to open '{}'", path.display()))?;
+
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let Some((hash, count)) = parse_line(&line) else {
+                bail_code!(ErrorCode::InvalidInput, "Malformed line {} in '{}'", number + 1, path.display());
+            };
+
+            let mut bytes = [0u8; HASH_LEN];
+
+            if let Err(e) = hex::decode_to_slice(format!("{}{}", prefix, hash), &mut bytes) {
+                bail_code!(ErrorCode::InvalidInput, "Invalid hash on line {} in '{}': {}", number + 1, path.display(), e);
+            }
+
+            if self.last.is_some_and(|last| last >= bytes) {
+                bail_code!(ErrorCode::InvalidInput, "'{}' is not ordered by hash (line {})", path.display(), number + 1);
+            }
+
+            self.out.write_all(&bytes)?;
+            self.out.write_all(&u32::try_from(count).unwrap_or(u32::MAX).to_be_bytes())?;
+            self.last = Some(bytes);
+            self.count += 1;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SHA-1 of "password" and "hunter2"
+    const PASSWORD: &str = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8";
+    const HUNTER2: &str = "F3BBBD66A63D4BF1747940578EC3D0103530E21D";
+
+    // Empty directory of the test's own
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rspass-hibp-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    // Range file for a prefix with the given suffixes, in order
+    fn range(dir: &Path, name: &str, lines: &[String]) {
+        fs::write(dir.join(format!("{}.txt", name)), lines.join("\r\n")).unwrap();
+    }
+
+    #[test]
+    fn parse_lines() {
+        assert_eq!(parse_line("1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r"), Some(("1E4C9B93F3F0682250B6CF8331B7EE68FD8", 9545824)));
+        assert_eq!(parse_line(" 00A:3 "), Some(("00A", 3)));
+        assert_eq!(parse_line("1E4C9B93F3F0682250B6CF8331B7EE68FD8"), None);
+        assert_eq!(parse_line("1E4C9B93F3F0682250B6CF8331B7EE68FD8:many"), None);
+    }
+
+    #[test]
+    fn range_files() {
+        let dir = temp_dir("ranges");
+
+        // Upper- and lowercase names, as different downloaders write them
+        range(&dir, "5BAA6", &["0018A45C4D1DEF81644B54AB7F969B88D65:1".to_string(), format!("{}:9545824", &PASSWORD[5..])]);
+        range(&dir, "f3bbb", &[format!("{}:17", HUNTER2[5..].to_lowercase())]);
+
+        let mut source = HibpSource::open(&dir).unwrap();
+        assert_eq!(source.lookup("password").unwrap(), Some(9545824));
+        assert_eq!(source.lookup("hunter2").unwrap(), Some(17));
+
+        // Same prefix as "password", but not in the file
+        range(&dir, "5BAA6", &["0018A45C4D1DEF81644B54AB7F969B88D65:1".to_string()]);
+        assert_eq!(source.lookup("password").unwrap(), None);
+
+        let e = source.lookup("correct horse battery staple").unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::NotFound);
+
+        let e = HibpSource::open(&dir.join("missing")).err().unwrap();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::NotFound);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn index_lookup() {
+        let dir = temp_dir("index");
+        let ranges = dir.join("ranges");
+        fs::create_dir_all(&ranges).unwrap();
+
+        // Many records around the ones looked up, so the search takes several steps
+        let mut hashes: Vec<String> = (0..500u32).map(|n| format!("5BAA6{:035X}", n * 7919)).collect();
+        hashes.push(PASSWORD.to_string());
+        hashes.push(HUNTER2.to_string());
+        hashes.push("0000000000000000000000000000000000000000".to_string());
+        hashes.push("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF".to_string());
+        hashes.sort();
+
+        let mut prefixes: Vec<&str> = hashes.iter().map(|hash| &hash[..PREFIX_LEN]).collect();
+        prefixes.dedup();
+
+        for prefix in prefixes {
+            let lines: Vec<String> = hashes.iter()
+                .filter(|hash| hash.starts_with(prefix))
+                .map(|hash| format!("{}:{}", &hash[PREFIX_LEN..], if hash == PASSWORD { 9545824 } else { 1 }))
+                .collect();
+
+            range(&ranges, prefix, &lines);
+        }
+
+        assert_eq!(build_index(&ranges, &dir).unwrap(), hashes.len() as u64);
+
+        let mut source = HibpSource::open(&dir).unwrap();
+        assert_eq!(source.lookup("password").unwrap(), Some(9545824));
+        assert_eq!(source.lookup("hunter2").unwrap(), Some(1));
+
+        // Not found, also without a range file for the prefix
+        assert_eq!(source.lookup("correct horse battery staple").unwrap(), None);
+
+        for hash in &hashes {
+            let mut bytes = [0u8; HASH_LEN];
+            hex::decode_to_slice(hash, &mut bytes).unwrap();
+            assert!(source.lookup_index(&bytes).unwrap().is_some());
+
+            // Just above each record
+            bytes[HASH_LEN - 1] ^= 1;
+            assert_eq!(source.lookup_index(&bytes).unwrap(), hashes.binary_search(&hex::encode_upper(bytes)).ok().map(|_| 1));
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unordered_input_keeps_old_index() {
+        let dir = temp_dir("unordered");
+        let input = dir.join("ordered-by-hash.txt");
+
+        fs::write(&input, format!("{}:1\n{}:2\n", PASSWORD, HUNTER2)).unwrap();
+        build_index(&input, &dir).unwrap();
+
+        for text in [format!("{}:2\n{}:1\n", HUNTER2, PASSWORD), format!("{}:1\nnot a hash:2\n", PASSWORD), "ABC\n".to_string()] {
+            fs::write(&input, text).unwrap();
+
+            let e = build_index(&input, &dir).unwrap_err();
+            assert_eq!(ErrorCode::of(&e), ErrorCode::InvalidInput);
+            assert!(!dir.join(format!("{}.tmp", INDEX_FILE_NAME)).exists());
+        }
+
+        let mut source = HibpSource::open(&dir).unwrap();
+        assert_eq!(source.lookup("hunter2").unwrap(), Some(2));
+
+        // A damaged index is refused
+        let index = dir.join(INDEX_FILE_NAME);
+        let mut data = fs::read(&index).unwrap();
+        data.pop();
+        fs::write(&index, &data).unwrap();
+        assert_eq!(ErrorCode::of(&HibpSource::open(&dir).err().unwrap()), ErrorCode::InvalidInput);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
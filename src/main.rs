@@ -1,29 +1,74 @@
+mod agent;
+mod askpass;
+mod cargo_credential;
 mod cli;
+mod clip;
+mod cloud;
+mod config;
+mod domain;
+mod docker_credential;
 mod encrypt;
+mod edit;
+mod error;
+mod exec;
+mod generate;
+mod git_credential;
+mod hibp;
+mod listing;
+mod manifest;
+mod native_host;
+mod otp;
+mod reference;
+mod output;
+mod search;
+mod secret_service;
+mod server;
+mod ssh;
+mod ssh_agent;
+mod strength;
+mod template;
+#[cfg(test)]
+mod testing;
+mod url_match;
 mod vault;
 
-use cli::{Args, Commands};
-use vault::Vault;
+use clip::{Clipboard, CommandClipboard};
+use cli::{Args, AttachCommands, AuditCommands, Commands, FieldCommands, SshKeyCommands};
+use config::Config;
+use error::{bail_code, ErrorCode};
+use hibp::HibpSource;
+use listing::Filter;
+use manifest::{Action, Manifest};
+use otp::{OtpKind, OtpSecret};
+use output::{Format, Output};
+use reference::Resolver;
+use search::Matcher;
+use vault::{EntryKind, SshOptions, Vault};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::{Duration, Utc};
 use clap::Parser;
+use serde_json::json;
 use rpassword::read_password;
-use std::io::{self, Write};
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
 use std::process::{Command, Stdio};
 use zeroize::Zeroize;
 
 fn prompt_password(prompt: &str, is_master: bool) -> Result<String> {
-    print!("{}", prompt);
+    // Prompts go to stderr so they never mix with machine-readable output
+    eprint!("{}", prompt);
     
-    io::stdout().flush()?;
+    io::stderr().flush()?;
     let mut password = read_password()?;
     
     if password.is_empty() {
         if is_master {
-            anyhow::bail!("Master password cannot be empty");
+            bail_code!(ErrorCode::InvalidInput, "Master password cannot be empty");
         }
         
-        anyhow::bail!("Password cannot be empty");
+        bail_code!(ErrorCode::InvalidInput, "Password cannot be empty");
     }
     
     let result = password.clone();
@@ -32,6 +77,136 @@ fn prompt_password(prompt: &str, is_master: bool) -> Result<String> {
     Ok(result)
 }
 
+// Master password of a vault: RSPASS_MASTER_PASSWORD for the selected vault, then a running
+// unlock agent, then the terminal
+fn vault_password(name: &str) -> Result<String> {
+    if let Some(password) = unattended_password(name) {
+        return Ok(password);
+    }
+
+    if name == vault::selected_vault() {
+        prompt_password("Enter master password: ", true)
+    } else {
+        prompt_password(&format!("Enter master password for vault '{}': ", name), true)
+    }
+}
+
+// Master password from the environment or the unlock agent, without asking
+fn unattended_password(name: &str) -> Option<String> {
+    if name == vault::selected_vault() {
+        if let Ok(password) = std::env::var("RSPASS_MASTER_PASSWORD") {
+            return Some(password);
+        }
+    }
+
+    agent::fetch(name)
+}
+
+// Read text from a file or, when no file is given, from stdin until EOF
+fn read_text(file: Option<&Path>, what: &str) -> Result<String> {
+    let mut text = String::new();
+
+    if let Some(file) = file {
+        text = fs::read_to_string(file)?;
+    } else {
+        if io::stdin().is_terminal() {
+            eprintln!("Enter the {}, then press Ctrl-D:", what);
+        }
+
+        io::stdin().read_to_string(&mut text)?;
+    }
+
+    Ok(text)
+}
+
+// Write a file only the current user can read
+fn write_private_file(path: &Path, data: &[u8], overwrite: bool) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true);
+
+    if overwrite {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => error::CodedError::new(ErrorCode::AlreadyExists, format!("'{}' already exists", path.display())).into(),
+        _ => anyhow::Error::new(e).context(format!("Failed to open '{}'", path.display()))
+    })?;
+
+    // An existing file keeps its mode when it is truncated
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+
+    file.write_all(data)?;
+
+    Ok(())
+}
+
+// Copy or move an entry within the selected vault or into another one
+fn transfer(from: &str, to: Option<&str>, folder: Option<&str>, to_vault: Option<&str>, force: bool, keep_source: bool) -> Result<String> {
+    let to = to.unwrap_or(from);
+    let to_vault = to_vault.filter(|name| *name != vault::selected_vault());
+    let verb = if keep_source { "Copied" } else { "Moved" };
+    let destination = match folder.map(|folder| folder.trim_matches('/')) {
+        Some("") if to == from => "no folder".to_string(),
+        Some(folder) if to == from => format!("folder '{}'", folder),
+        _ => format!("'{}'", to)
+    };
+
+    if to == from && folder.is_none() && to_vault.is_none() && !keep_source {
+        bail_code!(ErrorCode::InvalidInput, "Give a new name, --folder or --to-vault");
+    }
+
+    if to == from && to_vault.is_none() && keep_source {
+        bail_code!(ErrorCode::InvalidInput, "Give a new name or --to-vault to copy to");
+    }
+
+    let master_password = vault_password(vault::selected_vault())?;
+    let mut vault = Vault::load(&master_password)?;
+
+    let Some(target_name) = to_vault else {
+        if keep_source {
+            vault.copy_entry(from, to, force)?;
+        } else {
+            vault.move_entry(from, to, force)?;
+        }
+
+        vault.set_details(to, None, folder)?;
+        vault.save(&master_password)?;
+        vault.purge_attachments()?;
+
+        return Ok(format!("{} '{}' to {}", verb, from, destination));
+    };
+
+    let target_password = vault_password(target_name)?;
+    let mut target = Vault::load_named(target_name, &target_password)?;
+
+    vault.copy_entry_to(from, &mut target, to, force)?;
+    target.set_details(to, None, folder)?;
+    target.save(&target_password)?;
+    target.purge_attachments()?;
+
+    // The source is only changed once the copy is safely stored
+    if !keep_source {
+        vault.remove_password(from)?;
+        vault.save(&master_password)?;
+        vault.purge_attachments()?;
+    }
+
+    Ok(format!("{} '{}' to {} in vault '{}'", verb, from, destination, target_name))
+}
+
 fn launch_ui() -> std::io::Result<()> {
     #[cfg(target_os = "windows")]
     {
@@ -58,21 +233,70 @@ fn launch_ui() -> std::io::Result<()> {
     Ok(())
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+// Credential helpers are run with arguments of their own. Docker runs docker-credential-rspass
+// (a link to rspass) with just the operation, and cargo only adds --cargo-plugin.
+fn raw_args() -> Vec<std::ffi::OsString> {
+    let mut args: Vec<_> = std::env::args_os().collect();
+
+    let program = args.first().and_then(|arg| Path::new(arg).file_stem()).map(|name| name.to_string_lossy().into_owned());
+
+    if native_host::launched_by_browser(&args) {
+        args.insert(1, "native-host".into());
+    } else if program.as_deref() == Some("rspass-askpass") {
+        args.insert(1, "askpass".into());
+    } else if program.as_deref().is_some_and(|name| name.starts_with("docker-credential-")) {
+        args.insert(1, "docker-credential".into());
+    } else if program.as_deref().is_some_and(|name| name.starts_with("cargo-credential-")) || args.get(1).is_some_and(|arg| arg == "--cargo-plugin") {
+        args.insert(1, "cargo-credential".into());
+    }
+
+    args
+}
+
+fn main() {
+    let args = match Args::try_parse_from(raw_args()) {
+        Ok(args) => args,
+        Err(e) if Format::from_raw_args() == Format::Json && e.use_stderr() => {
+            let out = Output { format: Format::Json };
+            let rendered = e.render().to_string();
+            let message = rendered.lines().next().unwrap_or_default().trim_start_matches("error: ");
 
-    match args.command {
+            out.error(&error::CodedError::new(ErrorCode::Usage, message.to_string()).into());
+            std::process::exit(2);
+        }
+        Err(e) => e.exit()
+    };
+
+    let out = Output { format: args.format };
+
+    if let Some(name) = &args.vault {
+        if let Err(e) = vault::select_vault(name) {
+            out.error(&e);
+            std::process::exit(2);
+        }
+    }
+
+    if let Err(e) = run(args.command, &out) {
+        out.error(&e);
+        std::process::exit(1);
+    }
+}
+
+fn run(command: Commands, out: &Output) -> Result<()> {
+    match command {
         Commands::Init => {
-            println!("Initializing new password vault...");
+            if out.is_human() {
+                println!("Initializing new password vault...");
+            }
 
             let master_password = prompt_password("Enter master password: ", true)?;
             Vault::create_new(&master_password)?;
 
-            println!("Vault created successfully!");
+            out.message("Vault created successfully!");
         }
 
-        Commands::Add {service, password} => {
-            let master_password = prompt_password("Enter master password: ", true)?;
+        Commands::Add {service, password, rotate_days, username, folder, tag, url, url_match} => {
+            let master_password = vault_password(vault::selected_vault())?;
             let mut vault = Vault::load(&master_password)?;
             
             let password = if let Some(pwd) = password {
@@ -82,52 +306,693 @@ fn main() -> Result<()> {
             };
             
             vault.add_password(&service, &password)?;
+            vault.set_rotation(&service, rotate_days)?;
+            vault.set_details(&service, username.as_deref(), folder.as_deref())?;
+            vault.set_tags(&service, &tag, &[])?;
+            vault.set_urls(&service, &url, &[], url_match)?;
             vault.save(&master_password)?;
 
-            println!("Password added for '{}'", service);
+            out.message(format!("Password added for '{}'", service));
         }
 
-        Commands::Get {service} => {
-            let master_password = prompt_password("Enter master password: ", true)?;
+        Commands::Get {service, clip, clear_after} => {
+            // Fail before asking for the master password if there is no clipboard
+            let clipboard = if clip { Some(CommandClipboard::detect()?) } else { None };
+
+            let master_password = vault_password(vault::selected_vault())?;
             let vault = Vault::load(&master_password)?;
+
+            let Some(entry) = vault.get_entry(&service) else {
+                let suggestions = search::suggest(&vault.list_services(), &service, 3);
+                let hint = match suggestions.as_slice() {
+                    [] => String::new(),
+                    names => format!(". Did you mean {}?", names.iter().map(|n| format!("'{}'", n)).collect::<Vec<_>>().join(", "))
+                };
+
+                if out.is_human() {
+                    println!("No password found for '{}'{}", service, hint);
+                    return Ok(());
+                }
+
+                bail_code!(ErrorCode::NotFound, "No password found for '{}'{}", service, hint);
+            };
             
-            if let Some(password) = vault.get_password(&service) {
-                println!("Password for '{}': {}", service, password);
+            if let Some(mut clipboard) = clipboard {
+                let (what, secret) = match (&entry.kind, &entry.notes) {
+                    (EntryKind::Note, Some(notes)) => ("Note", notes),
+                    _ => ("Password", &entry.password)
+                };
+
+                let timeout = match clear_after {
+                    Some(seconds) => seconds,
+                    None => Config::load()?.clipboard_timeout
+                };
+
+                clipboard.set(secret)?;
+
+                if timeout > 0 {
+                    clip::spawn_clear_helper(secret, timeout)?;
+                }
+
+                match out.format {
+                    Format::Json => out.json(&json!({ "service": service, "copied": what.to_lowercase(), "clear_after": timeout })),
+                    Format::Tsv => out.row(&[&service, &what.to_lowercase(), &timeout.to_string()]),
+                    Format::Human if timeout > 0 => println!("{} for '{}' copied to the clipboard. It will be cleared in {} seconds.", what, service, timeout),
+                    Format::Human => println!("{} for '{}' copied to the clipboard", what, service)
+                }
             } else {
-                println!("No password found for '{}'", service);
+                match out.format {
+                    Format::Json => out.json(&listing::details_json(&service, entry)),
+
+                    Format::Tsv => {
+                        let secret = match (&entry.kind, &entry.notes) {
+                            (EntryKind::Note, Some(notes)) => notes,
+                            _ => &entry.password
+                        };
+
+                        out.row(&[&service, secret]);
+                    }
+
+                    Format::Human => {
+                        if entry.kind == EntryKind::Note {
+                            println!("Note '{}':", service);
+                        } else {
+                            println!("Password for '{}': {}", service, entry.password);
+                        }
+
+                        if let Some(username) = &entry.username {
+                            println!("Username: {}", username);
+                        }
+
+                        for url in &entry.urls {
+                            println!("URL: {}", url);
+                        }
+
+                        if let Some(notes) = &entry.notes {
+                            if entry.kind != EntryKind::Note {
+                                println!("Notes:");
+                            }
+
+                            println!("{}", notes.trim_end());
+                        }
+
+                        if !entry.attachments.is_empty() {
+                            println!("Attachments: {}", entry.attachments.iter().map(|a| a.name.as_str()).collect::<Vec<_>>().join(", "));
+                        }
+                    }
+                }
             }
         }
 
-        Commands::List => {
-            let master_password = prompt_password("Enter master password: ", true)?;
+        Commands::List {sort, reverse, tag, folder, kind, columns} => {
+            let master_password = vault_password(vault::selected_vault())?;
+            let vault = Vault::load(&master_password)?;
+            let now = Utc::now();
+
+            let filter = Filter { tags: tag, folder, kind };
+            let entries = listing::select(&vault, &filter, sort, reverse);
+
+            match out.format {
+                Format::Json => {
+                    let entries: Vec<_> = entries.iter()
+                        .map(|(service, entry)| listing::summary_json(service, entry, now))
+                        .collect();
+
+                    out.json(&json!({ "entries": entries }));
+                }
+
+                Format::Tsv => {
+                    for (service, entry) in entries {
+                        let mut row = vec![service.clone()];
+                        row.extend(columns.iter().map(|&column| listing::column_value(column, entry, now).unwrap_or_default()));
+
+                        out.row(&row.iter().map(String::as_str).collect::<Vec<_>>());
+                    }
+                }
+
+                Format::Human if entries.is_empty() && vault.list_services().is_empty() => println!("No passwords stored yet"),
+                Format::Human if entries.is_empty() => println!("No entries match the filter"),
+
+                Format::Human if columns.is_empty() => {
+                    println!("Stored services:");
+
+                    for (service, _) in entries {
+                        println!(" - {}", service);
+                    }
+                }
+
+                Format::Human => {
+                    let mut rows = vec![std::iter::once("SERVICE").chain(columns.iter().map(|&c| listing::column_title(c))).map(String::from).collect::<Vec<_>>()];
+
+                    for (service, entry) in entries {
+                        let mut row = vec![service.clone()];
+                        row.extend(columns.iter().map(|&column| listing::column_value(column, entry, now).unwrap_or("-".to_string())));
+                        rows.push(row);
+                    }
+
+                    let widths: Vec<usize> = (0..rows[0].len())
+                        .map(|column| rows.iter().map(|row| row[column].chars().count()).max().unwrap_or_default())
+                        .collect();
+
+                    for row in rows {
+                        let cells: Vec<String> = row.iter().zip(&widths).map(|(cell, width)| format!("{:width$}", cell, width = width)).collect();
+
+                        println!("{}", cells.join("  ").trim_end());
+                    }
+                }
+            }
+        }
+
+        Commands::Exec {env, mask, command} => {
+            let mappings = env.iter().map(|mapping| exec::parse_mapping(mapping)).collect::<Result<Vec<_>>>()?;
+
+            let master_password = vault_password(vault::selected_vault())?;
+            let vault = Vault::load(&master_password)?;
+
+            let mut variables = Vec::new();
+
+            for (name, service) in mappings {
+                let Some(entry) = vault.get_entry(&service) else {
+                    bail_code!(ErrorCode::NotFound, "No password found for '{}'", service);
+                };
+
+                let secret = match (&entry.kind, &entry.notes) {
+                    (EntryKind::Note, Some(notes)) => notes,
+                    _ => &entry.password
+                };
+
+                variables.push((name, secret.clone()));
+            }
+
+            // Nothing but the variables stays in memory while the command runs
+            drop(vault);
+
+            let result = exec::run(&command, &variables, mask);
+
+            for (_, value) in variables.iter_mut() {
+                value.zeroize();
+            }
+
+            let code = result?;
+
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+
+        Commands::Inject {input, output} => {
+            let mut template = read_text(input.as_deref(), "template")?;
+
+            let mut resolver = Resolver::new(vault_password);
+
+            let result = template::render(&template, &mut resolver);
+            template.zeroize();
+            let mut rendered = result?;
+
+            let result = match &output {
+                Some(path) => write_private_file(path, rendered.as_bytes(), true),
+                None => io::stdout().write_all(rendered.as_bytes()).map_err(Into::into)
+            };
+            rendered.zeroize();
+            result?;
+
+            if let Some(path) = output {
+                if out.is_human() {
+                    eprintln!("Wrote '{}'", path.display());
+                } else {
+                    out.message(format!("Wrote '{}'", path.display()));
+                }
+            }
+        }
+
+        Commands::Mv {from, to, folder, to_vault, force} => {
+            out.message(transfer(&from, to.as_deref(), folder.as_deref(), to_vault.as_deref(), force, false)?);
+        }
+
+        Commands::Cp {from, to, folder, to_vault, force} => {
+            out.message(transfer(&from, to.as_deref(), folder.as_deref(), to_vault.as_deref(), force, true)?);
+        }
+
+        Commands::Apply {manifest: path, dry_run} => {
+            let mut text = fs::read_to_string(&path).with_context(|| format!("Failed to read '{}'", path.display()))?;
+            let parsed = Manifest::parse(&text);
+            text.zeroize();
+            let parsed = parsed?;
+
+            let master_password = vault_password(vault::selected_vault())?;
+            let mut vault = Vault::load(&master_password)?;
+            let before = manifest::snapshot(&vault);
+
+            // On failure the vault is dropped unsaved, so either everything applies or nothing does
+            parsed.apply(&mut vault)?;
+
+            let changes = manifest::diff(&before, &vault);
+
+            if !dry_run && !changes.is_empty() {
+                vault.save(&master_password)?;
+                vault.purge_attachments()?;
+            }
+
+            match out.format {
+                Format::Json => out.json(&json!({ "dry_run": dry_run, "changes": changes })),
+
+                Format::Tsv => {
+                    for change in &changes {
+                        let action = json!(change.action);
+                        out.row(&[action.as_str().unwrap_or_default(), &change.service, &change.fields.join("; ")]);
+                    }
+                }
+
+                Format::Human if changes.is_empty() => println!("Nothing to change"),
+
+                Format::Human => {
+                    if dry_run {
+                        println!("Dry run, these changes were not saved:");
+                    } else {
+                        println!("Applied {} changes:", changes.len());
+                    }
+
+                    for change in &changes {
+                        let sign = match change.action {
+                            Action::Add => '+',
+                            Action::Update => '~',
+                            Action::Remove => '-'
+                        };
+
+                        if change.fields.is_empty() {
+                            println!(" {} {}", sign, change.service);
+                        } else {
+                            println!(" {} {}: {}", sign, change.service, change.fields.join(", "));
+                        }
+                    }
+                }
+            }
+        }
+
+        Commands::GitCredential {operation} => {
+            // Stdin belongs to git, so the master password is read from the terminal
+            let rules = Config::load()?.git_credentials;
+            git_credential::run(operation, io::stdin().lock(), io::stdout().lock(), &rules, || vault_password(vault::selected_vault()))?;
+        }
+
+        Commands::Askpass {prompt} => {
+            // ssh only wants a yes or no for these, which is not rspass's to give
+            if std::env::var("SSH_ASKPASS_PROMPT").is_ok_and(|kind| kind == "confirm" || kind == "none") {
+                bail_code!(ErrorCode::InvalidInput, "rspass askpass only answers prompts for secrets");
+            }
+
+            let prompt = prompt.join(" ");
+            let rules = Config::load()?.askpass;
+
+            let Some(target) = askpass::target(&prompt, &rules)? else {
+                bail_code!(ErrorCode::NotFound, "No askpass rule matches the prompt '{}'", prompt.trim());
+            };
+
+            // Askpass programs run without a terminal to ask on
+            let Some(master_password) = unattended_password(vault::selected_vault()) else {
+                bail_code!(ErrorCode::VaultLocked, "The vault is locked. Run 'rspass unlock' or set RSPASS_MASTER_PASSWORD first.");
+            };
+
             let vault = Vault::load(&master_password)?;
-            let services = vault.list_services();
 
-            if services.is_empty() {
-                println!("No passwords stored yet");
+            let mut secret = askpass::secret(&vault, &target)?;
+            println!("{}", secret);
+            secret.zeroize();
+        }
+
+        Commands::NativeHost {caller: _} => {
+            // Stdin and stdout carry the messages, and nothing can be asked on a terminal
+            native_host::run(io::stdin().lock(), io::stdout().lock(), || unattended_password(vault::selected_vault()))?;
+        }
+
+        Commands::NativeHostManifest {browser, extension_ids, install} => {
+            let path = std::env::current_exe().context("Could not find the rspass executable")?;
+            let manifest = serde_json::to_string_pretty(&native_host::manifest(browser, &extension_ids, &path))?;
+
+            if install {
+                let dir = native_host::manifest_dir(browser)?;
+                fs::create_dir_all(&dir).context("Failed to create the directory")?;
+
+                let file = dir.join(format!("{}.json", native_host::HOST_NAME));
+                fs::write(&file, format!("{}\n", manifest)).with_context(|| format!("Failed to write '{}'", file.display()))?;
+
+                out.message(format!("Installed the native messaging host manifest at '{}'", file.display()));
             } else {
-                println!("Stored services:");
+                println!("{}", manifest);
+            }
+        }
 
-                for service in services {
-                    println!(" - {}", service);
+        Commands::Serve {port, socket, token_file, audit_log, timeout, unlock} => {
+            let options = server::Options {
+                address: match socket {
+                    Some(socket) => server::Address::Socket(std::path::absolute(socket)?),
+                    None => server::Address::Port(port)
+                },
+                token_file: match token_file {
+                    Some(file) => file,
+                    None => server::default_token_file(vault::selected_vault())?
+                },
+                audit_log: match audit_log {
+                    Some(file) => file,
+                    None => server::default_audit_log(vault::selected_vault())?
+                },
+                timeout
+            };
+
+            let master_password = if unlock {
+                let master_password = vault_password(vault::selected_vault())?;
+
+                // Only a password that opens the vault is kept
+                Vault::load(&master_password)?;
+                Some(master_password)
+            } else {
+                None
+            };
+
+            server::serve(&options, master_password, |address| {
+                match out.format {
+                    Format::Json => out.json(&json!({ "address": address, "token_file": options.token_file, "audit_log": options.audit_log })),
+                    Format::Tsv => out.row(&[address, &options.token_file.to_string_lossy(), &options.audit_log.to_string_lossy()]),
+                    Format::Human => {
+                        println!("Serving vault '{}' on {}", vault::selected_vault(), address);
+                        println!("Bearer token: {}", options.token_file.display());
+                        println!("Audit log: {}", options.audit_log.display());
+                    }
                 }
+
+                Ok(())
+            })?;
+        }
+
+        Commands::SecretService {address} => {
+            let name = vault::selected_vault();
+
+            // Started by D-Bus activation there is no terminal; the vault then stays locked
+            // until 'rspass unlock' is run and a client asks to unlock it
+            let master_password = match unattended_password(name) {
+                Some(master_password) => Some(master_password),
+                None if io::stdin().is_terminal() => Some(prompt_password("Enter master password: ", true)?),
+                None => None
+            };
+
+            if let Some(master_password) = master_password.as_deref() {
+                Vault::load(master_password)?;
             }
+
+            let locked = master_password.is_none();
+
+            secret_service::serve(address.as_deref(), master_password, move || unattended_password(name), || {
+                let state = if locked { "locked" } else { "unlocked" };
+
+                match out.format {
+                    Format::Json => out.json(&json!({ "bus_name": secret_service::BUS_NAME, "folder": secret_service::FOLDER, "locked": locked })),
+                    Format::Tsv => out.row(&[secret_service::BUS_NAME, secret_service::FOLDER, state]),
+                    Format::Human => eprintln!("Serving vault '{}' ({}) as {}, with items in the folder '{}'", name, state, secret_service::BUS_NAME, secret_service::FOLDER)
+                }
+
+                Ok(())
+            })?;
         }
 
-        Commands::Remove {service} => {
+        Commands::CloudCredentials {service, kind} => {
+            let master_password = vault_password(vault::selected_vault())?;
+            let vault = Vault::load(&master_password)?;
+
+            let Some(entry) = vault.get_entry(&service) else {
+                bail_code!(ErrorCode::NotFound, "No entry found for '{}'", service);
+            };
+
+            // The calling program reads this JSON whatever --format says
+            let mut credentials = serde_json::to_string(&cloud::credentials(&service, entry, kind)?)?;
+            println!("{}", credentials);
+            credentials.zeroize();
+        }
+
+        Commands::DockerCredential {operation} => {
+            // Docker reads errors from stdout
+            if let Err(e) = docker_credential::run(operation, || vault_password(vault::selected_vault())) {
+                println!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
+
+        Commands::CargoCredential {cargo_plugin} => {
+            if !cargo_plugin {
+                bail_code!(ErrorCode::Usage, "This is run by cargo as a credential provider, see the README");
+            }
+
+            // Stdin belongs to cargo, so the token is read from the terminal
+            cargo_credential::run(io::stdin().lock(), io::stdout().lock(), || vault_password(vault::selected_vault()), |prompt| prompt_password(prompt, false))?;
+        }
+
+        Commands::SshKey {command} => match command {
+            SshKeyCommands::Import {service, file, confirm, lifetime} => {
+                let mut text = fs::read_to_string(&file).with_context(|| format!("Failed to read '{}'", file.display()))?;
+                let key = ssh::import(&text, &service, || prompt_password("Enter the passphrase of the key: ", false));
+                text.zeroize();
+                let mut private_key = ssh::to_openssh(&key?)?;
+
+                let master_password = vault_password(vault::selected_vault())?;
+                let mut vault = Vault::load(&master_password)?;
+
+                let result = vault.add_ssh_key(&service, &private_key, SshOptions { confirm, lifetime: lifetime.filter(|l| *l > 0) });
+                private_key.zeroize();
+                result?;
+
+                vault.save(&master_password)?;
+
+                out.message(format!("SSH key imported as '{}'", service));
+            }
+
+            SshKeyCommands::Generate {service, comment, confirm, lifetime} => {
+                let master_password = vault_password(vault::selected_vault())?;
+                let mut vault = Vault::load(&master_password)?;
+
+                let key = ssh::generate(comment.as_deref().unwrap_or(&service))?;
+                let mut private_key = ssh::to_openssh(&key)?;
+
+                let result = vault.add_ssh_key(&service, &private_key, SshOptions { confirm, lifetime: lifetime.filter(|l| *l > 0) });
+                private_key.zeroize();
+                result?;
+
+                vault.save(&master_password)?;
+
+                match out.format {
+                    Format::Json => out.json(&json!({ "service": service, "public_key": ssh::public_key(&key)? })),
+                    Format::Tsv => out.row(&[&service, &ssh::public_key(&key)?]),
+                    Format::Human => {
+                        println!("Ed25519 key generated as '{}'. Its public key:", service);
+                        println!("{}", ssh::public_key(&key)?);
+                    }
+                }
+            }
+
+            SshKeyCommands::Public {service} => {
+                let master_password = vault_password(vault::selected_vault())?;
+                let vault = Vault::load(&master_password)?;
+
+                let Some(entry) = vault.get_entry(&service).filter(|entry| entry.kind == EntryKind::SshKey) else {
+                    bail_code!(ErrorCode::NotFound, "No SSH key found for '{}'", service);
+                };
+
+                let public_key = ssh::public_key(&ssh::parse(&entry.password)?)?;
+
+                match out.format {
+                    Format::Json => out.json(&json!({ "service": service, "public_key": public_key })),
+                    Format::Tsv => out.row(&[&service, &public_key]),
+                    Format::Human => println!("{}", public_key)
+                }
+            }
+
+            SshKeyCommands::Set {service, confirm, lifetime} => {
+                let master_password = vault_password(vault::selected_vault())?;
+                let mut vault = Vault::load(&master_password)?;
+
+                vault.set_ssh_options(&service, confirm, lifetime)?;
+                vault.save(&master_password)?;
+
+                out.message(format!("Agent constraints of '{}' updated. Restart ssh-agent to apply them.", service));
+            }
+        }
+
+        Commands::SshAgent {socket, foreground, kill, stdin} => {
+            let socket = match socket {
+                Some(socket) => std::path::absolute(socket)?,
+                None => ssh_agent::default_socket(vault::selected_vault())?
+            };
+
+            if kill {
+                if !ssh_agent::stop(&socket)? {
+                    bail_code!(ErrorCode::NotFound, "No ssh-agent is running on '{}'", socket.display());
+                }
+
+                out.message("ssh-agent stopped");
+            } else if foreground {
+                let mut master_password = if stdin { agent::read_secret()? } else { vault_password(vault::selected_vault())? };
+                let vault = Vault::load(&master_password);
+                master_password.zeroize();
+
+                ssh_agent::serve(vault?, &socket, |count| {
+                    if stdin {
+                        // Tells the starting process it can go
+                        println!("ready");
+                    } else {
+                        println!("SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;", socket.display());
+                        eprintln!("Serving {} SSH keys", count);
+                    }
+
+                    Ok(io::stdout().flush()?)
+                })?;
+            } else {
+                let mut master_password = vault_password(vault::selected_vault())?;
+                let result = Vault::load(&master_password).and_then(|_| ssh_agent::spawn(vault::selected_vault(), &socket, &master_password));
+                master_password.zeroize();
+                result?;
+
+                // Meant for eval "$(rspass ssh-agent)", like ssh-agent's output
+                match out.format {
+                    Format::Json => out.json(&json!({ "socket": socket })),
+                    Format::Tsv => out.row(&[&socket.display().to_string()]),
+                    Format::Human => println!("SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;", socket.display())
+                }
+            }
+        }
+
+        Commands::Unlock {timeout} => {
+            if timeout == 0 {
+                bail_code!(ErrorCode::InvalidInput, "The timeout has to be at least 1 minute");
+            }
+
             let master_password = prompt_password("Enter master password: ", true)?;
+
+            // Only a password that opens the vault is kept
+            Vault::load(&master_password)?;
+            agent::spawn(vault::selected_vault(), &master_password, timeout * 60)?;
+
+            out.message(format!("Vault '{}' unlocked for {} minutes", vault::selected_vault(), timeout));
+        }
+
+        Commands::Lock => {
+            if agent::stop(vault::selected_vault())? {
+                out.message(format!("Vault '{}' locked", vault::selected_vault()));
+            } else {
+                out.message(format!("Vault '{}' was not unlocked", vault::selected_vault()));
+            }
+        }
+
+        Commands::AgentServe {timeout} => agent::serve(vault::selected_vault(), timeout)?,
+
+        Commands::Edit {service} => {
+            let master_password = vault_password(vault::selected_vault())?;
+            let mut vault = Vault::load(&master_password)?;
+
+            if edit::edit_entry(&mut vault, &service)? {
+                vault.save(&master_password)?;
+                out.message(format!("Entry '{}' updated", service));
+            } else {
+                out.message(format!("No changes made to '{}'", service));
+            }
+        }
+
+        Commands::Details {service, username, folder, tag, untag, url, remove_url, url_match} => {
+            let master_password = vault_password(vault::selected_vault())?;
+            let mut vault = Vault::load(&master_password)?;
+
+            vault.set_details(&service, username.as_deref(), folder.as_deref())?;
+            vault.set_tags(&service, &tag, &untag)?;
+            vault.set_urls(&service, &url, &remove_url, url_match)?;
+            vault.save(&master_password)?;
+
+            out.message(format!("Details updated for '{}'", service));
+        }
+
+        Commands::Search {query, mode, case_sensitive} => {
+            let matcher = Matcher::new(&query, mode, case_sensitive)?;
+
+            let master_password = vault_password(vault::selected_vault())?;
+            let vault = Vault::load(&master_password)?;
+            let hits = search::search(&vault, &matcher);
+
+            match out.format {
+                Format::Json => {
+                    let results: Vec<_> = hits.iter()
+                        .map(|hit| json!({ "service": hit.service, "field": hit.field, "score": hit.score }))
+                        .collect();
+
+                    out.json(&json!({ "query": query, "results": results }));
+                }
+
+                Format::Tsv => {
+                    for hit in hits {
+                        out.row(&[hit.service, hit.field]);
+                    }
+                }
+
+                Format::Human if hits.is_empty() => println!("No entries match '{}'", query),
+
+                Format::Human => {
+                    println!("Entries matching '{}':", query);
+
+                    for hit in hits {
+                        if hit.field == "service" {
+                            println!(" - {}", hit.service);
+                        } else {
+                            println!(" - {} (matched {})", hit.service, hit.field);
+                        }
+                    }
+                }
+            }
+        }
+
+        Commands::FindUrl {url} => {
+            let master_password = vault_password(vault::selected_vault())?;
+            let vault = Vault::load(&master_password)?;
+            let hits = url_match::find(&vault, &url)?;
+
+            match out.format {
+                Format::Json => {
+                    let results: Vec<_> = hits.iter()
+                        .map(|hit| json!({ "service": hit.service, "username": hit.entry.username, "url": hit.url, "match": hit.level }))
+                        .collect();
+
+                    out.json(&json!({ "url": url, "results": results }));
+                }
+
+                Format::Tsv => {
+                    for hit in hits {
+                        out.row(&[hit.service, &hit.url, hit.level.name()]);
+                    }
+                }
+
+                Format::Human if hits.is_empty() => println!("No entries for '{}'", url),
+
+                Format::Human => {
+                    println!("Entries for '{}':", url);
+
+                    for hit in hits {
+                        println!(" - {} ({} match on {})", hit.service, hit.level.name(), hit.url);
+                    }
+                }
+            }
+        }
+
+        Commands::Remove {service} => {
+            let master_password = vault_password(vault::selected_vault())?;
             let mut vault = Vault::load(&master_password)?;
             
             if vault.remove_password(&service)? {
                 vault.save(&master_password)?;
-                println!("Password removed for '{}'", service);
-            } else {
+                vault.purge_attachments()?;
+                out.message(format!("Password removed for '{}'", service));
+            } else if out.is_human() {
                 println!("No password found for '{}'", service);
+            } else {
+                bail_code!(ErrorCode::NotFound, "No password found for '{}'", service);
             }
         }
 
         Commands::Update {service, password} => {
-            let master_password = prompt_password("Enter master password: ", true)?;
+            let master_password = vault_password(vault::selected_vault())?;
             let mut vault = Vault::load(&master_password)?;
             
             let password = if let Some(pwd) = password {
@@ -139,15 +1004,563 @@ fn main() -> Result<()> {
             vault.update_password(&service, &password)?;
             vault.save(&master_password)?;
 
-            println!("Password updated for '{}'", service);
+            out.message(format!("Password updated for '{}'", service));
+        }
+
+        Commands::History {service, show, restore} => {
+            let master_password = vault_password(vault::selected_vault())?;
+            let mut vault = Vault::load(&master_password)?;
+
+            if let Some(version) = restore {
+                vault.restore_password(&service, version)?;
+                vault.save(&master_password)?;
+
+                out.message(format!("Version {} restored for '{}'", version, service));
+            } else {
+                let Some(entry) = vault.get_entry(&service) else {
+                    bail_code!(ErrorCode::NotFound, "No password found for '{}'", service);
+                };
+
+                match out.format {
+                    Format::Json => {
+                        let versions: Vec<_> = entry.history.iter().enumerate()
+                            .map(|(index, item)| json!({
+                                "version": index + 1,
+                                "set": item.set,
+                                "replaced": item.replaced,
+                                "password": show.then_some(&item.password)
+                            }))
+                            .collect();
+
+                        out.json(&json!({ "service": service, "versions": versions }));
+                    }
+
+                    Format::Tsv => {
+                        for (index, item) in entry.history.iter().enumerate() {
+                            let set = item.set.map(|set| set.to_rfc3339()).unwrap_or_default();
+                            let password = if show { item.password.as_str() } else { "" };
+
+                            out.row(&[&(index + 1).to_string(), &set, &item.replaced.to_rfc3339(), password]);
+                        }
+                    }
+
+                    Format::Human if entry.history.is_empty() => println!("No previous passwords for '{}'", service),
+
+                    Format::Human => {
+                        println!("Previous passwords for '{}':", service);
+
+                        for (index, item) in entry.history.iter().enumerate() {
+                            let set = item.set.map_or("unknown".to_string(), |set| set.format("%Y-%m-%d %H:%M").to_string());
+                            let replaced = item.replaced.format("%Y-%m-%d %H:%M");
+
+                            if show {
+                                println!(" {}. {} (set {}, replaced {})", index + 1, item.password, set, replaced);
+                            } else {
+                                println!(" {}. set {}, replaced {}", index + 1, set, replaced);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Commands::Config {key, value} => {
+            let mut config = Config::load()?;
+
+            match (key, value) {
+                (Some(key), Some(value)) => {
+                    config.set(&key, &value)?;
+                    config.save()?;
+
+                    out.message(format!("{} set to {}", key, value));
+                }
+
+                (Some(key), None) => match out.format {
+                    Format::Json => out.json(&json!({ key.as_str(): config.get(&key)? })),
+                    Format::Tsv => out.row(&[&key, &config.get(&key)?]),
+                    Format::Human => println!("{}", config.get(&key)?)
+                },
+
+                (None, _) => {
+                    let mut settings = serde_json::Map::new();
+
+                    for key in Config::keys() {
+                        match out.format {
+                            Format::Json => { settings.insert(key.to_string(), config.get(key)?.into()); }
+                            Format::Tsv => out.row(&[key, &config.get(key)?]),
+                            Format::Human => println!("{} = {}", key, config.get(key)?)
+                        }
+                    }
+
+                    if out.format == Format::Json {
+                        out.json(&settings.into());
+                    }
+                }
+            }
+        }
+
+        Commands::Otp {service, set, hotp, counter, digits, period, algorithm, remove} => {
+            let master_password = vault_password(vault::selected_vault())?;
+            let mut vault = Vault::load(&master_password)?;
+
+            if remove {
+                vault.set_otp(&service, None)?;
+                vault.save(&master_password)?;
+
+                out.message(format!("One-time code secret removed for '{}'", service));
+            } else if let Some(input) = set {
+                let mut otp = OtpSecret::parse(&input)?;
+
+                if hotp || counter.is_some() {
+                    otp.kind = OtpKind::Hotp { counter: counter.unwrap_or_default() };
+                }
+
+                if let Some(period) = period {
+                    let OtpKind::Totp { .. } = otp.kind else {
+                        bail_code!(ErrorCode::InvalidInput, "--period only applies to time-based codes");
+                    };
+
+                    otp.kind = OtpKind::Totp { period };
+                }
+
+                otp.digits = digits.unwrap_or(otp.digits);
+                otp.algorithm = algorithm.unwrap_or(otp.algorithm);
+                otp.validate()?;
+
+                vault.set_otp(&service, Some(otp))?;
+                vault.save(&master_password)?;
+
+                out.message(format!("One-time code secret stored for '{}'", service));
+            } else {
+                let Some(otp) = vault.get_entry(&service).and_then(|entry| entry.otp.as_ref()) else {
+                    bail_code!(ErrorCode::NotFound, "No one-time code secret stored for '{}'", service);
+                };
+
+                let (code, remaining) = if let OtpKind::Totp { .. } = otp.kind {
+                    let now = otp::unix_now();
+
+                    (otp.totp(now)?, otp.seconds_remaining(now))
+                } else {
+                    let code = vault.next_hotp(&service)?;
+                    vault.save(&master_password)?;
+
+                    (code, None)
+                };
+
+                match (out.format, remaining) {
+                    (Format::Json, _) => out.json(&json!({ "service": service, "code": code, "remaining": remaining })),
+                    (Format::Tsv, _) => out.row(&[&service, &code, &remaining.map(|r| r.to_string()).unwrap_or_default()]),
+                    (Format::Human, Some(remaining)) => println!("Code for '{}': {} (valid for {} seconds)", service, code, remaining),
+                    (Format::Human, None) => println!("Code for '{}': {}", service, code)
+                }
+            }
+        }
+
+        Commands::Note {service, file} => {
+            let master_password = vault_password(vault::selected_vault())?;
+            let mut vault = Vault::load(&master_password)?;
+
+            let mut text = read_text(file.as_deref(), "note")?;
+
+            if text.trim().is_empty() {
+                bail_code!(ErrorCode::InvalidInput, "Note cannot be empty");
+            }
+
+            vault.set_note(&service, &text)?;
+            text.zeroize();
+            vault.save(&master_password)?;
+
+            out.message(format!("Note saved for '{}'", service));
+        }
+
+        Commands::Attach {command} => match command {
+            AttachCommands::Add {service, file, name} => {
+                let name = match name {
+                    Some(name) => name,
+                    None => file.file_name().context("Attachment needs a file name")?.to_string_lossy().to_string()
+                };
+
+                let max_size = Config::load()?.attachment_max_mb * 1024 * 1024;
+
+                if fs::metadata(&file).with_context(|| format!("Failed to read '{}'", file.display()))?.len() > max_size {
+                    bail_code!(ErrorCode::InvalidInput, "'{}' is larger than the {} MB attachment limit", file.display(), max_size / 1024 / 1024);
+                }
+
+                let master_password = vault_password(vault::selected_vault())?;
+                let mut vault = Vault::load(&master_password)?;
+
+                let mut data = fs::read(&file).with_context(|| format!("Failed to read '{}'", file.display()))?;
+                let result = vault.add_attachment(&service, &name, &data, max_size);
+                data.zeroize();
+                result?;
+
+                vault.save(&master_password)?;
+
+                out.message(format!("Attached '{}' to '{}'", name, service));
+            }
+
+            AttachCommands::Get {service, name, output, force} => {
+                let master_password = vault_password(vault::selected_vault())?;
+                let vault = Vault::load(&master_password)?;
+
+                let output = output.unwrap_or_else(|| Path::new(&name).file_name().map(|n| n.into()).unwrap_or_default());
+                let mut data = vault.get_attachment(&service, &name)?;
+                let result = write_private_file(&output, &data, force);
+                data.zeroize();
+                result?;
+
+                out.message(format!("Saved '{}' to '{}'", name, output.display()));
+            }
+
+            AttachCommands::Rm {service, name} => {
+                let master_password = vault_password(vault::selected_vault())?;
+                let mut vault = Vault::load(&master_password)?;
+
+                vault.remove_attachment(&service, &name)?;
+                vault.save(&master_password)?;
+                vault.purge_attachments()?;
+
+                out.message(format!("Removed '{}' from '{}'", name, service));
+            }
+
+            AttachCommands::List {service} => {
+                let master_password = vault_password(vault::selected_vault())?;
+                let vault = Vault::load(&master_password)?;
+
+                let Some(entry) = vault.get_entry(&service) else {
+                    bail_code!(ErrorCode::NotFound, "No entry found for '{}'", service);
+                };
+
+                match out.format {
+                    Format::Json => {
+                        let attachments: Vec<_> = entry.attachments.iter()
+                            .map(|a| json!({ "name": a.name, "size": a.size, "added": a.added }))
+                            .collect();
+
+                        out.json(&json!({ "service": service, "attachments": attachments }));
+                    }
+
+                    Format::Tsv => {
+                        for attachment in &entry.attachments {
+                            out.row(&[&attachment.name, &attachment.size.to_string(), &attachment.added.to_rfc3339()]);
+                        }
+                    }
+
+                    Format::Human if entry.attachments.is_empty() => println!("No attachments for '{}'", service),
+
+                    Format::Human => {
+                        println!("Attachments of '{}':", service);
+
+                        for attachment in &entry.attachments {
+                            println!(" - {} ({} bytes, added {})", attachment.name, attachment.size, attachment.added.format("%Y-%m-%d"));
+                        }
+                    }
+                }
+            }
+        }
+
+        Commands::Field {command} => match command {
+            FieldCommands::Set {service, name, value} => {
+                let master_password = vault_password(vault::selected_vault())?;
+                let mut vault = Vault::load(&master_password)?;
+
+                let mut value = match value {
+                    Some(value) => value,
+                    None => prompt_password(&format!("Enter the value of '{}': ", name), false)?
+                };
+
+                let result = vault.set_custom_field(&service, &name, &value);
+                value.zeroize();
+                result?;
+
+                vault.save(&master_password)?;
+
+                out.message(format!("Field '{}' set for '{}'", name, service));
+            }
+
+            FieldCommands::Get {service, name} => {
+                let master_password = vault_password(vault::selected_vault())?;
+                let vault = Vault::load(&master_password)?;
+
+                let Some(entry) = vault.get_entry(&service) else {
+                    bail_code!(ErrorCode::NotFound, "No entry found for '{}'", service);
+                };
+
+                let Some(value) = entry.custom_fields.get(&name) else {
+                    bail_code!(ErrorCode::NotFound, "'{}' has no field named '{}'", service, name);
+                };
+
+                match out.format {
+                    Format::Json => out.json(&json!({ "service": service, "field": name, "value": value })),
+                    Format::Tsv => out.row(&[&name, value]),
+                    Format::Human => println!("{}", value)
+                }
+            }
+
+            FieldCommands::Rm {service, name} => {
+                let master_password = vault_password(vault::selected_vault())?;
+                let mut vault = Vault::load(&master_password)?;
+
+                vault.remove_custom_field(&service, &name)?;
+                vault.save(&master_password)?;
+
+                out.message(format!("Removed field '{}' from '{}'", name, service));
+            }
+
+            FieldCommands::List {service} => {
+                let master_password = vault_password(vault::selected_vault())?;
+                let vault = Vault::load(&master_password)?;
+
+                let Some(entry) = vault.get_entry(&service) else {
+                    bail_code!(ErrorCode::NotFound, "No entry found for '{}'", service);
+                };
+
+                match out.format {
+                    Format::Json => out.json(&json!({ "service": service, "fields": entry.custom_fields.keys().collect::<Vec<_>>() })),
+
+                    Format::Tsv => {
+                        for name in entry.custom_fields.keys() {
+                            out.row(&[name]);
+                        }
+                    }
+
+                    Format::Human if entry.custom_fields.is_empty() => println!("No fields for '{}'", service),
+
+                    Format::Human => {
+                        println!("Fields of '{}':", service);
+
+                        for name in entry.custom_fields.keys() {
+                            println!(" - {}", name);
+                        }
+                    }
+                }
+            }
+        }
+
+        Commands::Rotation {service, days} => {
+            let master_password = vault_password(vault::selected_vault())?;
+            let mut vault = Vault::load(&master_password)?;
+
+            vault.set_rotation(&service, days)?;
+            vault.save(&master_password)?;
+
+            match days {
+                Some(days) => out.message(format!("'{}' has to be rotated every {} days", service, days)),
+                None => out.message(format!("Rotation interval cleared for '{}'", service))
+            }
+        }
+
+        Commands::Due {within} => {
+            let master_password = vault_password(vault::selected_vault())?;
+            let vault = Vault::load(&master_password)?;
+            let now = Utc::now();
+
+            let mut due: Vec<_> = vault.list_services().into_iter()
+                .filter_map(|service| {
+                    let entry = vault.get_entry(service)?;
+                    let due = entry.rotation_due()?;
+
+                    (due - now <= Duration::days(within as i64)).then_some((service, entry, due))
+                })
+                .collect();
+            due.sort_by(|a, b| a.2.cmp(&b.2).then(a.0.cmp(b.0)));
+
+            match out.format {
+                Format::Json => {
+                    let entries: Vec<_> = due.iter()
+                        .map(|(service, entry, due)| json!({
+                            "service": service,
+                            "rotation_days": entry.rotation_days,
+                            "due": entry.modified.map(|_| due),
+                            "overdue": *due <= now
+                        }))
+                        .collect();
+
+                    out.json(&json!({ "within": within, "entries": entries }));
+                }
+
+                Format::Tsv => {
+                    for (service, entry, due) in due {
+                        let due = entry.modified.map(|_| due.to_rfc3339()).unwrap_or_default();
+
+                        out.row(&[service, &entry.rotation_days.unwrap_or_default().to_string(), &due]);
+                    }
+                }
+
+                Format::Human if due.is_empty() => println!("No passwords are due for rotation in the next {} days", within),
+
+                Format::Human => {
+                    println!("Passwords due for rotation:");
+
+                    for (service, entry, due) in due {
+                        let days = entry.rotation_days.unwrap_or_default();
+
+                        if entry.modified.is_none() {
+                            println!(" - {}: last change unknown (every {} days)", service, days);
+                        } else if (now - due).num_days() == 0 && due <= now {
+                            println!(" - {}: due today (every {} days)", service, days);
+                        } else if due <= now {
+                            println!(" - {}: overdue by {} days (every {} days)", service, (now - due).num_days(), days);
+                        } else {
+                            println!(" - {}: due in {} days (every {} days)", service, (due - now).num_days(), days);
+                        }
+                    }
+                }
+            }
+        }
+
+        Commands::Audit {command} => match command {
+            AuditCommands::Breached {hibp_dir} => {
+                let mut source = HibpSource::open(&hibp_dir)?;
+
+                let master_password = vault_password(vault::selected_vault())?;
+                let vault = Vault::load(&master_password)?;
+
+                // Notes have no password, and SSH key entries hold a private key
+                let logins: Vec<_> = vault.list_services().into_iter()
+                    .filter_map(|service| {
+                        let entry = vault.get_entry(service)?;
+                        matches!(entry.kind, EntryKind::Login | EntryKind::Registry).then_some((service, entry))
+                    })
+                    .collect();
+
+                if out.is_human() {
+                    println!("Checking {} passwords against the local breach data...", logins.len());
+                }
+
+                let mut breached = Vec::new();
+                let mut unchecked = Vec::new();
+
+                for (service, entry) in &logins {
+                    match source.lookup(&entry.password) {
+                        Ok(Some(count)) => {
+                            if out.is_human() {
+                                println!(" - {}: seen {} times in breaches", service, count);
+                            }
+
+                            breached.push((*service, count));
+                        }
+
+                        Ok(None) => {}
+
+                        // A partial copy of the dump leaves only some passwords unchecked
+                        Err(e) if ErrorCode::of(&e) == ErrorCode::NotFound => {
+                            if out.is_human() {
+                                eprintln!("Warning: '{}' was not checked: {:#}", service, e);
+                            }
+
+                            unchecked.push(*service);
+                        }
+
+                        Err(e) => return Err(e)
+                    }
+                }
+
+                match out.format {
+                    Format::Json => {
+                        let breached: Vec<_> = breached.iter()
+                            .map(|(service, count)| json!({ "service": service, "count": count }))
+                            .collect();
+
+                        out.json(&json!({ "checked": logins.len() - unchecked.len(), "unchecked": unchecked, "breached": breached }));
+                    }
+
+                    Format::Tsv => {
+                        for (service, count) in breached {
+                            out.row(&[service, &count.to_string()]);
+                        }
+                    }
+
+                    Format::Human if !unchecked.is_empty() => println!("{} breached passwords found, {} not checked.", breached.len(), unchecked.len()),
+                    Format::Human if breached.is_empty() => println!("No stored passwords were found in the breach data"),
+                    Format::Human => println!("{} breached passwords found. Update them with 'rspass update <service>'.", breached.len())
+                }
+            }
+
+            AuditCommands::Age {days} => {
+                let master_password = vault_password(vault::selected_vault())?;
+                let vault = Vault::load(&master_password)?;
+                let now = Utc::now();
+
+                let mut old: Vec<_> = vault.list_services().into_iter()
+                    .filter_map(|service| {
+                        let entry = vault.get_entry(service)?;
+                        let age = entry.age_days(now);
+
+                        age.is_none_or(|age| age >= days as i64).then_some((service, entry, age))
+                    })
+                    .collect();
+                old.sort_by(|a, b| b.2.unwrap_or(i64::MAX).cmp(&a.2.unwrap_or(i64::MAX)).then(a.0.cmp(b.0)));
+
+                match out.format {
+                    Format::Json => {
+                        let entries: Vec<_> = old.iter()
+                            .map(|(service, entry, age)| json!({ "service": service, "age_days": age, "modified": entry.modified }))
+                            .collect();
+
+                        out.json(&json!({ "days": days, "entries": entries }));
+                    }
+
+                    Format::Tsv => {
+                        for (service, _, age) in old {
+                            out.row(&[service, &age.map(|age| age.to_string()).unwrap_or_default()]);
+                        }
+                    }
+
+                    Format::Human if old.is_empty() => println!("No passwords older than {} days", days),
+
+                    Format::Human => {
+                        println!("Passwords older than {} days:", days);
+
+                        for (service, _, age) in old {
+                            match age {
+                                Some(age) => println!(" - {}: {} days", service, age),
+                                None => println!(" - {}: unknown (stored before change dates were recorded)", service)
+                            }
+                        }
+                    }
+                }
+            }
+
+            AuditCommands::BuildIndex {input, hibp_dir} => {
+                let output_dir = match hibp_dir {
+                    Some(dir) => dir,
+                    None if input.is_dir() => input.clone(),
+                    None => input.parent().map(|p| p.to_path_buf()).unwrap_or_default()
+                };
+
+                if out.is_human() {
+                    println!("Building HIBP index from '{}'...", input.display());
+                }
+
+                let count = hibp::build_index(&input, &output_dir)?;
+                let index = output_dir.join(hibp::INDEX_FILE_NAME);
+
+                match out.format {
+                    Format::Json => out.json(&json!({ "hashes": count, "index": index })),
+                    Format::Tsv => out.row(&[&count.to_string(), &index.to_string_lossy()]),
+                    Format::Human => println!("Indexed {} hashes into '{}'", count, index.display())
+                }
+            }
         }
 
         Commands::UI => {
-            println!("Launching the UI version...");
+            if out.is_human() {
+                println!("Launching the UI version...");
+            }
 
             launch_ui()?; // Spawn and detach the UI
 
-            println!("UI launched. You can close this window.");
+            out.message("UI launched. You can close this window.");
+        }
+
+        Commands::ClipClear {after} => {
+            let mut expected = String::new();
+            io::stdin().read_line(&mut expected)?;
+
+            std::thread::sleep(std::time::Duration::from_secs(after));
+
+            clip::clear_if_unchanged(&mut CommandClipboard::detect()?, expected.trim())?;
         }
     }
 
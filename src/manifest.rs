@@ -0,0 +1,406 @@
+use crate::error::{bail_code, ErrorCode};
+use crate::generate::{self, Policy};
+use crate::vault::{EntryFields, UrlMatch, Vault};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use zeroize::Zeroize;
+
+// Changes to apply to a vault in one go
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub operations: Vec<Operation>
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    Add(Values),
+    Update(Values),
+    Remove {
+        service: String
+    },
+    Tag {
+        service: String,
+        #[serde(default)]
+        add: Vec<String>,
+        #[serde(default)]
+        remove: Vec<String>
+    }
+}
+
+// Values of an add or update; fields that are left out are not changed
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Values {
+    service: String,
+    password: Option<String>,
+    generate: Option<Policy>, // Generate the password instead of giving it
+    username: Option<String>,
+    folder: Option<String>,
+    tags: Option<Vec<String>>,
+    rotation_days: Option<u32>,
+    notes: Option<String>,
+    custom_fields: Option<BTreeMap<String, String>>, // Replaces all custom fields
+    urls: Option<Vec<String>>, // Replaces all URLs
+    url_match: Option<UrlMatch>
+}
+
+impl Drop for Values {
+    fn drop(&mut self) {
+        self.password.zeroize();
+        self.notes.zeroize();
+
+        if let Some(fields) = self.custom_fields.as_mut() {
+            fields.values_mut().for_each(|value| value.zeroize());
+        }
+    }
+}
+
+impl Operation {
+    fn service(&self) -> &str {
+        match self {
+            Operation::Add(values) | Operation::Update(values) => &values.service,
+            Operation::Remove { service } | Operation::Tag { service, .. } => service
+        }
+    }
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Self> {
+        serde_yaml::from_str(text).context("Invalid manifest")
+    }
+
+    // Apply every operation in order. Stops at the first failure, leaving the vault
+    // partly changed, so callers must not save it then.
+    pub fn apply(&self, vault: &mut Vault) -> Result<()> {
+        for (index, operation) in self.operations.iter().enumerate() {
+            apply_operation(vault, operation)
+                .with_context(|| format!("Operation {} on '{}' failed", index + 1, operation.service()))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn apply_operation(vault: &mut Vault, operation: &Operation) -> Result<()> {
+    match operation {
+        Operation::Add(values) => {
+            let Some(mut password) = new_password(values)? else {
+                bail_code!(ErrorCode::InvalidInput, "An add needs a password or a generate policy");
+            };
+
+            let result = vault.add_password(&values.service, &password).and_then(|_| apply_values(vault, values, None));
+            password.zeroize();
+
+            result
+        }
+
+        Operation::Update(values) => {
+            if vault.get_entry(&values.service).is_none() {
+                bail_code!(ErrorCode::NotFound, "No password found for '{}'", values.service);
+            }
+
+            apply_values(vault, values, new_password(values)?)
+        }
+
+        Operation::Remove { service } => {
+            if !vault.remove_password(service)? {
+                bail_code!(ErrorCode::NotFound, "No password found for '{}'", service);
+            }
+
+            Ok(())
+        }
+
+        Operation::Tag { service, add, remove } => vault.set_tags(service, add, remove)
+    }
+}
+
+fn new_password(values: &Values) -> Result<Option<String>> {
+    match (&values.password, &values.generate) {
+        (Some(_), Some(_)) => bail_code!(ErrorCode::InvalidInput, "Give either a password or a generate policy, not both"),
+        (Some(password), None) => Ok(Some(password.clone())),
+        (None, Some(policy)) => Ok(Some(generate::generate(policy)?)),
+        (None, None) => Ok(None)
+    }
+}
+
+// Lay the given values over the current fields of the entry
+fn apply_values(vault: &mut Vault, values: &Values, password: Option<String>) -> Result<()> {
+    let entry = vault.get_entry(&values.service).context("Entry disappeared")?;
+    let mut fields = entry.fields();
+
+    if let Some(password) = password {
+        fields.password.zeroize();
+        fields.password = Some(password);
+    }
+
+    if let Some(username) = &values.username {
+        fields.username = Some(username.clone());
+    }
+
+    if let Some(folder) = &values.folder {
+        fields.folder = Some(folder.clone());
+    }
+
+    if let Some(tags) = &values.tags {
+        fields.tags = tags.clone();
+    }
+
+    if let Some(days) = values.rotation_days {
+        fields.rotation_days = Some(days);
+    }
+
+    if let Some(notes) = &values.notes {
+        fields.notes.zeroize();
+        fields.notes = Some(notes.clone());
+    }
+
+    if let Some(custom_fields) = &values.custom_fields {
+        fields.custom_fields = custom_fields.clone();
+    }
+
+    if let Some(urls) = &values.urls {
+        fields.urls = urls.clone();
+    }
+
+    if let Some(url_match) = values.url_match {
+        fields.url_match = Some(url_match);
+    }
+
+    vault.apply_fields(&values.service, &fields)
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Add,
+    Update,
+    Remove
+}
+
+// What a manifest changed in one entry. Secrets are only reported as changed.
+#[derive(Serialize)]
+pub struct Change {
+    pub service: String,
+    pub action: Action,
+    pub fields: Vec<String>
+}
+
+// Snapshot of all entries, to compare with after applying a manifest
+pub fn snapshot(vault: &Vault) -> BTreeMap<String, EntryFields> {
+    vault.list_services().into_iter()
+        .filter_map(|service| Some((service.clone(), vault.get_entry(service)?.fields())))
+        .collect()
+}
+
+pub fn diff(before: &BTreeMap<String, EntryFields>, vault: &Vault) -> Vec<Change> {
+    let after = snapshot(vault);
+    let mut changes = Vec::new();
+
+    for (service, old) in before {
+        match after.get(service) {
+            None => changes.push(Change { service: service.clone(), action: Action::Remove, fields: Vec::new() }),
+            Some(new) if new != old => changes.push(Change { service: service.clone(), action: Action::Update, fields: field_changes(Some(old), new) }),
+            Some(_) => {}
+        }
+    }
+
+    for (service, new) in &after {
+        if !before.contains_key(service) {
+            changes.push(Change { service: service.clone(), action: Action::Add, fields: field_changes(None, new) });
+        }
+    }
+
+    changes.sort_by(|a, b| a.service.cmp(&b.service));
+
+    changes
+}
+
+fn field_changes(old: Option<&EntryFields>, new: &EntryFields) -> Vec<String> {
+    let empty = EntryFields::default();
+    let old = old.unwrap_or(&empty);
+    let mut fields = Vec::new();
+
+    let show = |value: &Option<String>| value.as_deref().map_or("none".to_string(), |v| format!("'{}'", v));
+
+    if old.password != new.password {
+        fields.push(if old.password.is_none() { "password set".to_string() } else { "password changed".to_string() });
+    }
+
+    if old.username != new.username {
+        fields.push(format!("username: {} -> {}", show(&old.username), show(&new.username)));
+    }
+
+    if old.folder != new.folder {
+        fields.push(format!("folder: {} -> {}", show(&old.folder), show(&new.folder)));
+    }
+
+    let added: Vec<&String> = new.tags.iter().filter(|tag| !old.tags.contains(tag)).collect();
+    let removed: Vec<&String> = old.tags.iter().filter(|tag| !new.tags.contains(tag)).collect();
+
+    for tag in added {
+        fields.push(format!("tag +{}", tag));
+    }
+
+    for tag in removed {
+        fields.push(format!("tag -{}", tag));
+    }
+
+    if old.rotation_days != new.rotation_days {
+        let show_days = |days: Option<u32>| days.map_or("none".to_string(), |days| format!("{} days", days));
+        fields.push(format!("rotation: {} -> {}", show_days(old.rotation_days), show_days(new.rotation_days)));
+    }
+
+    if old.notes != new.notes {
+        fields.push("notes changed".to_string());
+    }
+
+    for url in new.urls.iter().filter(|url| !old.urls.contains(url)) {
+        fields.push(format!("url +{}", url));
+    }
+
+    for url in old.urls.iter().filter(|url| !new.urls.contains(url)) {
+        fields.push(format!("url -{}", url));
+    }
+
+    if old.url_match != new.url_match {
+        fields.push(format!("url match: {} -> {}", old.url_match.unwrap_or_default().name(), new.url_match.unwrap_or_default().name()));
+    }
+
+    // Custom fields often hold keys, so only their names are shown
+    for name in old.custom_fields.keys().chain(new.custom_fields.keys().filter(|name| !old.custom_fields.contains_key(*name))) {
+        match (old.custom_fields.get(name), new.custom_fields.get(name)) {
+            (None, Some(_)) => fields.push(format!("field {} set", name)),
+            (Some(_), None) => fields.push(format!("field {} removed", name)),
+            (Some(old), Some(new)) if old != new => fields.push(format!("field {} changed", name)),
+            _ => {}
+        }
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MASTER_PASSWORD};
+    use serde_json::{json, Value};
+
+    fn manifest(yaml: &str) -> Manifest {
+        Manifest::parse(yaml).unwrap()
+    }
+
+    fn vault() -> Vault {
+        let mut vault = Vault::in_memory();
+        vault.add_password("github", "hunter2").unwrap();
+        vault.set_details("github", Some("octocat"), Some("work")).unwrap();
+        vault.add_password("old", "retired").unwrap();
+
+        vault
+    }
+
+    #[test]
+    fn operations() {
+        let mut vault = vault();
+
+        manifest(r#"
+operations:
+  - op: add
+    service: mail
+    generate: { length: 32, symbols: false }
+    tags: [personal]
+    custom_fields: { pin: "1234" }
+  - op: update
+    service: github
+    username: hubber
+    urls: [github.com]
+  - op: tag
+    service: github
+    add: [dev]
+  - op: remove
+    service: old
+"#).apply(&mut vault).unwrap();
+
+        let mail = vault.get_entry("mail").unwrap();
+        assert_eq!(mail.password.len(), 32);
+        assert!(mail.password.chars().all(char::is_alphanumeric));
+        assert_eq!(mail.tags, ["personal"]);
+        assert_eq!(mail.custom_fields.get("pin").map(String::as_str), Some("1234"));
+
+        // Fields left out keep their values
+        let github = vault.get_entry("github").unwrap();
+        assert_eq!((github.password.as_str(), github.username.as_deref(), github.folder.as_deref()), ("hunter2", Some("hubber"), Some("work")));
+        assert_eq!((github.urls.as_slice(), github.tags.as_slice()), (["github.com".to_string()].as_slice(), ["dev".to_string()].as_slice()));
+
+        assert!(vault.get_entry("old").is_none());
+    }
+
+    #[test]
+    fn invalid_operations() {
+        for (yaml, code) in [
+            ("operations: [{ op: add, service: mail }]", ErrorCode::InvalidInput),
+            ("operations: [{ op: add, service: mail, password: a, generate: {} }]", ErrorCode::InvalidInput),
+            ("operations: [{ op: add, service: github, password: a }]", ErrorCode::AlreadyExists),
+            ("operations: [{ op: update, service: mail, username: a }]", ErrorCode::NotFound),
+            ("operations: [{ op: remove, service: mail }]", ErrorCode::NotFound)
+        ] {
+            let e = manifest(yaml).apply(&mut vault()).unwrap_err();
+            assert_eq!(ErrorCode::of(&e), code, "{}", yaml);
+        }
+
+        assert!(Manifest::parse("operations: [{ op: add, service: mail, password: a, colour: red }]").is_err());
+        assert!(Manifest::parse("operations: [{ op: rename, service: mail }]").is_err());
+    }
+
+    #[test]
+    fn dry_run_diff() {
+        let mut vault = vault();
+        let before = snapshot(&vault);
+
+        manifest(r#"
+operations:
+  - { op: add, service: mail, password: secret, username: me }
+  - { op: update, service: github, password: changed, folder: home, tags: [dev], rotation_days: 90, notes: "2FA on", custom_fields: { token: abc } }
+  - { op: remove, service: old }
+"#).apply(&mut vault).unwrap();
+
+        let changes = diff(&before, &vault);
+        let changes: Vec<(&str, Value, Vec<&str>)> = changes.iter()
+            .map(|change| (change.service.as_str(), json!(change.action), change.fields.iter().map(String::as_str).collect()))
+            .collect();
+
+        // Secrets only show up as changed
+        assert_eq!(changes, [
+            ("github", json!("update"), vec!["password changed", "folder: 'work' -> 'home'", "tag +dev", "rotation: none -> 90 days", "notes changed", "field token set"]),
+            ("mail", json!("add"), vec!["password set", "username: none -> 'me'"]),
+            ("old", json!("remove"), vec![])
+        ]);
+
+        assert!(diff(&snapshot(&vault), &vault).is_empty());
+    }
+
+    #[test]
+    fn failure_leaves_saved_vault_alone() {
+        let _vault = testing::fresh_vault();
+        testing::change(|vault| vault.add_password("github", "hunter2").unwrap());
+
+        let mut vault = Vault::load(MASTER_PASSWORD).unwrap();
+        let e = manifest(r#"
+operations:
+  - { op: update, service: github, password: changed }
+  - { op: remove, service: missing }
+"#).apply(&mut vault).unwrap_err();
+
+        // The error names the operation and keeps its code, and the first one was applied
+        assert_eq!(e.to_string(), "Operation 2 on 'missing' failed");
+        assert_eq!(ErrorCode::of(&e), ErrorCode::NotFound);
+        assert_eq!(vault.get_password("github").map(String::as_str), Some("changed"));
+
+        // Dropping the vault unsaved discards that
+        drop(vault);
+        assert_eq!(Vault::load(MASTER_PASSWORD).unwrap().get_password("github").map(String::as_str), Some("hunter2"));
+    }
+}
@@ -0,0 +1,540 @@
+use crate::domain;
+use crate::error::{bail_code, CodedError, ErrorCode};
+use crate::generate::{self, Policy};
+use crate::url_match;
+use crate::vault::{EntryKind, Vault};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use dirs::home_dir;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use url::Url;
+use zeroize::Zeroize;
+
+// Name browsers know the host by, which is also the file name of its manifest
+pub const HOST_NAME: &str = "rspass";
+
+// Largest message in either direction. Chrome refuses bigger messages from the host.
+const MAX_MESSAGE: usize = 1024 * 1024;
+
+#[derive(ValueEnum, Clone, Copy, PartialEq)]
+pub enum Browser {
+    Chrome,
+    Chromium,
+    Brave,
+    Edge,
+    Firefox
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum Request {
+    // Whether the vault can be read without asking for the master password
+    Status,
+
+    // Logins for the page at `origin`, which may be the full URL of the page
+    Lookup {
+        origin: String
+    },
+
+    // Store a login, with the given password or a generated one
+    Save {
+        origin: String,
+        service: Option<String>, // Defaults to the host of the origin
+        username: Option<String>,
+        password: Option<String>,
+        generate: Option<Policy>,
+        #[serde(default)]
+        update: bool // Change the password of an existing entry
+    },
+
+    // A new random password, nothing is stored
+    Generate {
+        #[serde(default)]
+        policy: Policy
+    }
+}
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        if let Request::Save { password, .. } = self {
+            password.zeroize();
+        }
+    }
+}
+
+// Whether the browser started rspass as a native messaging host. Chrome passes the origin
+// of the extension, Firefox the path of the host manifest and the extension ID.
+pub fn launched_by_browser(args: &[std::ffi::OsString]) -> bool {
+    let Some(first) = args.get(1).map(|arg| arg.to_string_lossy()) else {
+        return false;
+    };
+
+    first.starts_with("chrome-extension://")
+        || (args.len() > 2 && Path::new(first.as_ref()).file_name().is_some_and(|name| name == format!("{}.json", HOST_NAME).as_str()))
+}
+
+// Read one message: a 32-bit length in native byte order followed by that much JSON.
+// Returns None when the browser has closed the connection.
+pub fn read_message(input: &mut impl Read) -> Result<Option<Value>> {
+    let mut length = [0u8; 4];
+
+    if input.read_exact(&mut length).is_err() {
+        return Ok(None);
+    }
+
+    let length = u32::from_ne_bytes(length) as usize;
+
+    if length > MAX_MESSAGE {
+        bail_code!(ErrorCode::InvalidInput, "Message of {} bytes is too large", length);
+    }
+
+    let mut message = vec![0u8; length];
+    input.read_exact(&mut message).context("Message ended early")?;
+
+    let value = serde_json::from_slice(&message).context("Message is not valid JSON");
+    message.zeroize();
+
+    Ok(Some(value?))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> Result<()> {
+    let mut data = serde_json::to_vec(message)?;
+
+    if data.len() > MAX_MESSAGE {
+        data.zeroize();
+        bail_code!(ErrorCode::InvalidInput, "Response of {} bytes is too large for the browser", data.len());
+    }
+
+    let result = output.write_all(&(data.len() as u32).to_ne_bytes())
+        .and_then(|_| output.write_all(&data))
+        .and_then(|_| output.flush());
+
+    data.zeroize();
+
+    Ok(result?)
+}
+
+// Answer messages until the browser closes the connection. `unlock` gives the master
+// password when it is available without asking; the vault is read for every request, so
+// `rspass lock` takes effect right away. Any byte streams can be passed, such as
+// recorded sessions.
+pub fn run(mut input: impl Read, mut output: impl Write, mut unlock: impl FnMut() -> Option<String>) -> Result<()> {
+    while let Some(message) = read_message(&mut input)? {
+        let id = message.get("id").cloned().unwrap_or(Value::Null);
+
+        let reply = match serde_json::from_value::<Request>(message) {
+            Ok(request) => handle(&request, &mut unlock),
+            Err(e) => Err(CodedError::new(ErrorCode::InvalidInput, format!("Invalid request: {}", e)).into())
+        };
+
+        let reply = match reply {
+            Ok(mut reply) => {
+                reply["id"] = id;
+                reply["ok"] = json!(true);
+                reply
+            }
+
+            Err(e) => json!({
+                "id": id,
+                "ok": false,
+                "error": { "code": ErrorCode::of(&e), "message": format!("{:#}", e) }
+            })
+        };
+
+        if let Err(e) = write_message(&mut output, &reply) {
+            let error = json!({ "id": reply["id"], "ok": false, "error": { "code": ErrorCode::of(&e), "message": format!("{:#}", e) } });
+            write_message(&mut output, &error)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn handle(request: &Request, unlock: &mut impl FnMut() -> Option<String>) -> Result<Value> {
+    match request {
+        Request::Status => {
+            let Some(mut master_password) = unlock() else {
+                return Ok(json!({ "locked": true }));
+            };
+
+            master_password.zeroize();
+
+            Ok(json!({ "locked": false }))
+        }
+
+        Request::Lookup { origin } => {
+            let page = Page::parse(origin)?;
+            let (vault, mut master_password) = open(unlock)?;
+            master_password.zeroize();
+
+            Ok(json!({ "logins": lookup(&vault, &page)? }))
+        }
+
+        Request::Save { origin, service, username, password, generate, update } => {
+            let page = Page::parse(origin)?;
+            let service = service.clone().unwrap_or_else(|| page.host.clone());
+            let generated = password.is_none();
+
+            let mut password = match (password, generate) {
+                (Some(password), _) => password.clone(),
+                (None, Some(policy)) => generate::generate(policy)?,
+                (None, None) => bail_code!(ErrorCode::InvalidInput, "Give a password or a policy to generate one with")
+            };
+
+            let (mut vault, mut master_password) = open(unlock)?;
+            let result = save(&mut vault, &service, username.as_deref(), &password, *update)
+                .and_then(|_| vault.set_urls(&service, std::slice::from_ref(&page.origin), &[], None))
+                .and_then(|_| vault.save(&master_password));
+            master_password.zeroize();
+
+            if let Err(e) = result {
+                password.zeroize();
+                return Err(e);
+            }
+
+            // Generated passwords are sent back, so the extension can fill them in
+            let reply = if generated {
+                json!({ "service": service, "password": password })
+            } else {
+                json!({ "service": service })
+            };
+
+            password.zeroize();
+
+            Ok(reply)
+        }
+
+        Request::Generate { policy } => {
+            let mut password = generate::generate(policy)?;
+            let reply = json!({ "password": password });
+            password.zeroize();
+
+            Ok(reply)
+        }
+    }
+}
+
+fn open(unlock: &mut impl FnMut() -> Option<String>) -> Result<(Vault, String)> {
+    let Some(mut master_password) = unlock() else {
+        bail_code!(ErrorCode::VaultLocked, "The vault is locked. Run 'rspass unlock' first.");
+    };
+
+    match Vault::load(&master_password) {
+        Ok(vault) => Ok((vault, master_password)),
+        Err(e) => {
+            master_password.zeroize();
+            Err(e)
+        }
+    }
+}
+
+// The page a request comes from
+struct Page {
+    address: String, // The page URL without its fragment
+    origin: String, // Scheme, host and port
+    host: String
+}
+
+impl Page {
+    // Only web pages are served, not extension or file URLs
+    fn parse(origin: &str) -> Result<Self> {
+        let mut url = Url::parse(origin).with_context(|| format!("Invalid origin '{}'", origin))?;
+        url.set_fragment(None);
+
+        if url.scheme() != "https" && url.scheme() != "http" {
+            bail_code!(ErrorCode::InvalidInput, "Origin '{}' is not a web page", origin);
+        }
+
+        let Some(host) = domain::host(origin) else {
+            bail_code!(ErrorCode::InvalidInput, "Origin '{}' has no host", origin);
+        };
+
+        Ok(Page { address: url.to_string(), origin: url.origin().ascii_serialization(), host })
+    }
+}
+
+// Logins for the page, closest matches first, as `rspass find-url` finds them
+fn lookup(vault: &Vault, page: &Page) -> Result<Vec<Value>> {
+    let logins = url_match::find(vault, &page.address)?.into_iter()
+        .filter(|hit| hit.entry.kind == EntryKind::Login)
+        .map(|hit| json!({
+            "service": hit.service,
+            "username": hit.entry.username,
+            "password": hit.entry.password,
+            "match": hit.level
+        }))
+        .collect();
+
+    Ok(logins)
+}
+
+fn save(vault: &mut Vault, service: &str, username: Option<&str>, password: &str, update: bool) -> Result<()> {
+    match vault.get_entry(service) {
+        None => vault.add_password(service, password)?,
+
+        Some(entry) if entry.kind != EntryKind::Login => {
+            bail_code!(ErrorCode::InvalidInput, "'{}' is not a login", service);
+        }
+
+        Some(_) if !update => {
+            bail_code!(ErrorCode::AlreadyExists, "'{}' already exists. Send \"update\": true to change its password.", service);
+        }
+
+        Some(entry) if entry.password == password => {}
+        Some(_) => vault.update_password(service, password)?
+    }
+
+    if let Some(username) = username {
+        vault.set_details(service, Some(username), None)?;
+    }
+
+    Ok(())
+}
+
+// Host manifest that lets the given extensions start `path`
+pub fn manifest(browser: Browser, extension_ids: &[String], path: &Path) -> Value {
+    let mut manifest = json!({
+        "name": HOST_NAME,
+        "description": "RsPass password manager",
+        "path": path,
+        "type": "stdio"
+    });
+
+    if browser == Browser::Firefox {
+        manifest["allowed_extensions"] = json!(extension_ids);
+    } else {
+        let origins: Vec<String> = extension_ids.iter()
+            .map(|id| format!("chrome-extension://{}/", id.trim_start_matches("chrome-extension://").trim_end_matches('/')))
+            .collect();
+
+        manifest["allowed_origins"] = json!(origins);
+    }
+
+    manifest
+}
+
+// Directory the browser looks for host manifests of the current user in
+pub fn manifest_dir(browser: Browser) -> Result<PathBuf> {
+    let home_dir = home_dir().context("Could not find home directory")?;
+
+    let dir = if cfg!(target_os = "macos") {
+        let support = home_dir.join("Library/Application Support");
+
+        match browser {
+            Browser::Chrome => support.join("Google/Chrome"),
+            Browser::Chromium => support.join("Chromium"),
+            Browser::Brave => support.join("BraveSoftware/Brave-Browser"),
+            Browser::Edge => support.join("Microsoft Edge"),
+            Browser::Firefox => support.join("Mozilla")
+        }.join("NativeMessagingHosts")
+    } else if cfg!(windows) {
+        bail_code!(ErrorCode::Other, "On Windows the manifest is registered in the registry. Save it without --install and add its path as the default value of HKCU\\Software\\<browser>\\NativeMessagingHosts\\{}.", HOST_NAME);
+    } else {
+        let config = home_dir.join(".config");
+
+        match browser {
+            Browser::Chrome => config.join("google-chrome/NativeMessagingHosts"),
+            Browser::Chromium => config.join("chromium/NativeMessagingHosts"),
+            Browser::Brave => config.join("BraveSoftware/Brave-Browser/NativeMessagingHosts"),
+            Browser::Edge => config.join("microsoft-edge/NativeMessagingHosts"),
+            Browser::Firefox => home_dir.join(".mozilla/native-messaging-hosts")
+        }
+    };
+
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MASTER_PASSWORD};
+    use crate::vault::UrlMatch;
+
+    fn frame(message: &str) -> Vec<u8> {
+        let mut data = (message.len() as u32).to_ne_bytes().to_vec();
+        data.extend_from_slice(message.as_bytes());
+
+        data
+    }
+
+    // A recorded session: the messages the browser sent, one after another
+    fn session(messages: &[&str]) -> Vec<u8> {
+        messages.iter().flat_map(|message| frame(message)).collect()
+    }
+
+    // The framed replies the host wrote
+    fn replies(mut output: &[u8]) -> Vec<Value> {
+        let mut replies = Vec::new();
+
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+
+        replies
+    }
+
+    fn run_session(input: &[u8], unlocked: bool) -> (Result<()>, Vec<Value>) {
+        let mut output = Vec::new();
+        let result = run(input, &mut output, || unlocked.then(|| MASTER_PASSWORD.to_string()));
+
+        (result, replies(&output))
+    }
+
+    #[test]
+    fn status() {
+        let input = session(&[r#"{"action":"status","id":1}"#]);
+
+        let (result, replies) = run_session(&input, false);
+        assert!(result.is_ok());
+        assert_eq!(replies, [json!({ "id": 1, "ok": true, "locked": true })]);
+
+        let (_, replies) = run_session(&input, true);
+        assert_eq!(replies, [json!({ "id": 1, "ok": true, "locked": false })]);
+    }
+
+    #[test]
+    fn generate() {
+        let input = session(&[
+            r#"{"action":"generate","id":"a","policy":{"length":12,"symbols":false}}"#,
+            r#"{"action":"generate","id":"b"}"#
+        ]);
+        let (result, replies) = run_session(&input, false);
+
+        assert!(result.is_ok());
+        assert_eq!(replies.len(), 2);
+
+        let password = replies[0]["password"].as_str().unwrap();
+        assert_eq!(replies[0]["id"], "a");
+        assert_eq!(password.len(), 12);
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_eq!(replies[1]["password"].as_str().unwrap().len(), 24);
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let input = session(&[
+            r#"{"action":"delete","id":1}"#,
+            r#"{"action":"lookup","id":2}"#,
+            r#"{"action":"lookup","id":3,"origin":"file:///etc/passwd"}"#,
+            r#"{"action":"lookup","id":4,"origin":"https://github.com"}"#
+        ]);
+        let (result, replies) = run_session(&input, false);
+
+        assert!(result.is_ok());
+
+        let codes: Vec<&Value> = replies.iter().map(|reply| &reply["error"]["code"]).collect();
+        assert_eq!(codes, ["invalid_input", "invalid_input", "invalid_input", "vault_locked"]);
+        assert!(replies.iter().enumerate().all(|(i, reply)| reply["id"] == i + 1 && reply["ok"] == false));
+    }
+
+    #[test]
+    fn oversized_message() {
+        let mut input = session(&[r#"{"action":"status","id":1}"#]);
+        input.extend_from_slice(&(MAX_MESSAGE as u32 + 1).to_ne_bytes());
+        input.extend_from_slice(b"{}");
+
+        let (result, replies) = run_session(&input, false);
+
+        // Messages before it are answered, then the host gives up
+        assert_eq!(replies.len(), 1);
+        assert_eq!(ErrorCode::of(&result.unwrap_err()), ErrorCode::InvalidInput);
+    }
+
+    #[test]
+    fn truncated_message() {
+        let mut input = session(&[r#"{"action":"status","id":1}"#]);
+        input.extend_from_slice(&frame(r#"{"action":"status","id":2}"#)[..10]);
+
+        let (result, replies) = run_session(&input, false);
+
+        assert_eq!(replies.len(), 1);
+        assert!(format!("{:#}", result.unwrap_err()).contains("ended early"));
+
+        // A partial length is the end of the session
+        let (result, replies) = run_session(&[1, 0], false);
+        assert!(result.is_ok());
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn invalid_json() {
+        let input = session(&[r#"{"action":"status","id":1}"#, r#"{"action":"status""#]);
+        let (result, replies) = run_session(&input, false);
+
+        assert_eq!(replies.len(), 1);
+        assert!(format!("{:#}", result.unwrap_err()).contains("not valid JSON"));
+    }
+
+    #[test]
+    fn lookup() {
+        let _vault = testing::fresh_vault();
+
+        testing::change(|vault| {
+            vault.add_password("github.com", "by-name").unwrap();
+            vault.set_details("github.com", Some("alice"), None).unwrap();
+
+            vault.add_password("gist", "gist-host").unwrap();
+            vault.set_urls("gist", &["https://gist.github.com".to_string()], &[], Some(UrlMatch::Host)).unwrap();
+
+            vault.add_password("gist-exact", "gist-exact").unwrap();
+            vault.set_urls("gist-exact", &["https://gist.github.com/alice".to_string()], &[], Some(UrlMatch::Exact)).unwrap();
+
+            vault.add_password("http-only", "plain").unwrap();
+            vault.set_urls("http-only", &["http://gist.github.com".to_string()], &[], None).unwrap();
+
+            vault.set_note("github.com/notes", "not a login").unwrap();
+            vault.add_password("gitlab.com", "other-site").unwrap();
+        });
+
+        let input = session(&[
+            r#"{"action":"lookup","id":1,"origin":"https://gist.github.com/alice#files"}"#,
+            r#"{"action":"lookup","id":2,"origin":"https://example.com"}"#
+        ]);
+        let (result, replies) = run_session(&input, true);
+
+        assert!(result.is_ok());
+        assert_eq!(replies[0]["logins"], json!([
+            { "service": "gist-exact", "username": null, "password": "gist-exact", "match": "exact" },
+            { "service": "gist", "username": null, "password": "gist-host", "match": "host" },
+            { "service": "github.com", "username": "alice", "password": "by-name", "match": "domain" }
+        ]));
+        assert_eq!(replies[1]["logins"], json!([]));
+    }
+
+    #[test]
+    fn save() {
+        let _vault = testing::fresh_vault();
+
+        let input = session(&[
+            r#"{"action":"save","id":1,"origin":"https://example.com/login","username":"alice","password":"first"}"#,
+            r#"{"action":"save","id":2,"origin":"https://example.com/login","password":"second"}"#,
+            r#"{"action":"save","id":3,"origin":"https://example.com/login","password":"second","update":true}"#,
+            r#"{"action":"save","id":4,"origin":"https://shop.example.org","service":"shop","generate":{"length":16}}"#,
+            r#"{"action":"save","id":5,"origin":"https://example.net"}"#
+        ]);
+        let (result, replies) = run_session(&input, true);
+
+        assert!(result.is_ok());
+        assert_eq!(replies[0], json!({ "id": 1, "ok": true, "service": "example.com" }));
+        assert_eq!(replies[1]["error"]["code"], "already_exists");
+        assert_eq!(replies[2], json!({ "id": 3, "ok": true, "service": "example.com" }));
+        assert_eq!(replies[3]["service"], "shop");
+        assert_eq!(replies[4]["error"]["code"], "invalid_input");
+
+        let vault = Vault::load(MASTER_PASSWORD).unwrap();
+
+        let entry = vault.get_entry("example.com").unwrap();
+        assert_eq!(entry.password, "second");
+        assert_eq!(entry.username.as_deref(), Some("alice"));
+        assert_eq!(entry.urls, ["https://example.com"]);
+
+        let generated = replies[3]["password"].as_str().unwrap();
+        assert_eq!(generated.len(), 16);
+        assert_eq!(vault.get_password("shop").unwrap(), generated);
+        assert!(vault.get_entry("example.net").is_none());
+    }
+}
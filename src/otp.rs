@@ -0,0 +1,350 @@
+use crate::error::{bail_code, ErrorCode};
+
+use anyhow::{Context, Result};
+use data_encoding::{Encoding, BASE32_NOPAD};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::fmt;
+use std::str::FromStr;
+use url::Url;
+use zeroize::Zeroize;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OtpAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512
+}
+
+impl FromStr for OtpAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_uppercase().replace('-', "").as_str() {
+            "SHA1" => Ok(OtpAlgorithm::Sha1),
+            "SHA256" => Ok(OtpAlgorithm::Sha256),
+            "SHA512" => Ok(OtpAlgorithm::Sha512),
+            _ => bail_code!(ErrorCode::InvalidInput, "Unsupported OTP algorithm '{}' (use SHA1, SHA256 or SHA512)", s)
+        }
+    }
+}
+
+impl fmt::Display for OtpAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OtpAlgorithm::Sha1 => write!(f, "SHA1"),
+            OtpAlgorithm::Sha256 => write!(f, "SHA256"),
+            OtpAlgorithm::Sha512 => write!(f, "SHA512")
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum OtpKind {
+    Totp { period: u64 }, // RFC 6238, time step in seconds
+    Hotp { counter: u64 } // RFC 4226, counter for the next code
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OtpSecret {
+    pub secret: String, // Base32 without padding
+    #[serde(flatten)]
+    pub kind: OtpKind,
+    pub digits: u32,
+    pub algorithm: OtpAlgorithm,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>
+}
+
+impl Zeroize for OtpSecret {
+    fn zeroize(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+impl OtpSecret {
+    // Parse an otpauth:// URI or a raw base32 secret (TOTP, 6 digits, 30 seconds, SHA-1)
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+
+        if input.starts_with("otpauth://") {
+            return Self::parse_uri(input);
+        }
+
+        Ok(OtpSecret {
+            secret: normalize_secret(input)?,
+            kind: OtpKind::Totp { period: 30 },
+            digits: 6,
+            algorithm: OtpAlgorithm::Sha1,
+            issuer: None,
+            account: None
+        })
+    }
+
+    fn parse_uri(input: &str) -> Result<Self> {
+        let uri = Url::parse(input).context("Invalid otpauth URI")?;
+
+        let mut secret = None;
+        let mut issuer = None;
+        let mut digits = 6;
+        let mut period = 30;
+        let mut counter = None;
+        let mut algorithm = OtpAlgorithm::Sha1;
+
+        for (key, value) in uri.query_pairs() {
+            match key.as_ref() {
+                "secret" => secret = Some(normalize_secret(&value)?),
+                "issuer" => issuer = Some(value.into_owned()),
+                "digits" => digits = value.parse().context("Invalid digits in otpauth URI")?,
+                "period" => period = value.parse().context("Invalid period in otpauth URI")?,
+                "counter" => counter = Some(value.parse().context("Invalid counter in otpauth URI")?),
+                "algorithm" => algorithm = value.parse()?,
+                _ => {}
+            }
+        }
+
+        let kind = match uri.host_str() {
+            Some("totp") => OtpKind::Totp { period },
+            Some("hotp") => OtpKind::Hotp { counter: counter.context("HOTP URI is missing the counter")? },
+            _ => bail_code!(ErrorCode::InvalidInput, "otpauth URI must be of type totp or hotp")
+        };
+
+        // The label is "issuer:account" or just "account"
+        let label = percent_decode(uri.path().trim_start_matches('/'));
+        let (label_issuer, account) = match label.split_once(':') {
+            Some((issuer, account)) => (Some(issuer.trim().to_string()), account.trim().to_string()),
+            None => (None, label.trim().to_string())
+        };
+
+        let otp = OtpSecret {
+            secret: secret.context("otpauth URI is missing the secret")?,
+            kind,
+            digits,
+            algorithm,
+            issuer: issuer.or(label_issuer),
+            account: (!account.is_empty()).then_some(account)
+        };
+        otp.validate()?;
+
+        Ok(otp)
+    }
+
+    // Check the parameters after parsing or changing them
+    pub fn validate(&self) -> Result<()> {
+        if !(6..=10).contains(&self.digits) {
+            bail_code!(ErrorCode::InvalidInput, "OTP codes must have between 6 and 10 digits");
+        }
+
+        if let OtpKind::Totp { period: 0 } = self.kind {
+            bail_code!(ErrorCode::InvalidInput, "TOTP period must be at least one second");
+        }
+
+        Ok(())
+    }
+
+    // Code for a given counter value (RFC 4226)
+    pub fn hotp(&self, counter: u64) -> Result<String> {
+        let mut key = base32().decode(self.secret.as_bytes()).context("Invalid base32 secret")?;
+
+        let mut digest = match self.algorithm {
+            OtpAlgorithm::Sha1 => hmac::<Hmac<Sha1>>(&key, counter),
+            OtpAlgorithm::Sha256 => hmac::<Hmac<Sha256>>(&key, counter),
+            OtpAlgorithm::Sha512 => hmac::<Hmac<Sha512>>(&key, counter)
+        };
+        key.zeroize();
+
+        // Dynamic truncation
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([digest[offset] & 0x7f, digest[offset + 1], digest[offset + 2], digest[offset + 3]]);
+        digest.zeroize();
+
+        let code = binary as u64 % 10u64.pow(self.digits);
+
+        Ok(format!("{:0width$}", code, width = self.digits as usize))
+    }
+
+    // Code valid at a unix timestamp (RFC 6238)
+    pub fn totp(&self, unix_time: u64) -> Result<String> {
+        let OtpKind::Totp { period } = self.kind else {
+            bail_code!(ErrorCode::InvalidInput, "Not a TOTP secret");
+        };
+
+        self.hotp(unix_time / period)
+    }
+
+    // Seconds until the TOTP code valid at `unix_time` changes
+    pub fn seconds_remaining(&self, unix_time: u64) -> Option<u64> {
+        match self.kind {
+            OtpKind::Totp { period } => Some(period - unix_time % period),
+            OtpKind::Hotp { .. } => None
+        }
+    }
+}
+
+fn hmac<M: Mac + hmac::digest::KeyInit>(key: &[u8], counter: u64) -> Vec<u8> {
+    let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Base32 that tolerates non-zero trailing bits, which some issuers produce
+fn base32() -> Encoding {
+    let mut spec = BASE32_NOPAD.specification();
+    spec.check_trailing_bits = false;
+
+    spec.encoding().expect("valid base32 specification")
+}
+
+// Uppercase, strip spaces and padding, and check the secret decodes
+fn normalize_secret(secret: &str) -> Result<String> {
+    let normalized: String = secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    let mut key = base32().decode(normalized.as_bytes()).context("Secret is not valid base32")?;
+    let is_empty = key.is_empty();
+    key.zeroize();
+
+    if is_empty {
+        bail_code!(ErrorCode::InvalidInput, "Secret cannot be empty");
+    }
+
+    Ok(normalized)
+}
+
+fn percent_decode(input: &str) -> String {
+    url::form_urlencoded::parse(format!("x={}", input.replace('+', "%2B")).as_bytes())
+        .next()
+        .map(|(_, value)| value.into_owned())
+        .unwrap_or_default()
+}
+
+// Current unix time in seconds
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret(key: &[u8], algorithm: OtpAlgorithm, digits: u32) -> OtpSecret {
+        OtpSecret {
+            secret: BASE32_NOPAD.encode(key),
+            kind: OtpKind::Totp { period: 30 },
+            digits,
+            algorithm,
+            issuer: None,
+            account: None
+        }
+    }
+
+    #[test]
+    fn rfc4226_hotp() {
+        let otp = secret(b"12345678901234567890", OtpAlgorithm::Sha1, 6);
+        let expected = ["755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583", "399871", "520489"];
+
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(otp.hotp(counter as u64).unwrap(), *code, "counter {}", counter);
+        }
+    }
+
+    #[test]
+    fn rfc6238_totp() {
+        let sha1 = secret(b"12345678901234567890", OtpAlgorithm::Sha1, 8);
+        let sha256 = secret(b"12345678901234567890123456789012", OtpAlgorithm::Sha256, 8);
+        let sha512 = secret(b"1234567890123456789012345678901234567890123456789012345678901234", OtpAlgorithm::Sha512, 8);
+
+        let expected = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826")
+        ];
+
+        for (time, code1, code256, code512) in expected {
+            assert_eq!(sha1.totp(time).unwrap(), code1, "SHA1 at {}", time);
+            assert_eq!(sha256.totp(time).unwrap(), code256, "SHA256 at {}", time);
+            assert_eq!(sha512.totp(time).unwrap(), code512, "SHA512 at {}", time);
+        }
+    }
+
+    #[test]
+    fn seconds_remaining() {
+        let otp = secret(b"12345678901234567890", OtpAlgorithm::Sha1, 6);
+
+        assert_eq!(otp.seconds_remaining(59), Some(1));
+        assert_eq!(otp.seconds_remaining(60), Some(30));
+    }
+
+    #[test]
+    fn parse_raw_secret() {
+        let otp = OtpSecret::parse(" jbsw y3dp-ehpk 3pxp== ").unwrap();
+
+        assert_eq!(otp.secret, "JBSWY3DPEHPK3PXP");
+        assert!(matches!(otp.kind, OtpKind::Totp { period: 30 }));
+        assert_eq!(otp.digits, 6);
+        assert!(otp.algorithm == OtpAlgorithm::Sha1);
+    }
+
+    #[test]
+    fn parse_totp_uri() {
+        let otp = OtpSecret::parse("otpauth://totp/ACME%20Co:john@example.com?secret=JBSWY3DPEHPK3PXP&issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60").unwrap();
+
+        assert_eq!(otp.secret, "JBSWY3DPEHPK3PXP");
+        assert!(matches!(otp.kind, OtpKind::Totp { period: 60 }));
+        assert_eq!(otp.digits, 8);
+        assert!(otp.algorithm == OtpAlgorithm::Sha256);
+        assert_eq!(otp.issuer.as_deref(), Some("ACME Co"));
+        assert_eq!(otp.account.as_deref(), Some("john@example.com"));
+    }
+
+    #[test]
+    fn parse_hotp_uri() {
+        let otp = OtpSecret::parse("otpauth://hotp/Example:alice?secret=JBSWY3DPEHPK3PXP&counter=42").unwrap();
+
+        assert!(matches!(otp.kind, OtpKind::Hotp { counter: 42 }));
+        assert_eq!(otp.issuer.as_deref(), Some("Example"));
+        assert_eq!(otp.account.as_deref(), Some("alice"));
+
+        assert!(OtpSecret::parse("otpauth://hotp/Example:alice?secret=JBSWY3DPEHPK3PXP").is_err());
+    }
+
+    #[test]
+    fn parse_invalid_uris() {
+        let invalid = [
+            "otpauth://totp/Example?secret=not-base32!",
+            "otpauth://totp/Example?secret=",
+            "otpauth://totp/Example?issuer=Example",
+            "otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP&digits=six",
+            "otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP&digits=5",
+            "otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP&digits=11",
+            "otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP&period=0",
+            "otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP&algorithm=MD5",
+            "otpauth://motp/Example?secret=JBSWY3DPEHPK3PXP"
+        ];
+
+        for uri in invalid {
+            assert!(OtpSecret::parse(uri).is_err(), "{}", uri);
+        }
+
+        let Err(e) = OtpSecret::parse("otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP&algorithm=MD5") else {
+            panic!("unknown algorithm accepted");
+        };
+        assert_eq!(ErrorCode::of(&e), ErrorCode::InvalidInput);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::error::ErrorCode;
+
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Default)]
+pub enum Format {
+    #[default]
+    Human, // Plain sentences for people
+    Json, // One JSON object per command
+    Tsv // Tab-separated rows without a header
+}
+
+impl Format {
+    // Format requested on the command line, for errors raised before the arguments are parsed
+    pub fn from_raw_args() -> Self {
+        let args: Vec<String> = std::env::args().collect();
+
+        let value = args.iter().enumerate().find_map(|(index, arg)| match arg.strip_prefix("--format") {
+            Some("") => args.get(index + 1).cloned(),
+            Some(value) => value.strip_prefix('=').map(|v| v.to_string()),
+            None => None
+        });
+
+        value.and_then(|value| Format::from_str(&value, true).ok()).unwrap_or_default()
+    }
+}
+
+pub struct Output {
+    pub format: Format
+}
+
+impl Output {
+    pub fn is_human(&self) -> bool {
+        self.format == Format::Human
+    }
+
+    // Print a status message; JSON wraps it in a status object
+    pub fn message(&self, text: impl AsRef<str>) {
+        match self.format {
+            Format::Json => self.json(&json!({ "status": "ok", "message": text.as_ref() })),
+            Format::Human | Format::Tsv => println!("{}", text.as_ref())
+        }
+    }
+
+    // Print a JSON document on one line
+    pub fn json(&self, value: &Value) {
+        println!("{}", value);
+    }
+
+    // Print a tab-separated row, escaping characters that would break the columns
+    pub fn row(&self, fields: &[&str]) {
+        let escaped: Vec<String> = fields.iter()
+            .map(|field| field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r"))
+            .collect();
+
+        println!("{}", escaped.join("\t"));
+    }
+
+    // Print an error in the selected format
+    pub fn error(&self, error: &anyhow::Error) {
+        let code = ErrorCode::of(error);
+
+        match self.format {
+            Format::Human => eprintln!("Error: {:?}", error),
+            Format::Json => self.json(&json!({ "error": { "code": code, "message": format!("{:#}", error) } })),
+            Format::Tsv => eprintln!("error\t{}\t{:#}", json!(code).as_str().unwrap_or_default(), error)
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use crate::error::{bail_code, CodedError, ErrorCode};
+use crate::otp::{self, OtpKind};
+use crate::vault::{self, Entry, EntryKind, Vault};
+
+use anyhow::Result;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::collections::HashMap;
+use std::fmt;
+use zeroize::Zeroize;
+
+pub const SCHEME: &str = "rspass://";
+
+// Characters kept as they are in reference segments
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~').remove(b'@').remove(b':');
+
+// Fields a reference can point at
+pub const FIELDS: &[&str] = &["password", "username", "notes", "folder", "tags", "otp"];
+
+// Address of one field: rspass://[vault/]entry/field. Segments containing '/' or other
+// special characters are percent-encoded.
+#[derive(Clone, PartialEq)]
+pub struct Reference {
+    pub vault: Option<String>, // The selected vault when missing
+    pub entry: String,
+    pub field: String
+}
+
+impl Reference {
+    pub fn new(vault: Option<&str>, entry: &str, field: &str) -> Result<Self> {
+        if entry.is_empty() {
+            bail_code!(ErrorCode::InvalidInput, "Reference needs an entry name");
+        }
+
+        if !FIELDS.contains(&field) {
+            bail_code!(ErrorCode::InvalidInput, "Unknown field '{}'. Use one of: {}", field, FIELDS.join(", "));
+        }
+
+        Ok(Reference {
+            vault: vault.map(|v| v.to_string()),
+            entry: entry.to_string(),
+            field: field.to_string()
+        })
+    }
+
+    // Parse a rspass:// URI
+    pub fn parse_uri(uri: &str) -> Result<Self> {
+        let Some(path) = uri.strip_prefix(SCHEME) else {
+            bail_code!(ErrorCode::InvalidInput, "'{}' does not start with {}", uri, SCHEME);
+        };
+
+        let segments = path.split('/')
+            .map(|segment| percent_decode_str(segment).decode_utf8().map(|s| s.into_owned()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| CodedError::new(ErrorCode::InvalidInput, format!("'{}' is not valid UTF-8", uri)))?;
+
+        match segments.as_slice() {
+            [entry, field] => Self::new(None, entry, field),
+            [vault, entry, field] => Self::new(Some(vault), entry, field),
+            _ => bail_code!(ErrorCode::InvalidInput, "Expected {}[vault/]entry/field, got '{}'", SCHEME, uri)
+        }
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let encode = |segment: &str| utf8_percent_encode(segment, SEGMENT).to_string();
+
+        write!(f, "{}", SCHEME)?;
+
+        if let Some(vault) = &self.vault {
+            write!(f, "{}/", encode(vault))?;
+        }
+
+        write!(f, "{}/{}", encode(&self.entry), encode(&self.field))
+    }
+}
+
+// Value of a field of an entry, None when the entry does not have it
+pub fn field_value(entry: &Entry, field: &str) -> Result<Option<String>> {
+    let value = match field {
+        "password" if entry.kind == EntryKind::Note => None,
+        "password" => Some(entry.password.clone()),
+        "username" => entry.username.clone(),
+        "notes" => entry.notes.clone(),
+        "folder" => entry.folder.clone(),
+        "tags" => (!entry.tags.is_empty()).then(|| entry.tags.join(",")),
+
+        "otp" => match &entry.otp {
+            Some(secret) if matches!(secret.kind, OtpKind::Totp { .. }) => Some(secret.totp(otp::unix_now())?),
+            // Counter-based codes change the vault, so they cannot be read as a value
+            Some(_) => bail_code!(ErrorCode::InvalidInput, "Only time-based one-time codes can be referenced"),
+            None => None
+        },
+
+        _ => bail_code!(ErrorCode::InvalidInput, "Unknown field '{}'", field)
+    };
+
+    Ok(value)
+}
+
+// Asks for the master password of a vault
+type Unlock<'a> = Box<dyn FnMut(&str) -> Result<String> + 'a>;
+
+// Resolves references, unlocking each vault once on first use
+pub struct Resolver<'a> {
+    vaults: HashMap<String, Vault>,
+    unlock: Unlock<'a>
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(unlock: impl FnMut(&str) -> Result<String> + 'a) -> Self {
+        Resolver {
+            vaults: HashMap::new(),
+            unlock: Box::new(unlock)
+        }
+    }
+
+    pub fn resolve(&mut self, reference: &Reference) -> Result<String> {
+        let name = reference.vault.clone().unwrap_or_else(|| vault::selected_vault().to_string());
+
+        if !self.vaults.contains_key(&name) {
+            let mut master_password = (self.unlock)(&name)?;
+            let vault = Vault::load_named(&name, &master_password);
+            master_password.zeroize();
+
+            self.vaults.insert(name.clone(), vault?);
+        }
+
+        let Some(entry) = self.vaults[&name].get_entry(&reference.entry) else {
+            bail_code!(ErrorCode::NotFound, "No entry '{}' in vault '{}'", reference.entry, name);
+        };
+
+        match field_value(entry, &reference.field)? {
+            Some(value) => Ok(value),
+            None => bail_code!(ErrorCode::NotFound, "'{}' has no {}", reference.entry, reference.field)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MASTER_PASSWORD};
+
+    #[test]
+    fn parse_uris() {
+        let reference = Reference::parse_uri("rspass://github/password").unwrap();
+        assert!(reference == Reference::new(None, "github", "password").unwrap());
+
+        let reference = Reference::parse_uri("rspass://work/db%2Fprod%20eu/username").unwrap();
+        assert_eq!(reference.vault.as_deref(), Some("work"));
+        assert_eq!(reference.entry, "db/prod eu");
+        assert_eq!(reference.field, "username");
+
+        for uri in ["https://github/password", "rspass://github", "rspass://a/b/c/password", "rspass:///password", "rspass://github/pin", "rspass://%ff/password"] {
+            let e = Reference::parse_uri(uri).err().unwrap();
+            assert_eq!(ErrorCode::of(&e), ErrorCode::InvalidInput, "{}", uri);
+        }
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let reference = Reference::new(Some("work"), "db/prod eu", "otp").unwrap();
+        let uri = reference.to_string();
+
+        assert_eq!(uri, "rspass://work/db%2Fprod%20eu/otp");
+        assert!(Reference::parse_uri(&uri).unwrap() == reference);
+        assert_eq!(Reference::new(None, "me@host:22", "password").unwrap().to_string(), "rspass://me@host:22/password");
+    }
+
+    #[test]
+    fn field_values() {
+        let mut vault = Vault::in_memory();
+        vault.add_password("github", "gh-token").unwrap();
+        vault.set_tags("github", &["dev".to_string(), "work".to_string()], &[]).unwrap();
+        vault.set_note("recovery", "codes").unwrap();
+
+        let github = vault.get_entry("github").unwrap();
+        assert_eq!(field_value(github, "password").unwrap().as_deref(), Some("gh-token"));
+        assert_eq!(field_value(github, "tags").unwrap().as_deref(), Some("dev,work"));
+        assert_eq!(field_value(github, "username").unwrap(), None);
+        assert_eq!(field_value(github, "otp").unwrap(), None);
+
+        let recovery = vault.get_entry("recovery").unwrap();
+        assert_eq!(field_value(recovery, "password").unwrap(), None);
+        assert_eq!(field_value(recovery, "notes").unwrap().as_deref(), Some("codes"));
+    }
+
+    #[test]
+    fn resolver_unlocks_once() {
+        let _vault = testing::fresh_vault();
+        testing::change(|vault| vault.add_password("github", "gh-token").unwrap());
+
+        let mut unlocked = 0;
+        let mut resolver = Resolver::new(|_| {
+            unlocked += 1;
+            Ok(MASTER_PASSWORD.to_string())
+        });
+
+        let password = Reference::new(None, "github", "password").unwrap();
+        assert_eq!(resolver.resolve(&password).unwrap(), "gh-token");
+        assert_eq!(resolver.resolve(&password).unwrap(), "gh-token");
+
+        let e = resolver.resolve(&Reference::new(None, "github", "username").unwrap()).unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::NotFound);
+
+        let e = resolver.resolve(&Reference::new(None, "gitlab", "password").unwrap()).unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::NotFound);
+
+        drop(resolver);
+        assert_eq!(unlocked, 1);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::error::{bail_code, ErrorCode};
+use crate::vault::Vault;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use glob::{MatchOptions, Pattern};
+use regex::{Regex, RegexBuilder};
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Default)]
+pub enum SearchMode {
+    #[default]
+    Fuzzy, // Characters in order, ranked by how closely they match
+    Substring, // Query appears anywhere in the text
+    Glob, // Shell-style pattern such as 'git*' or '*.example.com'
+    Regex // Regular expression
+}
+
+enum Compiled {
+    Fuzzy(Vec<char>),
+    Substring(String),
+    Glob(Pattern),
+    Regex(Regex)
+}
+
+pub struct Matcher {
+    compiled: Compiled,
+    case_sensitive: bool
+}
+
+// An entry that matched, with the field that matched best
+pub struct SearchHit<'a> {
+    pub service: &'a String,
+    pub field: &'static str,
+    pub score: i64
+}
+
+impl Matcher {
+    pub fn new(query: &str, mode: SearchMode, case_sensitive: bool) -> Result<Self> {
+        let compiled = match mode {
+            SearchMode::Fuzzy => Compiled::Fuzzy(fold(query, case_sensitive).chars().filter(|c| !c.is_whitespace()).collect()),
+            SearchMode::Substring => Compiled::Substring(fold(query, case_sensitive)),
+
+            SearchMode::Glob => match Pattern::new(query) {
+                Ok(pattern) => Compiled::Glob(pattern),
+                Err(e) => bail_code!(ErrorCode::InvalidInput, "Invalid glob pattern '{}': {}", query, e)
+            },
+
+            SearchMode::Regex => match RegexBuilder::new(query).case_insensitive(!case_sensitive).build() {
+                Ok(regex) => Compiled::Regex(regex),
+                Err(e) => bail_code!(ErrorCode::InvalidInput, "Invalid regular expression: {}", e)
+            }
+        };
+
+        Ok(Matcher { compiled, case_sensitive })
+    }
+
+    // Score of `text`, higher is better. Free text such as notes is never fuzzy matched,
+    // because almost any short query is a subsequence of a long note.
+    pub fn score(&self, text: &str, free_text: bool) -> Option<i64> {
+        match &self.compiled {
+            Compiled::Fuzzy(query) if free_text => {
+                let query: String = query.iter().collect();
+
+                fold(text, self.case_sensitive).contains(&query).then_some(0)
+            }
+
+            Compiled::Fuzzy(query) => fuzzy_score(query, &fold(text, self.case_sensitive)),
+            Compiled::Substring(query) => fold(text, self.case_sensitive).contains(query.as_str()).then_some(0),
+
+            Compiled::Glob(pattern) => {
+                let options = MatchOptions { case_sensitive: self.case_sensitive, ..MatchOptions::new() };
+
+                if free_text {
+                    text.split_whitespace().any(|word| pattern.matches_with(word, options)).then_some(0)
+                } else {
+                    pattern.matches_with(text, options).then_some(0)
+                }
+            }
+
+            Compiled::Regex(regex) => regex.is_match(text).then_some(0)
+        }
+    }
+}
+
+fn fold(text: &str, case_sensitive: bool) -> String {
+    if case_sensitive { text.to_string() } else { text.to_lowercase() }
+}
+
+// Score the query as a subsequence of the text. Consecutive characters and characters at the
+// start of a word score higher, gaps score lower. Every start position is tried.
+fn fuzzy_score(query: &[char], text: &str) -> Option<i64> {
+    let text: Vec<char> = text.chars().collect();
+
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let mut best = None;
+
+    for start in (0..text.len()).filter(|&i| text[i] == query[0]) {
+        let mut score = 0;
+        let mut position = start;
+        let mut previous: Option<usize> = None;
+        let mut matched = 0;
+
+        while matched < query.len() && position < text.len() {
+            if text[position] == query[matched] {
+                score += 1;
+
+                if previous.is_some_and(|p| p + 1 == position) {
+                    score += 5;
+                } else if let Some(p) = previous {
+                    score -= (position - p - 1).min(5) as i64;
+                }
+
+                if position == 0 || !text[position - 1].is_alphanumeric() {
+                    score += 8;
+                }
+
+                previous = Some(position);
+                matched += 1;
+            }
+
+            position += 1;
+        }
+
+        if matched == query.len() {
+            // Prefer short names and matches near the start
+            score -= (start as i64).min(10) + (text.len() - query.len()).min(20) as i64 / 4;
+
+            if query.len() == text.len() {
+                score += 20;
+            }
+
+            best = best.max(Some(score));
+        }
+    }
+
+    best
+}
+
+// Entries matching the query, best matches first
+pub fn search<'a>(vault: &'a Vault, matcher: &Matcher) -> Vec<SearchHit<'a>> {
+    let mut hits: Vec<SearchHit> = vault.list_services().into_iter()
+        .filter_map(|service| {
+            let entry = vault.get_entry(service)?;
+
+            // Matches on the name rank above matches in other fields
+            let name = matcher.score(service, false).map(|score| (score + 1000, "service"));
+
+            let other = entry.search_fields().into_iter()
+                .filter_map(|(field, text, free_text)| Some((matcher.score(text, free_text)?, field)))
+                .max_by_key(|(score, _)| *score);
+
+            let (score, field) = name.or(other)?;
+
+            Some(SearchHit { service, field, score })
+        })
+        .collect();
+
+    hits.sort_by(|a, b| b.score.cmp(&a.score).then(a.service.cmp(b.service)));
+
+    hits
+}
+
+// Names that look like a mistyped `query`, closest first
+pub fn suggest<'a>(names: &[&'a String], query: &str, limit: usize) -> Vec<&'a String> {
+    let query = query.to_lowercase();
+
+    let mut scored: Vec<(f64, &String)> = names.iter()
+        .filter_map(|name| {
+            let lower = name.to_lowercase();
+            let mut similarity = strsim::jaro_winkler(&query, &lower);
+
+            // One name containing the other counts, unless the contained part is tiny
+            let (shorter, longer) = if lower.len() < query.len() { (&lower, &query) } else { (&query, &lower) };
+
+            if longer.contains(shorter.as_str()) && shorter.len() * 3 >= longer.len() {
+                similarity = similarity.max(0.9);
+            }
+
+            (similarity >= 0.75).then_some((similarity, *name))
+        })
+        .collect();
+
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(b.1)));
+
+    scored.into_iter().take(limit).map(|(_, name)| name).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+
+    fn matcher(query: &str, mode: SearchMode) -> Matcher {
+        Matcher::new(query, mode, false).unwrap()
+    }
+
+    #[test]
+    fn fuzzy() {
+        let git = matcher("gh", SearchMode::Fuzzy);
+
+        assert!(git.score("GitHub", false).is_some());
+        assert!(git.score("hg", false).is_none());
+        assert!(Matcher::new("gh", SearchMode::Fuzzy, true).unwrap().score("GitHub", false).is_none());
+
+        // Exact names beat word starts, which beat letters inside words
+        let hub = matcher("hub", SearchMode::Fuzzy);
+        let exact = hub.score("hub", false).unwrap();
+        let word = hub.score("docker-hub", false).unwrap();
+        let inside = hub.score("github", false).unwrap();
+        assert!(exact > word && word > inside, "{} {} {}", exact, word, inside);
+
+        // Spaces in the query are left out
+        assert_eq!(matcher("doc hub", SearchMode::Fuzzy).score("docker-hub", false), matcher("dochub", SearchMode::Fuzzy).score("docker-hub", false));
+
+        // Free text needs the query as it is
+        assert!(hub.score("see the github wiki", true).is_some());
+        assert!(matcher("sw", SearchMode::Fuzzy).score("see the github wiki", true).is_none());
+    }
+
+    #[test]
+    fn substring() {
+        let hub = matcher("HUB", SearchMode::Substring);
+
+        assert_eq!(hub.score("github", false), Some(0));
+        assert_eq!(hub.score("gh", false), None);
+        assert_eq!(Matcher::new("HUB", SearchMode::Substring, true).unwrap().score("github", false), None);
+    }
+
+    #[test]
+    fn glob() {
+        let git = matcher("git*", SearchMode::Glob);
+
+        assert!(git.score("GitHub", false).is_some());
+        assert!(git.score("my-git", false).is_none());
+        assert!(Matcher::new("git*", SearchMode::Glob, true).unwrap().score("GitHub", false).is_none());
+
+        // Free text matches word by word
+        assert!(git.score("moved to gitlab", true).is_some());
+        assert!(git.score("moved to gitlab", false).is_none());
+
+        let e = Matcher::new("[git", SearchMode::Glob, false).err().unwrap();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::InvalidInput);
+    }
+
+    #[test]
+    fn regex() {
+        let git = matcher("^git(hub|lab)$", SearchMode::Regex);
+
+        assert!(git.score("GitLab", false).is_some());
+        assert!(git.score("gitea", false).is_none());
+        assert!(Matcher::new("^git(hub|lab)$", SearchMode::Regex, true).unwrap().score("GitLab", false).is_none());
+
+        let e = Matcher::new("git(", SearchMode::Regex, false).err().unwrap();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::InvalidInput);
+    }
+
+    #[test]
+    fn names_rank_above_fields() {
+        let mut vault = Vault::in_memory();
+        vault.add_password("github", "one").unwrap();
+        vault.add_password("mail", "two").unwrap();
+        vault.set_details("mail", Some("github-bot"), None).unwrap();
+        vault.add_password("bank", "three").unwrap();
+
+        let hits = search(&vault, &matcher("github", SearchMode::Substring));
+        let hits: Vec<(&str, &str)> = hits.iter().map(|hit| (hit.service.as_str(), hit.field)).collect();
+
+        assert_eq!(hits, [("github", "service"), ("mail", "username")]);
+    }
+
+    #[test]
+    fn suggestions() {
+        let names = ["github", "gitlab", "bank", "docker-registry"].map(String::from);
+        let names: Vec<&String> = names.iter().collect();
+
+        assert_eq!(suggest(&names, "GitHib", 3), ["github", "gitlab"]);
+        assert_eq!(suggest(&names, "gitlab", 1), ["gitlab"]);
+        assert!(suggest(&names, "zebra", 3).is_empty());
+
+        // Jaro-Winkler similarity of 0.775 and 0.583 on either side of the 0.75 threshold
+        assert_eq!(suggest(&names, "b", 3), ["bank"]);
+        assert!(suggest(&names, "bk", 3).is_empty());
+
+        // Containing the query counts unless it is a small part of the name
+        assert_eq!(suggest(&names, "registry", 3), ["docker-registry"]);
+        assert!(suggest(&names, "try", 3).is_empty());
+    }
+}
@@ -0,0 +1,1039 @@
+use crate::error::{bail_code, ErrorCode};
+use crate::vault::{EntryKind, Vault};
+
+use aes::Aes128;
+use anyhow::{Context, Result};
+use cbc::cipher::block_padding::Pkcs7;
+use cbc::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use hkdf::Hkdf;
+use num_bigint::BigUint;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Type, Value};
+use zbus::fdo::RequestNameFlags;
+use zbus::{fdo, interface, Connection, ObjectServer};
+use zeroize::Zeroize;
+
+pub const BUS_NAME: &str = "org.freedesktop.secrets";
+
+// Folder of the vault that holds the items of the collection
+pub const FOLDER: &str = "secret-service";
+
+const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+const COLLECTION_PATH: &str = "/org/freedesktop/secrets/collection/rspass";
+const DEFAULT_ALIAS_PATH: &str = "/org/freedesktop/secrets/aliases/default";
+const SESSION_PATH: &str = "/org/freedesktop/secrets/session";
+
+// Object path clients pass when there is no prompt or object
+const NO_OBJECT: &str = "/";
+
+const LABEL_PROPERTY: &str = "org.freedesktop.Secret.Item.Label";
+const ATTRIBUTES_PROPERTY: &str = "org.freedesktop.Secret.Item.Attributes";
+
+const PLAIN: &str = "plain";
+const DH_AES: &str = "dh-ietf1024-sha256-aes128-cbc-pkcs7";
+
+// Second Oakley group of RFC 2409, used with generator 2 by the dh-ietf1024 algorithm
+const IETF1024_PRIME: &str = "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7EDEE386BFB5A899FA5AE9F24117C4B1FE649286651ECE65381FFFFFFFFFFFFFFFF";
+
+// A secret as it goes over the bus, encrypted for the session unless it is plain
+#[derive(Serialize, Deserialize, Type)]
+struct Secret {
+    session: OwnedObjectPath,
+    parameters: Vec<u8>, // The AES IV of encrypted sessions
+    value: Vec<u8>,
+    content_type: String
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+#[derive(zbus::DBusError, Debug)]
+#[zbus(prefix = "org.freedesktop.Secret.Error")]
+enum Error {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    IsLocked(String),
+    NoSession(String),
+    NoSuchObject(String)
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        let message = format!("{:#}", e);
+
+        match ErrorCode::of(&e) {
+            ErrorCode::VaultLocked => Error::IsLocked(message),
+            ErrorCode::NotFound => Error::NoSuchObject(message),
+            ErrorCode::InvalidInput | ErrorCode::AlreadyExists => Error::ZBus(fdo::Error::InvalidArgs(message).into()),
+            _ => Error::ZBus(fdo::Error::Failed(message).into())
+        }
+    }
+}
+
+impl From<fdo::Error> for Error {
+    fn from(e: fdo::Error) -> Self {
+        Error::ZBus(e.into())
+    }
+}
+
+impl From<zbus::zvariant::Error> for Error {
+    fn from(e: zbus::zvariant::Error) -> Self {
+        Error::ZBus(e.into())
+    }
+}
+
+// Properties can only fail with the standard errors
+fn failed(e: anyhow::Error) -> fdo::Error {
+    fdo::Error::Failed(format!("{:#}", e))
+}
+
+struct State {
+    master_password: Option<String>,
+    unlock: Box<dyn FnMut() -> Option<String> + Send>,
+    sessions: HashMap<String, Option<[u8; 16]>>, // AES key of each session, None for plain ones
+    items: BTreeMap<String, u64>, // Entries and the numbers of their item objects
+    attributes: BTreeMap<u64, BTreeMap<String, String>>, // As last read, so locked items can be searched
+    next_id: u64
+}
+
+type Shared = Arc<Mutex<State>>;
+
+fn lock(state: &Shared) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl State {
+    // Unlocked with the master password from the environment or `rspass unlock`. There is no
+    // prompt, as the daemon has no terminal.
+    fn ensure_unlocked(&mut self) -> Result<()> {
+        if self.master_password.is_none() {
+            self.master_password = (self.unlock)();
+        }
+
+        if self.master_password.is_none() {
+            bail_code!(ErrorCode::VaultLocked, "The vault is locked. Run 'rspass unlock' first.");
+        }
+
+        Ok(())
+    }
+
+    fn forget(&mut self) {
+        self.master_password.zeroize();
+        self.master_password = None;
+    }
+
+    // The vault is read for every call, so changes made with other commands show up
+    fn open(&self) -> Result<(Vault, &str)> {
+        let Some(master_password) = self.master_password.as_deref() else {
+            bail_code!(ErrorCode::VaultLocked, "The vault is locked. Run 'rspass unlock' first.");
+        };
+
+        Ok((Vault::load(master_password)?, master_password))
+    }
+
+    fn read<T>(&mut self, f: impl FnOnce(&Vault) -> Result<T>) -> Result<T> {
+        f(&self.open()?.0)
+    }
+
+    fn change<T>(&mut self, f: impl FnOnce(&mut Vault) -> Result<T>) -> Result<T> {
+        let (mut vault, master_password) = self.open()?;
+        let result = f(&mut vault)?;
+        vault.save(master_password)?;
+
+        Ok(result)
+    }
+
+    // Remove an entry together with the attachment files only it used
+    fn remove(&mut self, name: &str) -> Result<()> {
+        let (mut vault, master_password) = self.open()?;
+        vault.remove_password(name)?;
+        vault.save(master_password)?;
+        vault.purge_attachments()?;
+
+        Ok(())
+    }
+
+    fn item_name(&self, id: u64) -> Result<String> {
+        match self.items.iter().find(|(_, item)| **item == id) {
+            Some((name, _)) => Ok(name.clone()),
+            None => bail_code!(ErrorCode::NotFound, "The item no longer exists")
+        }
+    }
+
+    fn item_path(&self, name: &str) -> Option<OwnedObjectPath> {
+        self.items.get(name).map(|id| item_path(*id))
+    }
+
+    fn encrypt(&self, session: &ObjectPath, secret: &str) -> Result<Secret, Error> {
+        let Some(key) = self.sessions.get(session.as_str()) else {
+            return Err(Error::NoSession(format!("No session '{}'", session)));
+        };
+
+        let (parameters, value) = match key {
+            None => (Vec::new(), secret.as_bytes().to_vec()),
+            Some(key) => {
+                let iv = rand::random::<[u8; 16]>();
+                let value = cbc::Encryptor::<Aes128>::new(key.into(), &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(secret.as_bytes());
+
+                (iv.to_vec(), value)
+            }
+        };
+
+        Ok(Secret { session: session.clone().into(), parameters, value, content_type: "text/plain".to_string() })
+    }
+
+    // Items are stored as passwords, so secrets have to be text
+    fn decrypt(&self, secret: &Secret) -> Result<String, Error> {
+        let Some(key) = self.sessions.get(secret.session.as_str()) else {
+            return Err(Error::NoSession(format!("No session '{}'", secret.session.as_str())));
+        };
+
+        let value = match key {
+            None => secret.value.clone(),
+            Some(key) => {
+                let Ok(iv) = <[u8; 16]>::try_from(secret.parameters.as_slice()) else {
+                    return Err(fdo::Error::InvalidArgs("The secret has no valid IV".to_string()).into());
+                };
+
+                cbc::Decryptor::<Aes128>::new(key.into(), &iv.into()).decrypt_padded_vec_mut::<Pkcs7>(&secret.value)
+                    .map_err(|_| fdo::Error::InvalidArgs("The secret could not be decrypted".to_string()))?
+            }
+        };
+
+        String::from_utf8(value).map_err(|e| {
+            let mut value = e.into_bytes();
+            value.zeroize();
+
+            fdo::Error::InvalidArgs("rspass only stores secrets that are text".to_string()).into()
+        })
+    }
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        self.forget();
+        self.sessions.values_mut().flatten().for_each(|key| key.zeroize());
+    }
+}
+
+fn item_path(id: u64) -> OwnedObjectPath {
+    ObjectPath::from_string_unchecked(format!("{}/{}", COLLECTION_PATH, id)).into()
+}
+
+fn object_path(path: &'static str) -> OwnedObjectPath {
+    ObjectPath::from_static_str_unchecked(path).into()
+}
+
+// Logins in the folder, which are the items of the collection
+fn item_names(vault: &Vault) -> Vec<String> {
+    vault.list_services().into_iter()
+        .filter(|service| vault.get_entry(service).is_some_and(|entry| entry.kind == EntryKind::Login && entry.folder.as_deref() == Some(FOLDER)))
+        .cloned()
+        .collect()
+}
+
+// Attributes are kept apart from the entry's custom fields
+fn matches(fields: &BTreeMap<String, String>, attributes: &HashMap<String, String>) -> bool {
+    attributes.iter().all(|(key, value)| fields.get(key) == Some(value))
+}
+
+// Items with all of the attributes
+fn search(vault: &Vault, attributes: &HashMap<String, String>) -> Vec<String> {
+    item_names(vault).into_iter()
+        .filter(|name| vault.get_entry(name).is_some_and(|entry| matches(&entry.attributes, attributes)))
+        .collect()
+}
+
+// Give every item an object number and forget the items removed since, also by other
+// commands. Returns the paths of all items and the objects to register and to drop.
+fn refresh(state: &mut State) -> Result<(Vec<OwnedObjectPath>, Vec<u64>, Vec<u64>)> {
+    let mut attributes = state.read(|vault| Ok(item_names(vault).into_iter()
+        .map(|name| {
+            let fields = vault.get_entry(&name).map(|entry| entry.attributes.clone()).unwrap_or_default();
+            (name, fields)
+        })
+        .collect::<BTreeMap<_, _>>()))?;
+
+    let names: Vec<String> = attributes.keys().cloned().collect();
+
+    let removed: Vec<u64> = state.items.iter()
+        .filter(|(name, _)| !names.contains(name))
+        .map(|(_, id)| *id)
+        .collect();
+    state.items.retain(|name, _| names.contains(name));
+
+    let mut added = Vec::new();
+
+    for name in &names {
+        if !state.items.contains_key(name) {
+            state.next_id += 1;
+            state.items.insert(name.clone(), state.next_id);
+            added.push(state.next_id);
+        }
+    }
+
+    state.attributes = state.items.iter()
+        .filter_map(|(name, id)| Some((*id, attributes.remove(name)?)))
+        .collect();
+
+    let paths = names.iter().filter_map(|name| state.item_path(name)).collect();
+
+    Ok((paths, added, removed))
+}
+
+async fn register(server: &ObjectServer, state: &Shared, added: Vec<u64>, removed: Vec<u64>) -> zbus::Result<()> {
+    for id in removed {
+        server.remove::<Item, _>(item_path(id)).await?;
+    }
+
+    for id in added {
+        server.at(item_path(id), Item { id, state: Arc::clone(state) }).await?;
+    }
+
+    Ok(())
+}
+
+// Bring the item objects up to date with the vault. Returns the paths of all items.
+async fn sync(server: &ObjectServer, state: &Shared) -> Result<Vec<OwnedObjectPath>, Error> {
+    let (paths, added, removed) = refresh(&mut lock(state))?;
+    register(server, state, added, removed).await?;
+
+    Ok(paths)
+}
+
+// Answer the client's half of a Diffie-Hellman exchange. Returns our public key and the
+// AES key derived from the shared secret with HKDF-SHA256, as libsecret does.
+fn dh_exchange(client_public: &[u8]) -> Result<(Vec<u8>, [u8; 16])> {
+    let mut private = [0u8; 128];
+    rand::thread_rng().fill_bytes(&mut private);
+
+    let result = dh_answer(client_public, &private);
+    private.zeroize();
+
+    result
+}
+
+fn dh_answer(client_public: &[u8], private: &[u8; 128]) -> Result<(Vec<u8>, [u8; 16])> {
+    let prime = BigUint::parse_bytes(IETF1024_PRIME.as_bytes(), 16).context("Invalid prime")?;
+    let client_public = BigUint::from_bytes_be(client_public);
+
+    if client_public <= BigUint::from(1u8) || client_public >= &prime - 1u8 {
+        bail_code!(ErrorCode::InvalidInput, "Invalid public key");
+    }
+
+    let private_key = BigUint::from_bytes_be(private);
+
+    let public = BigUint::from(2u8).modpow(&private_key, &prime);
+    let shared = client_public.modpow(&private_key, &prime).to_bytes_be();
+
+    // Padded to the length of the prime
+    let mut secret = vec![0u8; 128];
+    secret[128 - shared.len()..].copy_from_slice(&shared);
+
+    let mut key = [0u8; 16];
+    let result = Hkdf::<Sha256>::new(None, &secret).expand(&[], &mut key);
+    secret.zeroize();
+    result.map_err(|_| anyhow::anyhow!("Failed to derive the session key"))?;
+
+    Ok((public.to_bytes_be(), key))
+}
+
+struct Service {
+    state: Shared
+}
+
+#[interface(name = "org.freedesktop.Secret.Service")]
+impl Service {
+    async fn open_session(&self, algorithm: &str, input: OwnedValue, #[zbus(object_server)] server: &ObjectServer) -> Result<(OwnedValue, OwnedObjectPath), Error> {
+        let (output, key) = match algorithm {
+            PLAIN => (Value::from("").try_into()?, None),
+
+            DH_AES => {
+                let client_public = Vec::<u8>::try_from(input)
+                    .map_err(|_| fdo::Error::InvalidArgs("The input has to be the client's public key".to_string()))?;
+                let (public, key) = dh_exchange(&client_public)?;
+
+                (Value::from(public).try_into()?, Some(key))
+            }
+
+            _ => return Err(fdo::Error::NotSupported(format!("Algorithm '{}' is not supported", algorithm)).into())
+        };
+
+        let path = {
+            let mut state = lock(&self.state);
+            state.next_id += 1;
+
+            let path = format!("{}/{}", SESSION_PATH, state.next_id);
+            state.sessions.insert(path.clone(), key);
+            path
+        };
+
+        server.at(path.as_str(), Session { path: path.clone(), state: Arc::clone(&self.state) }).await?;
+
+        Ok((output, ObjectPath::try_from(path)?.into()))
+    }
+
+    // There is only the vault's collection, which any new collection is
+    async fn create_collection(&self, _properties: HashMap<String, OwnedValue>, _alias: &str) -> (OwnedObjectPath, OwnedObjectPath) {
+        (object_path(COLLECTION_PATH), object_path(NO_OBJECT))
+    }
+
+    // While the vault is locked, matches are found among the items seen before it was
+    // locked, as the vault cannot be read. Clients call Unlock for them.
+    async fn search_items(&self, attributes: HashMap<String, String>, #[zbus(object_server)] server: &ObjectServer) -> Result<(Vec<OwnedObjectPath>, Vec<OwnedObjectPath>), Error> {
+        {
+            let state = lock(&self.state);
+
+            if state.master_password.is_none() {
+                let locked = state.attributes.iter()
+                    .filter(|(_, fields)| matches(fields, &attributes))
+                    .map(|(id, _)| item_path(*id))
+                    .collect();
+
+                return Ok((Vec::new(), locked));
+            }
+        }
+
+        sync(server, &self.state).await?;
+
+        let mut state = lock(&self.state);
+        let names = state.read(|vault| Ok(search(vault, &attributes)))?;
+
+        Ok((names.iter().filter_map(|name| state.item_path(name)).collect(), Vec::new()))
+    }
+
+    async fn unlock(&self, objects: Vec<OwnedObjectPath>, #[zbus(object_server)] server: &ObjectServer) -> Result<(Vec<OwnedObjectPath>, OwnedObjectPath), Error> {
+        lock(&self.state).ensure_unlocked()?;
+        sync(server, &self.state).await?;
+
+        Ok((objects, object_path(NO_OBJECT)))
+    }
+
+    async fn lock(&self, objects: Vec<OwnedObjectPath>) -> (Vec<OwnedObjectPath>, OwnedObjectPath) {
+        lock(&self.state).forget();
+
+        (objects, object_path(NO_OBJECT))
+    }
+
+    async fn get_secrets(&self, items: Vec<OwnedObjectPath>, session: ObjectPath<'_>) -> Result<HashMap<OwnedObjectPath, Secret>, Error> {
+        let mut state = lock(&self.state);
+
+        // Paths of other objects are left out
+        let names: Vec<(OwnedObjectPath, String)> = items.into_iter()
+            .filter_map(|path| {
+                let name = state.items.iter().find(|(_, id)| item_path(**id) == path)?.0.clone();
+                Some((path, name))
+            })
+            .collect();
+
+        let mut passwords = state.read(|vault| Ok(names.into_iter()
+            .filter_map(|(path, name)| Some((path, vault.get_password(&name)?.clone())))
+            .collect::<Vec<_>>()))?;
+
+        let secrets = passwords.iter()
+            .map(|(path, password)| Ok((path.clone(), state.encrypt(&session, password)?)))
+            .collect();
+
+        passwords.iter_mut().for_each(|(_, password)| password.zeroize());
+
+        secrets
+    }
+
+    async fn read_alias(&self, name: &str) -> OwnedObjectPath {
+        object_path(if name == "default" { COLLECTION_PATH } else { NO_OBJECT })
+    }
+
+    async fn set_alias(&self, name: &str, collection: ObjectPath<'_>) -> fdo::Result<()> {
+        if name == "default" && collection.as_str() == COLLECTION_PATH {
+            return Ok(());
+        }
+
+        Err(fdo::Error::NotSupported("Only the rspass collection can be the default".to_string()))
+    }
+
+    #[zbus(property)]
+    async fn collections(&self) -> Vec<OwnedObjectPath> {
+        vec![object_path(COLLECTION_PATH)]
+    }
+}
+
+struct Collection {
+    state: Shared
+}
+
+#[interface(name = "org.freedesktop.Secret.Collection")]
+impl Collection {
+    async fn delete(&self) -> fdo::Result<OwnedObjectPath> {
+        Err(fdo::Error::NotSupported(format!("The rspass collection cannot be deleted. Remove the entries in the folder '{}' instead.", FOLDER)))
+    }
+
+    async fn search_items(&self, attributes: HashMap<String, String>, #[zbus(object_server)] server: &ObjectServer) -> Result<Vec<OwnedObjectPath>, Error> {
+        sync(server, &self.state).await?;
+
+        let mut state = lock(&self.state);
+        let names = state.read(|vault| Ok(search(vault, &attributes)))?;
+
+        Ok(names.iter().filter_map(|name| state.item_path(name)).collect())
+    }
+
+    // Items are named after their label, made unique. With `replace` an item with the same
+    // attributes is changed instead.
+    async fn create_item(
+        &self,
+        properties: HashMap<String, OwnedValue>,
+        secret: Secret,
+        replace: bool,
+        #[zbus(object_server)] server: &ObjectServer,
+        #[zbus(connection)] connection: &Connection
+    ) -> Result<(OwnedObjectPath, OwnedObjectPath), Error> {
+        let label = match properties.get(LABEL_PROPERTY) {
+            Some(label) => String::try_from(label.try_clone()?)?,
+            None => String::new()
+        };
+
+        let attributes = match properties.get(ATTRIBUTES_PROPERTY) {
+            Some(attributes) => HashMap::<String, String>::try_from(attributes.try_clone()?)?,
+            None => HashMap::new()
+        };
+
+        let (name, replaced) = {
+            let mut state = lock(&self.state);
+            let mut password = state.decrypt(&secret)?;
+
+            let result = state.change(|vault| {
+                // Same attributes means no others either
+                let existing = replace.then(|| search(vault, &attributes).into_iter()
+                    .find(|name| vault.get_entry(name).is_some_and(|entry| entry.attributes.len() == attributes.len())))
+                    .flatten();
+
+                if let Some(name) = existing {
+                    if vault.get_password(&name) != Some(&password) {
+                        vault.update_password(&name, &password)?;
+                    }
+
+                    return Ok((name, true));
+                }
+
+                let name = unique_name(vault, &label);
+                vault.add_password(&name, &password)?;
+                vault.set_details(&name, None, Some(FOLDER))?;
+
+                vault.set_attributes(&name, attributes.clone().into_iter().collect())?;
+
+                Ok((name, false))
+            });
+
+            password.zeroize();
+            result?
+        };
+
+        sync(server, &self.state).await?;
+
+        let Some(path) = lock(&self.state).item_path(&name) else {
+            return Err(Error::NoSuchObject(format!("'{}' was not stored in the folder '{}'", name, FOLDER)));
+        };
+
+        let emitter = SignalEmitter::new(connection, COLLECTION_PATH)?;
+
+        if replaced {
+            Collection::item_changed(&emitter, path.as_ref()).await?;
+        } else {
+            Collection::item_created(&emitter, path.as_ref()).await?;
+        }
+
+        Ok((path, object_path(NO_OBJECT)))
+    }
+
+    #[zbus(property)]
+    async fn items(&self, #[zbus(connection)] connection: &Connection) -> fdo::Result<Vec<OwnedObjectPath>> {
+        if lock(&self.state).master_password.is_none() {
+            return Ok(Vec::new());
+        }
+
+        let (paths, added, removed) = refresh(&mut lock(&self.state)).map_err(failed)?;
+
+        // Objects cannot be added while a property is read, so they are added right after
+        let server = connection.object_server().clone();
+        let state = Arc::clone(&self.state);
+
+        connection.executor().spawn(async move {
+            let _ = register(&server, &state, added, removed).await;
+        }, "register items").detach();
+
+        Ok(paths)
+    }
+
+    #[zbus(property)]
+    async fn label(&self) -> String {
+        format!("rspass vault '{}'", crate::vault::selected_vault())
+    }
+
+    #[zbus(property)]
+    async fn locked(&self) -> bool {
+        lock(&self.state).master_password.is_none()
+    }
+
+    #[zbus(property)]
+    async fn created(&self) -> u64 {
+        0
+    }
+
+    #[zbus(property)]
+    async fn modified(&self) -> u64 {
+        0
+    }
+
+    #[zbus(signal)]
+    async fn item_created(emitter: &SignalEmitter<'_>, item: ObjectPath<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn item_deleted(emitter: &SignalEmitter<'_>, item: ObjectPath<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn item_changed(emitter: &SignalEmitter<'_>, item: ObjectPath<'_>) -> zbus::Result<()>;
+}
+
+// The label, or the label with a number when an entry already has that name
+fn unique_name(vault: &Vault, label: &str) -> String {
+    let label = match label.trim() {
+        "" => "Unnamed secret",
+        label => label
+    };
+
+    (1..)
+        .map(|n| if n == 1 { label.to_string() } else { format!("{} ({})", label, n) })
+        .find(|name| vault.get_entry(name).is_none())
+        .unwrap_or_default()
+}
+
+struct Item {
+    id: u64,
+    state: Shared
+}
+
+#[interface(name = "org.freedesktop.Secret.Item")]
+impl Item {
+    async fn delete(&self, #[zbus(object_server)] server: &ObjectServer, #[zbus(connection)] connection: &Connection) -> Result<OwnedObjectPath, Error> {
+        {
+            let mut state = lock(&self.state);
+            let name = state.item_name(self.id)?;
+
+            state.remove(&name)?;
+        }
+
+        sync(server, &self.state).await?;
+
+        let emitter = SignalEmitter::new(connection, COLLECTION_PATH)?;
+        Collection::item_deleted(&emitter, item_path(self.id).as_ref()).await?;
+
+        Ok(object_path(NO_OBJECT))
+    }
+
+    async fn get_secret(&self, session: ObjectPath<'_>) -> Result<(Secret,), Error> {
+        let mut state = lock(&self.state);
+        let name = state.item_name(self.id)?;
+        let mut password = state.read(|vault| Ok(vault.get_password(&name).cloned().unwrap_or_default()))?;
+
+        let secret = state.encrypt(&session, &password);
+        password.zeroize();
+
+        Ok((secret?,))
+    }
+
+    async fn set_secret(&self, secret: Secret) -> Result<(), Error> {
+        let mut state = lock(&self.state);
+        let name = state.item_name(self.id)?;
+        let mut password = state.decrypt(&secret)?;
+
+        let result = state.change(|vault| match vault.get_password(&name) {
+            Some(current) if *current == password => Ok(()),
+            _ => vault.update_password(&name, &password)
+        });
+        password.zeroize();
+
+        Ok(result?)
+    }
+
+    #[zbus(property)]
+    async fn locked(&self) -> bool {
+        lock(&self.state).master_password.is_none()
+    }
+
+    #[zbus(property)]
+    async fn attributes(&self) -> fdo::Result<HashMap<String, String>> {
+        let mut state = lock(&self.state);
+        let name = state.item_name(self.id).map_err(failed)?;
+
+        state.read(|vault| Ok(vault.get_entry(&name).map(|entry| entry.attributes.clone().into_iter().collect()).unwrap_or_default()))
+            .map_err(failed)
+    }
+
+    #[zbus(property)]
+    async fn set_attributes(&mut self, attributes: HashMap<String, String>) -> fdo::Result<()> {
+        let mut state = lock(&self.state);
+        let name = state.item_name(self.id).map_err(failed)?;
+        let attributes: BTreeMap<String, String> = attributes.into_iter().collect();
+
+        state.change(|vault| vault.set_attributes(&name, attributes.clone())).map_err(failed)?;
+
+        state.attributes.insert(self.id, attributes);
+
+        Ok(())
+    }
+
+    #[zbus(property)]
+    async fn label(&self) -> fdo::Result<String> {
+        lock(&self.state).item_name(self.id).map_err(failed)
+    }
+
+    // Renames the entry. The item keeps its object path.
+    #[zbus(property)]
+    async fn set_label(&mut self, label: String) -> fdo::Result<()> {
+        let mut state = lock(&self.state);
+        let name = state.item_name(self.id).map_err(failed)?;
+
+        if label.trim().is_empty() || label == name {
+            return Ok(());
+        }
+
+        let label = label.trim().to_string();
+        state.change(|vault| vault.move_entry(&name, &label, false)).map_err(failed)?;
+
+        state.items.remove(&name);
+        state.items.insert(label, self.id);
+
+        Ok(())
+    }
+
+    #[zbus(property)]
+    async fn created(&self) -> fdo::Result<u64> {
+        self.timestamp(|entry| entry.created)
+    }
+
+    #[zbus(property)]
+    async fn modified(&self) -> fdo::Result<u64> {
+        self.timestamp(|entry| entry.modified)
+    }
+}
+
+impl Item {
+    fn timestamp(&self, field: impl Fn(&crate::vault::Entry) -> Option<chrono::DateTime<chrono::Utc>>) -> fdo::Result<u64> {
+        let mut state = lock(&self.state);
+        let name = state.item_name(self.id).map_err(failed)?;
+
+        state.read(|vault| Ok(vault.get_entry(&name).and_then(&field).map_or(0, |time| time.timestamp().max(0) as u64)))
+            .map_err(failed)
+    }
+}
+
+struct Session {
+    path: String,
+    state: Shared
+}
+
+#[interface(name = "org.freedesktop.Secret.Session")]
+impl Session {
+    async fn close(&self, #[zbus(object_server)] server: &ObjectServer) -> fdo::Result<()> {
+        if let Some(Some(mut key)) = lock(&self.state).sessions.remove(&self.path) {
+            key.zeroize();
+        }
+
+        server.remove::<Session, _>(self.path.as_str()).await?;
+
+        Ok(())
+    }
+}
+
+// Serve the Secret Service API on the session bus, or the bus at `address`, until the bus
+// goes away. `unlock` gives the master password without asking, when the vault is locked.
+pub fn serve(address: Option<&str>, master_password: Option<String>, unlock: impl FnMut() -> Option<String> + Send + 'static, ready: impl FnOnce() -> Result<()>) -> Result<()> {
+    let state = Arc::new(Mutex::new(State {
+        master_password,
+        unlock: Box::new(unlock),
+        sessions: HashMap::new(),
+        items: BTreeMap::new(),
+        attributes: BTreeMap::new(),
+        next_id: 0
+    }));
+
+    let builder = match address {
+        Some(address) => zbus::blocking::connection::Builder::address(address),
+        None => zbus::blocking::connection::Builder::session()
+    }.context("Failed to find the D-Bus session bus")?;
+
+    let connection = builder
+        .serve_at(SERVICE_PATH, Service { state: Arc::clone(&state) })?
+        .serve_at(COLLECTION_PATH, Collection { state: Arc::clone(&state) })?
+        .serve_at(DEFAULT_ALIAS_PATH, Collection { state: Arc::clone(&state) })?
+        .build()
+        .context("Failed to connect to the D-Bus session bus")?;
+
+    // Waiting in line for the name would leave clients talking to the other service
+    match connection.request_name_with_flags(BUS_NAME, RequestNameFlags::DoNotQueue.into()) {
+        Ok(_) => {}
+        Err(zbus::Error::NameTaken) => bail_code!(ErrorCode::AlreadyExists, "{} is already served by another program, such as gnome-keyring", BUS_NAME),
+        Err(e) => return Err(anyhow::Error::new(e).context(format!("Failed to claim {}", BUS_NAME)))
+    }
+
+    if lock(&state).master_password.is_some() {
+        let (_, added, removed) = refresh(&mut lock(&state))?;
+        zbus::block_on(register(connection.object_server().inner(), &state, added, removed))?;
+    }
+
+    ready()?;
+
+    // Ends when the connection to the bus is closed
+    for message in zbus::blocking::MessageIterator::from(&connection) {
+        if message.is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MASTER_PASSWORD};
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use zbus::blocking::proxy::Builder;
+    use zbus::blocking::Proxy;
+    use zbus::proxy::CacheProperties;
+
+    // Known answers, worked out with Python's pow() and the cryptography package
+    const CLIENT_PUBLIC: &str = "e417ebaafb34f62d945f65389f5565ba1281af06215d9f1ffaca1334b174a4065080dae67a401b437c7d2cffb45ef47255209b61eec8b835f167dab8d7000375daea618311d0ae41323bd3d49acf105234d17003b357c1e59e5ecc3c9c32de57f32f422a1f235c7f449de4d0ce548f96ae59aeb680b07250a0ab353652358c05";
+    const SERVICE_PUBLIC: &str = "16b7fff8d04e6a9fe2a07659d1efd29c34096cf2562145a88c20ad5421db892687e36b5803a2d5f758ab3303fd86c21e177e6f1f1996d473376d49c63a29c2d22ffbbbcde1d5c13481f96b7d30916ff18c2911563acabc834dc98fefb24a9c753cf519ef27e456269f9f72b4410a7e71a9a896a06d871d4a4c620d3ccfc66da1";
+    const SESSION_KEY: &str = "28484db27199aab1ad8485150659f8ca";
+    const CIPHERTEXT: &str = "798e18ebd89c192f49464c54639eadce"; // "hunter2" with IV 00..0f
+
+    const CLIENT_PRIVATE: [u8; 128] = [0xa5; 128];
+    const SERVICE_PRIVATE: [u8; 128] = [0x5a; 128];
+
+    fn state(sessions: HashMap<String, Option<[u8; 16]>>) -> State {
+        State { master_password: None, unlock: Box::new(|| None), sessions, items: BTreeMap::new(), attributes: BTreeMap::new(), next_id: 0 }
+    }
+
+    fn secret(session: &str, parameters: Vec<u8>, value: Vec<u8>) -> Secret {
+        Secret { session: ObjectPath::try_from(session).unwrap().into(), parameters, value, content_type: "text/plain".to_string() }
+    }
+
+    #[test]
+    fn dh_known_answer() {
+        let (public, key) = dh_answer(&hex::decode(CLIENT_PUBLIC).unwrap(), &SERVICE_PRIVATE).unwrap();
+
+        assert_eq!(hex::encode(public), SERVICE_PUBLIC);
+        assert_eq!(hex::encode(key), SESSION_KEY);
+
+        // The client comes to the same key from its side
+        let (public, key) = dh_answer(&hex::decode(SERVICE_PUBLIC).unwrap(), &CLIENT_PRIVATE).unwrap();
+
+        assert_eq!(hex::encode(public), CLIENT_PUBLIC);
+        assert_eq!(hex::encode(key), SESSION_KEY);
+    }
+
+    #[test]
+    fn dh_rejects_weak_keys() {
+        let prime = BigUint::parse_bytes(IETF1024_PRIME.as_bytes(), 16).unwrap();
+
+        for public in [BigUint::from(0u8), BigUint::from(1u8), &prime - 1u8, prime.clone()] {
+            assert!(dh_answer(&public.to_bytes_be(), &SERVICE_PRIVATE).is_err());
+        }
+    }
+
+    #[test]
+    fn aes_known_answer() {
+        let key: [u8; 16] = hex::decode(SESSION_KEY).unwrap().try_into().unwrap();
+        let state = state(HashMap::from([("/s/1".to_string(), Some(key)), ("/s/2".to_string(), None)]));
+        let iv: Vec<u8> = (0..16).collect();
+
+        let password = state.decrypt(&secret("/s/1", iv.clone(), hex::decode(CIPHERTEXT).unwrap())).unwrap();
+        assert_eq!(password, "hunter2");
+
+        // New IV for every secret
+        let first = state.encrypt(&ObjectPath::try_from("/s/1").unwrap(), "hunter2").unwrap();
+        let second = state.encrypt(&ObjectPath::try_from("/s/1").unwrap(), "hunter2").unwrap();
+        assert_ne!(first.parameters, second.parameters);
+        assert_eq!(state.decrypt(&first).unwrap(), "hunter2");
+
+        let plain = state.encrypt(&ObjectPath::try_from("/s/2").unwrap(), "hunter2").unwrap();
+        assert!(plain.parameters.is_empty());
+        assert_eq!(plain.value, b"hunter2");
+
+        // Wrong key, missing IV, unknown session, and secrets that are not text
+        assert!(state.decrypt(&secret("/s/1", iv.clone(), vec![0; 16])).is_err());
+        assert!(state.decrypt(&secret("/s/1", Vec::new(), hex::decode(CIPHERTEXT).unwrap())).is_err());
+        assert!(matches!(state.decrypt(&secret("/s/3", Vec::new(), b"hunter2".to_vec())), Err(Error::NoSession(_))));
+        assert!(state.decrypt(&secret("/s/2", Vec::new(), vec![0xff, 0xfe])).is_err());
+    }
+
+    #[test]
+    fn attributes_apart_from_fields() {
+        let mut vault = Vault::in_memory();
+        vault.add_password("Mail", "hunter2").unwrap();
+        vault.set_details("Mail", None, Some(FOLDER)).unwrap();
+        vault.set_attributes("Mail", BTreeMap::from([("user".to_string(), "alice".to_string())])).unwrap();
+        vault.set_custom_field("Mail", "user", "bob").unwrap();
+
+        let attributes = |user: &str| HashMap::from([("user".to_string(), user.to_string())]);
+        assert_eq!(search(&vault, &attributes("alice")), vec!["Mail".to_string()]);
+        assert!(search(&vault, &attributes("bob")).is_empty());
+        assert_eq!(vault.get_entry("Mail").unwrap().custom_fields.get("user").map(String::as_str), Some("bob"));
+
+        assert!(vault.set_attributes("Missing", BTreeMap::new()).is_err());
+    }
+
+    // A private session bus, stopped when dropped
+    struct Bus {
+        daemon: Child,
+        address: String
+    }
+
+    impl Bus {
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address=1"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("dbus-daemon is not installed");
+
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address).unwrap();
+
+            Bus { daemon, address: address.trim().to_string() }
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    fn proxy<'a>(connection: &zbus::blocking::Connection, path: &'a str, interface: &'a str) -> Proxy<'a> {
+        Builder::new(connection)
+            .destination(BUS_NAME).unwrap()
+            .path(path).unwrap()
+            .interface(interface).unwrap()
+            .cache_properties(CacheProperties::No)
+            .build()
+            .unwrap()
+    }
+
+    fn error_name(error: zbus::Error) -> String {
+        match error {
+            zbus::Error::MethodError(name, _, _) => name.to_string(),
+            e => panic!("unexpected error {}", e)
+        }
+    }
+
+    // Needs dbus-daemon
+    #[test]
+    #[ignore]
+    fn private_session_bus() {
+        let _vault = testing::fresh_vault();
+        let bus = Bus::start();
+
+        testing::change(|vault| {
+            vault.add_password("Other login", "not an item").unwrap();
+        });
+
+        let (ready, started) = std::sync::mpsc::channel();
+        let address = bus.address.clone();
+
+        let daemon = std::thread::spawn(move || {
+            serve(Some(&address), Some(MASTER_PASSWORD.to_string()), || Some(MASTER_PASSWORD.to_string()), || Ok(ready.send(())?))
+        });
+        started.recv().unwrap();
+
+        let connection = zbus::blocking::connection::Builder::address(bus.address.as_str()).unwrap().build().unwrap();
+        let service = proxy(&connection, SERVICE_PATH, "org.freedesktop.Secret.Service");
+        let collection = proxy(&connection, DEFAULT_ALIAS_PATH, "org.freedesktop.Secret.Collection");
+
+        // Store a secret over a plain session
+        let (_, plain): (OwnedValue, OwnedObjectPath) = service.call("OpenSession", &(PLAIN, Value::from(""))).unwrap();
+
+        let attributes = HashMap::from([("service", "smtp.example.com"), ("user", "alice")]);
+        let properties = HashMap::from([(LABEL_PROPERTY, Value::from("Mail")), (ATTRIBUTES_PROPERTY, Value::from(attributes.clone()))]);
+        let (item, _): (OwnedObjectPath, OwnedObjectPath) = collection.call("CreateItem", &(properties, secret(plain.as_str(), Vec::new(), b"hunter2".to_vec()), false)).unwrap();
+
+        let vault = Vault::load(MASTER_PASSWORD).unwrap();
+        let entry = vault.get_entry("Mail").unwrap();
+        assert_eq!(entry.password, "hunter2");
+        assert_eq!(entry.folder.as_deref(), Some(FOLDER));
+        assert_eq!(entry.attributes.get("user").map(String::as_str), Some("alice"));
+        assert!(entry.custom_fields.is_empty());
+
+        let items: Vec<OwnedObjectPath> = collection.get_property("Items").unwrap();
+        assert_eq!(items, vec![item.clone()]);
+
+        let (unlocked, locked): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) = service.call("SearchItems", &(HashMap::from([("user", "alice")]),)).unwrap();
+        assert_eq!((unlocked, locked), (vec![item.clone()], Vec::new()));
+
+        let (unlocked, _): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) = service.call("SearchItems", &(HashMap::from([("user", "bob")]),)).unwrap();
+        assert!(unlocked.is_empty());
+
+        // Read it back over an encrypted session
+        let client_public = dh_answer(&hex::decode(SERVICE_PUBLIC).unwrap(), &CLIENT_PRIVATE).unwrap().0;
+        let (output, encrypted): (OwnedValue, OwnedObjectPath) = service.call("OpenSession", &(DH_AES, Value::from(client_public))).unwrap();
+        let (_, key) = dh_answer(&Vec::<u8>::try_from(output).unwrap(), &CLIENT_PRIVATE).unwrap();
+
+        let item_proxy = proxy(&connection, item.as_str(), "org.freedesktop.Secret.Item");
+        let (secret,): (Secret,) = item_proxy.call("GetSecret", &(encrypted.clone(),)).unwrap();
+        let iv: [u8; 16] = secret.parameters.as_slice().try_into().unwrap();
+        let value = cbc::Decryptor::<Aes128>::new(&key.into(), &iv.into()).decrypt_padded_vec_mut::<Pkcs7>(&secret.value).unwrap();
+        assert_eq!(value, b"hunter2");
+
+        let label: String = item_proxy.get_property("Label").unwrap();
+        assert_eq!(label, "Mail");
+
+        let e = item_proxy.call_method("GetSecret", &(ObjectPath::try_from("/org/freedesktop/secrets/session/999").unwrap(),)).unwrap_err();
+        assert_eq!(error_name(e), "org.freedesktop.Secret.Error.NoSession");
+
+        // Locked items are still found, and listed as locked
+        let _: (Vec<OwnedObjectPath>, OwnedObjectPath) = service.call("Lock", &(vec![collection.path().to_owned()],)).unwrap();
+        assert!(collection.get_property::<bool>("Locked").unwrap());
+
+        let (unlocked, locked): (Vec<OwnedObjectPath>, Vec<OwnedObjectPath>) = service.call("SearchItems", &(HashMap::from([("user", "alice")]),)).unwrap();
+        assert_eq!((unlocked, locked), (Vec::new(), vec![item.clone()]));
+
+        let e = service.call_method("GetSecrets", &(vec![item.clone()], plain.clone())).unwrap_err();
+        assert_eq!(error_name(e), "org.freedesktop.Secret.Error.IsLocked");
+
+        let (unlocked, _): (Vec<OwnedObjectPath>, OwnedObjectPath) = service.call("Unlock", &(vec![item.clone()],)).unwrap();
+        assert_eq!(unlocked, vec![item.clone()]);
+
+        let secrets: HashMap<OwnedObjectPath, Secret> = service.call("GetSecrets", &(vec![item.clone()], plain.clone())).unwrap();
+        assert_eq!(secrets[&item].value, b"hunter2");
+
+        // Deleting the item removes the entry and the attachment files only it had
+        testing::change(|vault| vault.add_attachment("Mail", "notes.txt", b"attached", 1024).unwrap());
+        assert_eq!(testing::attachment_files(), 1);
+
+        let _: OwnedObjectPath = item_proxy.call("Delete", &()).unwrap();
+
+        let vault = Vault::load(MASTER_PASSWORD).unwrap();
+        assert!(vault.get_entry("Mail").is_none());
+        assert!(vault.get_entry("Other login").is_some());
+        assert_eq!(testing::attachment_files(), 0);
+
+        let items: Vec<OwnedObjectPath> = collection.get_property("Items").unwrap();
+        assert!(items.is_empty());
+
+        // The service ends with the bus
+        drop(connection);
+        drop(bus);
+        assert!(daemon.join().unwrap().is_ok());
+    }
+}
@@ -0,0 +1,633 @@
+use crate::error::{bail_code, CodedError, ErrorCode};
+use crate::listing::{self, Filter, SortKey};
+use crate::manifest::Manifest;
+use crate::search::{self, Matcher, SearchMode};
+use crate::vault::{EntryKind, Vault};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use clap::ValueEnum;
+use dirs::home_dir;
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Method, Request, Response, Server};
+use zeroize::Zeroize;
+
+// Largest request body accepted
+const MAX_BODY: usize = 1024 * 1024;
+
+// Where the server listens. Only local clients can connect either way.
+pub enum Address {
+    Port(u16), // On 127.0.0.1
+    Socket(PathBuf)
+}
+
+pub struct Options {
+    pub address: Address,
+    pub token_file: PathBuf,
+    pub audit_log: PathBuf,
+    pub timeout: u64 // Minutes until an unlocked vault is locked again, 0 for never
+}
+
+pub fn default_token_file(vault: &str) -> Result<PathBuf> {
+    let home_dir = home_dir().context("Could not find home directory")?;
+
+    Ok(home_dir.join(".rspass").join(format!("serve-{}.token", vault)))
+}
+
+pub fn default_audit_log(vault: &str) -> Result<PathBuf> {
+    let home_dir = home_dir().context("Could not find home directory")?;
+
+    Ok(home_dir.join(".rspass").join(format!("serve-{}.log", vault)))
+}
+
+// Master password while the vault is unlocked. The vault itself is read for every
+// request, so changes made with other commands show up right away.
+struct Session {
+    master_password: Option<String>,
+    locks_at: Option<Instant>,
+    timeout: u64
+}
+
+impl Session {
+    fn unlock(&mut self, master_password: String, minutes: u64) {
+        self.lock();
+        self.master_password = Some(master_password);
+        self.locks_at = (minutes > 0).then(|| Instant::now() + Duration::from_secs(minutes * 60));
+    }
+
+    fn lock(&mut self) {
+        self.master_password.zeroize();
+        self.master_password = None;
+        self.locks_at = None;
+    }
+
+    fn expire(&mut self) {
+        if self.locks_at.is_some_and(|locks_at| Instant::now() >= locks_at) {
+            self.lock();
+        }
+    }
+
+    fn open(&mut self) -> Result<(Vault, &str)> {
+        self.expire();
+
+        let Some(master_password) = self.master_password.as_deref() else {
+            bail_code!(ErrorCode::VaultLocked, "The vault is locked. POST the master password to /unlock first.");
+        };
+
+        Ok((Vault::load(master_password)?, master_password))
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.lock();
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UnlockRequest {
+    master_password: String,
+    timeout: Option<u64> // Minutes, defaults to the server's --timeout
+}
+
+impl Drop for UnlockRequest {
+    fn drop(&mut self) {
+        self.master_password.zeroize();
+    }
+}
+
+// Removes the server's socket when it stops
+struct SocketFile(Option<PathBuf>);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.0 {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// A handled request, for the audit log
+struct Outcome {
+    response: Value,
+    status: u16,
+    service: Option<String>
+}
+
+// Serve the API until the process is stopped. A new token is written to the token file
+// on every start, so clients from an earlier run are locked out. `ready` gets the address
+// clients connect to.
+pub fn serve(options: &Options, master_password: Option<String>, ready: impl FnOnce(&str) -> Result<()>) -> Result<()> {
+    let (server, address) = match &options.address {
+        Address::Port(port) => {
+            let listener = std::net::TcpListener::bind(("127.0.0.1", *port))
+                .with_context(|| format!("Failed to listen on 127.0.0.1:{}", port))?;
+            let address = format!("http://{}", listener.local_addr()?);
+
+            (Server::from_listener(listener, None), address)
+        }
+
+        #[cfg(unix)]
+        Address::Socket(path) => {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+
+            // A socket left behind by a server that is gone is replaced, a live one is not
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                bail_code!(ErrorCode::AlreadyExists, "Another server is listening on '{}'", path.display());
+            }
+
+            (Server::from_listener(crate::agent::bind_private(path)?, None), format!("unix:{}", path.display()))
+        }
+
+        #[cfg(not(unix))]
+        Address::Socket(_) => bail_code!(ErrorCode::Usage, "Unix sockets are only available on Unix systems. Use --port instead."),
+    };
+
+    let _socket = SocketFile(match &options.address {
+        Address::Socket(path) => Some(path.clone()),
+        Address::Port(_) => None
+    });
+
+    let server = server.map_err(|e| anyhow::anyhow!("Failed to start the server: {}", e))?;
+
+    let mut token = hex::encode(rand::random::<[u8; 32]>());
+    let written = write_token(&options.token_file, &token);
+
+    if let Err(e) = written {
+        token.zeroize();
+        return Err(e);
+    }
+
+    let mut audit_log = open_audit_log(&options.audit_log)?;
+    let mut session = Session { master_password: None, locks_at: None, timeout: options.timeout };
+
+    if let Some(master_password) = master_password {
+        session.unlock(master_password, options.timeout);
+    }
+
+    ready(&address)?;
+
+    for mut request in server.incoming_requests() {
+        let (response, status) = answer(&mut request, &token, &mut session, &mut audit_log)?;
+
+        let mut body = response.to_string();
+        let _ = request.respond(Response::from_string(body.as_str())
+            .with_status_code(status)
+            .with_header(header("Content-Type", "application/json"))
+            .with_header(header("Cache-Control", "no-store")));
+        body.zeroize();
+    }
+
+    token.zeroize();
+
+    Ok(())
+}
+
+// Check and handle a request and record it in the audit log. Gives the response body
+// and status.
+fn answer(request: &mut Request, token: &str, session: &mut Session, audit_log: &mut impl Write) -> Result<(Value, u16)> {
+    let client = request.remote_addr().map(|addr| addr.to_string()).unwrap_or_else(|| "unix".to_string());
+    let method = request.method().to_string();
+    let path = request.url().split('?').next().unwrap_or_default().to_string();
+
+    let outcome = match authorize(request, token) {
+        Ok(()) => handle(request, session),
+        Err(e) => Err(e)
+    };
+
+    let (response, status, service, code) = match outcome {
+        Ok(outcome) => (outcome.response, outcome.status, outcome.service, None),
+        Err(e) => {
+            let code = ErrorCode::of(&e);
+            let body = json!({ "error": { "code": code, "message": format!("{:#}", e) } });
+
+            (body, error_status(&e), path_service(&path), Some(code))
+        }
+    };
+
+    // Paths can name entries, but bodies and queries hold secrets and search terms
+    let record = json!({
+        "time": Utc::now().to_rfc3339(),
+        "client": client,
+        "method": method,
+        "path": path,
+        "service": service,
+        "status": status,
+        "error": code
+    });
+
+    writeln!(audit_log, "{}", record).context("Failed to write the audit log")?;
+
+    Ok((response, status))
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("valid header")
+}
+
+fn write_token(path: &Path, token: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    crate::write_private_file(path, format!("{}\n", token).as_bytes(), true)
+}
+
+// Appends to the log, which only the current user can read
+fn open_audit_log(path: &Path) -> Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.create(true).append(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path).with_context(|| format!("Failed to open the audit log '{}'", path.display()))
+}
+
+// Requests need the token as a bearer token. Web pages can reach 127.0.0.1 too, so
+// requests a browser marks with an Origin are refused.
+fn authorize(request: &Request, token: &str) -> Result<()> {
+    let value = |name: &'static str| request.headers().iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str());
+
+    if value("Origin").is_some() {
+        return Err(CodedError::new(ErrorCode::Unauthorized, "Requests from web pages are not accepted".to_string()).into());
+    }
+
+    let given = value("Authorization").and_then(|value| value.strip_prefix("Bearer ")).unwrap_or_default();
+
+    if !constant_time_eq(given.trim().as_bytes(), token.as_bytes()) {
+        return Err(CodedError::new(ErrorCode::Unauthorized, "Missing or wrong bearer token".to_string()).into());
+    }
+
+    Ok(())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |difference, (x, y)| difference | (x ^ y)) == 0
+}
+
+fn segments(path: &str) -> Vec<String> {
+    path.trim_matches('/').split('/')
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+        .collect()
+}
+
+// Entry named in the path, such as "github" in /entries/github
+fn path_service(path: &str) -> Option<String> {
+    match segments(path).as_slice() {
+        [entries, service] if entries == "entries" => Some(service.clone()),
+        _ => None
+    }
+}
+
+fn handle(request: &mut Request, session: &mut Session) -> Result<Outcome> {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let query: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+    let param = |name: &str| query.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+
+    let segments = segments(path);
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let method = request.method().clone();
+
+    let ok = |response: Value| Ok(Outcome { response, status: 200, service: None });
+
+    match (&method, segments.as_slice()) {
+        (Method::Get, ["status"]) => {
+            session.expire();
+
+            ok(json!({ "locked": session.master_password.is_none() }))
+        }
+
+        (Method::Post, ["unlock"]) => {
+            let mut body = read_body(request)?;
+            let unlock = serde_json::from_slice::<UnlockRequest>(&body);
+            body.zeroize();
+
+            let unlock = unlock.map_err(|e| CodedError::new(ErrorCode::InvalidInput, format!("Invalid request: {}", e)))?;
+
+            // Checks the password before keeping it
+            Vault::load(&unlock.master_password)?;
+
+            let timeout = unlock.timeout.unwrap_or(session.timeout);
+            session.unlock(unlock.master_password.clone(), timeout);
+
+            ok(json!({ "locked": false, "timeout": timeout }))
+        }
+
+        (Method::Post, ["lock"]) => {
+            session.lock();
+
+            ok(json!({ "locked": true }))
+        }
+
+        (Method::Get, ["entries"]) => {
+            let kind = match param("type") {
+                Some(kind) => Some(EntryKind::from_str(kind, true).map_err(|_| CodedError::new(ErrorCode::InvalidInput, format!("Unknown entry type '{}'", kind)))?),
+                None => None
+            };
+
+            let filter = Filter {
+                tags: query.iter().filter(|(key, _)| key == "tag").map(|(_, value)| value.clone()).collect(),
+                folder: param("folder").map(str::to_string),
+                kind
+            };
+
+            let (vault, _) = session.open()?;
+            let now = Utc::now();
+
+            let entries: Vec<_> = listing::select(&vault, &filter, SortKey::Name, false).into_iter()
+                .map(|(service, entry)| listing::summary_json(service, entry, now))
+                .collect();
+
+            ok(json!({ "entries": entries }))
+        }
+
+        (Method::Get, ["search"]) => {
+            let Some(text) = param("q") else {
+                bail_code!(ErrorCode::InvalidInput, "Give the query as ?q=");
+            };
+
+            let mode = match param("mode") {
+                Some(mode) => SearchMode::from_str(mode, true).map_err(|_| CodedError::new(ErrorCode::InvalidInput, format!("Unknown search mode '{}'", mode)))?,
+                None => SearchMode::default()
+            };
+
+            let matcher = Matcher::new(text, mode, param("case_sensitive").is_some_and(|value| value == "true"))?;
+            let (vault, _) = session.open()?;
+
+            let results: Vec<_> = search::search(&vault, &matcher).iter()
+                .map(|hit| json!({ "service": hit.service, "field": hit.field, "score": hit.score }))
+                .collect();
+
+            ok(json!({ "query": text, "results": results }))
+        }
+
+        (Method::Get, ["entries", service]) => {
+            let (vault, _) = session.open()?;
+
+            let Some(entry) = vault.get_entry(service) else {
+                bail_code!(ErrorCode::NotFound, "No password found for '{}'", service);
+            };
+
+            Ok(Outcome { response: listing::details_json(service, entry), status: 200, service: Some(service.to_string()) })
+        }
+
+        (Method::Post, ["entries"]) => {
+            let values = read_json(request)?;
+            let service = values.get("service").and_then(Value::as_str).map(str::to_string);
+
+            apply(session, "add", values, service, 201)
+        }
+
+        (Method::Put | Method::Patch, ["entries", service]) => {
+            let mut values = read_json(request)?;
+
+            if !values.is_object() {
+                bail_code!(ErrorCode::InvalidInput, "The body must be a JSON object");
+            }
+
+            match values.get("service").and_then(Value::as_str) {
+                Some(name) if name != *service => bail_code!(ErrorCode::InvalidInput, "The body names '{}' but the path names '{}'", name, service),
+                _ => values["service"] = json!(service)
+            }
+
+            apply(session, "update", values, Some(service.to_string()), 200)
+        }
+
+        (Method::Delete, ["entries", service]) => {
+            let (mut vault, master_password) = session.open()?;
+
+            if !vault.remove_password(service)? {
+                bail_code!(ErrorCode::NotFound, "No password found for '{}'", service);
+            }
+
+            vault.save(master_password)?;
+            vault.purge_attachments()?;
+
+            Ok(Outcome { response: json!({ "service": service, "removed": true }), status: 200, service: Some(service.to_string()) })
+        }
+
+        _ => bail_code!(ErrorCode::NotFound, "No endpoint {} {}", method, path)
+    }
+}
+
+// Add or update an entry as a manifest operation would, and answer with the entry,
+// including a generated password
+fn apply(session: &mut Session, op: &str, mut values: Value, service: Option<String>, status: u16) -> Result<Outcome> {
+    values["op"] = json!(op);
+
+    let manifest = serde_json::from_value::<Manifest>(json!({ "operations": [values] }))
+        .map_err(|e| CodedError::new(ErrorCode::InvalidInput, format!("Invalid entry: {}", e)))?;
+
+    let (mut vault, master_password) = session.open()?;
+    manifest.apply(&mut vault)?;
+    vault.save(master_password)?;
+
+    let service = service.unwrap_or_default();
+    let entry = vault.get_entry(&service).context("Entry missing after saving")?;
+
+    Ok(Outcome { response: listing::details_json(&service, entry), status, service: Some(service) })
+}
+
+fn read_body(request: &mut Request) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    request.as_reader().take(MAX_BODY as u64 + 1).read_to_end(&mut body)?;
+
+    if body.len() > MAX_BODY {
+        body.zeroize();
+        bail_code!(ErrorCode::InvalidInput, "Request body is larger than {} bytes", MAX_BODY);
+    }
+
+    Ok(body)
+}
+
+fn read_json(request: &mut Request) -> Result<Value> {
+    let mut body = read_body(request)?;
+    let value = serde_json::from_slice(&body);
+    body.zeroize();
+
+    value.map_err(|e| CodedError::new(ErrorCode::InvalidInput, format!("Body is not valid JSON: {}", e)).into())
+}
+
+fn error_status(e: &anyhow::Error) -> u16 {
+    match ErrorCode::of(e) {
+        ErrorCode::Usage | ErrorCode::InvalidInput => 400,
+        ErrorCode::Unauthorized => 401,
+        ErrorCode::InvalidMasterPassword => 403,
+        ErrorCode::NotFound | ErrorCode::NoVault => 404,
+        ErrorCode::AlreadyExists | ErrorCode::VaultExists => 409,
+        ErrorCode::VaultLocked => 423,
+        _ => 500
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MASTER_PASSWORD};
+    use tiny_http::TestRequest;
+
+    const TOKEN: &str = "0123456789abcdef";
+
+    fn request(method: Method, path: &str, token: Option<&str>, body: &'static str) -> Request {
+        let mut request = TestRequest::new().with_method(method).with_path(path).with_body(body);
+
+        if let Some(token) = token {
+            request = request.with_header(header("Authorization", &format!("Bearer {}", token)));
+        }
+
+        request.into()
+    }
+
+    fn unlocked() -> Session {
+        let mut session = Session { master_password: None, locks_at: None, timeout: 0 };
+        session.unlock(MASTER_PASSWORD.to_string(), 0);
+
+        session
+    }
+
+    // Answers a request with the right token
+    fn call(session: &mut Session, method: Method, path: &str, body: &'static str) -> (Value, u16) {
+        answer(&mut request(method, path, Some(TOKEN), body), TOKEN, session, &mut std::io::sink()).unwrap()
+    }
+
+    #[test]
+    fn token_and_origin() {
+        let mut session = Session { master_password: None, locks_at: None, timeout: 0 };
+        let mut log = Vec::new();
+
+        for token in [None, Some(""), Some("0123456789abcdeF"), Some("0123456789abcde")] {
+            let (response, status) = answer(&mut request(Method::Get, "/status", token, ""), TOKEN, &mut session, &mut log).unwrap();
+
+            assert_eq!(status, 401);
+            assert_eq!(response["error"]["code"], "unauthorized");
+        }
+
+        let (response, status) = answer(&mut request(Method::Get, "/status", Some(TOKEN), ""), TOKEN, &mut session, &mut log).unwrap();
+        assert_eq!((response, status), (json!({ "locked": true }), 200));
+
+        // The right token does not help a web page
+        let mut from_page: Request = TestRequest::new()
+            .with_path("/status")
+            .with_header(header("Authorization", &format!("Bearer {}", TOKEN)))
+            .with_header(header("Origin", "https://example.com"))
+            .into();
+        let (response, status) = answer(&mut from_page, TOKEN, &mut session, &mut log).unwrap();
+
+        assert_eq!(status, 401);
+        assert_eq!(response["error"]["code"], "unauthorized");
+    }
+
+    #[test]
+    fn audit_log_leaves_out_secrets() {
+        let _vault = testing::fresh_vault();
+        let mut session = unlocked();
+        let mut log = Vec::new();
+
+        answer(&mut request(Method::Post, "/entries", Some(TOKEN), r#"{"service": "github", "password": "hunter2"}"#), TOKEN, &mut session, &mut log).unwrap();
+        answer(&mut request(Method::Get, "/search?q=secret-term", Some(TOKEN), ""), TOKEN, &mut session, &mut log).unwrap();
+        answer(&mut request(Method::Get, "/entries/gitlab", None, ""), TOKEN, &mut session, &mut log).unwrap();
+
+        let log = String::from_utf8(log).unwrap();
+        let records: Vec<Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!((&records[0]["method"], &records[0]["path"], &records[0]["service"]), (&json!("POST"), &json!("/entries"), &json!("github")));
+        assert_eq!((&records[0]["status"], &records[0]["error"]), (&json!(201), &Value::Null));
+        assert_eq!(records[1]["path"], "/search");
+        assert_eq!((&records[2]["service"], &records[2]["status"], &records[2]["error"]), (&json!("gitlab"), &json!(401), &json!("unauthorized")));
+
+        assert!(!log.contains("hunter2"));
+        assert!(!log.contains("secret-term"));
+    }
+
+    #[test]
+    fn add_update_delete() {
+        let _vault = testing::fresh_vault();
+        let mut session = unlocked();
+
+        let (response, status) = call(&mut session, Method::Post, "/entries", r#"{"service": "github", "password": "hunter2"}"#);
+        assert_eq!(status, 201);
+        assert_eq!(response["password"], "hunter2");
+
+        let (_, status) = call(&mut session, Method::Post, "/entries", r#"{"service": "github", "password": "other"}"#);
+        assert_eq!(status, 409);
+
+        let (response, status) = call(&mut session, Method::Patch, "/entries/github", r#"{"username": "octocat"}"#);
+        assert_eq!(status, 200);
+        assert_eq!((&response["username"], &response["password"]), (&json!("octocat"), &json!("hunter2")));
+
+        let (_, status) = call(&mut session, Method::Put, "/entries/github", r#"{"service": "gitlab", "username": "octocat"}"#);
+        assert_eq!(status, 400);
+
+        let vault = Vault::load(MASTER_PASSWORD).unwrap();
+        assert_eq!(vault.get_entry("github").unwrap().username.as_deref(), Some("octocat"));
+
+        let (response, status) = call(&mut session, Method::Delete, "/entries/github", "");
+        assert_eq!((response, status), (json!({ "service": "github", "removed": true }), 200));
+
+        let (response, status) = call(&mut session, Method::Get, "/entries/github", "");
+        assert_eq!(status, 404);
+        assert_eq!(response["error"]["code"], "not_found");
+
+        let (_, status) = call(&mut session, Method::Delete, "/entries/github", "");
+        assert_eq!(status, 404);
+
+        // Locked again, nothing can be read
+        call(&mut session, Method::Post, "/lock", "");
+        let (response, status) = call(&mut session, Method::Get, "/entries", "");
+        assert_eq!(status, 423);
+        assert_eq!(response["error"]["code"], "vault_locked");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket_files() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = std::env::temp_dir().join(format!("rspass-serve-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let options = |socket: &Path| Options {
+            address: Address::Socket(socket.to_path_buf()),
+            token_file: dir.join("serve.token"),
+            audit_log: dir.join("serve.log"),
+            timeout: 0
+        };
+
+        // A live listener is left alone
+        let live = dir.join("live.sock");
+        let _listener = UnixListener::bind(&live).unwrap();
+        let e = serve(&options(&live), None, |_| Ok(())).unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::AlreadyExists);
+        assert!(live.exists());
+
+        // A stale one is replaced, and the socket is removed when the server stops
+        let stale = dir.join("stale.sock");
+        drop(UnixListener::bind(&stale).unwrap());
+        let e = serve(&options(&stale), None, |_| anyhow::bail!("stop")).unwrap_err();
+        assert_eq!(e.to_string(), "stop");
+        assert!(!stale.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
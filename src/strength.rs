@@ -0,0 +1,92 @@
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Strength {
+    Weak,
+    Fair,
+    Good,
+    Strong
+}
+
+impl fmt::Display for Strength {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Strength::Weak => "weak",
+            Strength::Fair => "fair",
+            Strength::Good => "good",
+            Strength::Strong => "strong"
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+// Rough entropy estimate in bits, from the character classes used and the length.
+// Repeated characters and runs like "aaaa" or "1234" only count once.
+pub fn entropy_bits(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+
+    let mut pool = 0;
+    if chars.iter().any(|c| c.is_ascii_lowercase()) { pool += 26; }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) { pool += 26; }
+    if chars.iter().any(|c| c.is_ascii_digit()) { pool += 10; }
+    if chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' ') { pool += 33; }
+    if chars.iter().any(|c| !c.is_ascii()) { pool += 100; }
+
+    if pool == 0 {
+        return 0.0;
+    }
+
+    let mut length = 0;
+
+    for (index, c) in chars.iter().enumerate() {
+        let previous = index.checked_sub(1).map(|i| chars[i] as i64);
+        let step = previous.map(|p| *c as i64 - p);
+
+        if !matches!(step, Some(-1..=1)) {
+            length += 1;
+        }
+    }
+
+    // A password built from very few distinct characters is no better than its unique ones
+    let unique = chars.iter().collect::<HashSet<_>>().len();
+
+    (length.min(unique * 2) as f64) * (pool as f64).log2()
+}
+
+pub fn strength(password: &str) -> Strength {
+    match entropy_bits(password) {
+        bits if bits < 40.0 => Strength::Weak,
+        bits if bits < 60.0 => Strength::Fair,
+        bits if bits < 80.0 => Strength::Good,
+        _ => Strength::Strong
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entropy() {
+        assert_eq!(entropy_bits(""), 0.0);
+        assert_eq!(entropy_bits("abcdefgh"), 26f64.log2()); // One run
+        assert_eq!(entropy_bits("aaaaaaaa"), 26f64.log2()); // One repeated character
+        assert_eq!(entropy_bits("acacacac"), 4.0 * 26f64.log2()); // Two unique characters
+        assert_eq!(entropy_bits("Ab1!"), 4.0 * 95f64.log2());
+        assert!(entropy_bits("pässwörd") > entropy_bits("passwort"));
+    }
+
+    #[test]
+    fn ratings() {
+        assert!(strength("123456") == Strength::Weak);
+        assert!(strength("hunter2") == Strength::Weak);
+        assert!(strength("Tr0ub4dor&3") == Strength::Good);
+        assert!(strength("correct horse battery staple") == Strength::Strong);
+        assert!(Strength::Weak < Strength::Fair && Strength::Good < Strength::Strong);
+        assert_eq!(Strength::Fair.to_string(), "fair");
+    }
+}
@@ -0,0 +1,169 @@
+use crate::error::{bail_code, CodedError, ErrorCode};
+use crate::reference::{Reference, Resolver, SCHEME};
+
+use anyhow::Result;
+use regex::Regex;
+use std::sync::LazyLock;
+
+// {{ rspass "entry" "field" }}, {{ rspass "vault" "entry" "field" }} or {{ rspass "rspass://..." }}
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{\{\s*rspass\b(.*?)\}\}").unwrap());
+static ARGUMENT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#""((?:[^"\\]|\\.)*)""#).unwrap());
+
+// Bare references in the text. Other characters have to be percent-encoded. They end with
+// the field name, so punctuation after them, as in "see rspass://github/password.", is text.
+static URI: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"rspass://[A-Za-z0-9._~%@:/-]*[A-Za-z0-9]").unwrap());
+
+// Replace every reference in `template` with its value. Nothing is returned unless every
+// reference resolves; the error lists all that failed, with their line numbers.
+pub fn render(template: &str, resolver: &mut Resolver) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut failures = Vec::new();
+    let mut last = 0;
+
+    for (start, end, reference) in find_references(template) {
+        output.push_str(&template[last..start]);
+        last = end;
+
+        let line = template[..start].matches('\n').count() + 1;
+        let value = reference.and_then(|reference| resolver.resolve(&reference));
+
+        match value {
+            Ok(value) => output.push_str(&value),
+            Err(e) => failures.push(format!("line {}: {}: {:#}", line, &template[start..end], e))
+        }
+    }
+
+    output.push_str(&template[last..]);
+
+    if !failures.is_empty() {
+        bail_code!(ErrorCode::NotFound, "{} unresolved references:\n  {}", failures.len(), failures.join("\n  "));
+    }
+
+    Ok(output)
+}
+
+// Placeholders and bare URIs with their byte ranges, in order
+fn find_references(template: &str) -> Vec<(usize, usize, Result<Reference>)> {
+    let mut found: Vec<(usize, usize, Result<Reference>)> = PLACEHOLDER.captures_iter(template)
+        .map(|captures| {
+            let whole = captures.get(0).unwrap();
+            let arguments: Vec<String> = ARGUMENT.captures_iter(&captures[1])
+                .map(|argument| unescape(&argument[1]))
+                .collect();
+
+            // Anything but quoted arguments is a mistake, not text to keep
+            let reference = if ARGUMENT.replace_all(&captures[1], "").trim().is_empty() {
+                placeholder_reference(&arguments)
+            } else {
+                Err(CodedError::new(ErrorCode::InvalidInput, "Arguments have to be in double quotes".to_string()).into())
+            };
+
+            (whole.start(), whole.end(), reference)
+        })
+        .collect();
+
+    for uri in URI.find_iter(template) {
+        // URIs inside a placeholder are handled with it
+        if !found.iter().any(|(start, end, _)| uri.start() >= *start && uri.end() <= *end) {
+            found.push((uri.start(), uri.end(), Reference::parse_uri(uri.as_str())));
+        }
+    }
+
+    found.sort_by_key(|(start, _, _)| *start);
+
+    found
+}
+
+fn placeholder_reference(arguments: &[String]) -> Result<Reference> {
+    match arguments {
+        [uri] if uri.starts_with(SCHEME) => Reference::parse_uri(uri),
+        [entry, field] => Reference::new(None, entry, field),
+        [vault, entry, field] => Reference::new(Some(vault), entry, field),
+        _ => bail_code!(ErrorCode::InvalidInput, "Expected {{{{ rspass \"entry\" \"field\" }}}}")
+    }
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            _ => result.push(c)
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MASTER_PASSWORD};
+
+    fn resolver<'a>() -> Resolver<'a> {
+        Resolver::new(|_| Ok(MASTER_PASSWORD.to_string()))
+    }
+
+    fn vault() -> std::sync::MutexGuard<'static, ()> {
+        let guard = testing::fresh_vault();
+
+        testing::change(|vault| {
+            vault.add_password("github", "gh-token").unwrap();
+            vault.set_details("github", Some("alice"), None).unwrap();
+            vault.add_password("work/db", "db-pass").unwrap();
+        });
+
+        guard
+    }
+
+    #[test]
+    fn placeholders_and_uris() {
+        let _vault = vault();
+        let template = concat!(
+            "user = \"{{ rspass \"github\" \"username\" }}\"\n",
+            "token = \"{{rspass \"rspass://github/password\"}}\"\n",
+            "db = rspass://work%2Fdb/password\n",
+            "quoted = {{ rspass \"work/db\" \"pass\\\"word\" }}\n"
+        );
+
+        // The last one is no field; everything else resolves
+        let e = render(template, &mut resolver()).unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::NotFound);
+        assert!(format!("{:#}", e).starts_with("1 unresolved references:\n  line 4: "));
+
+        let template = template.lines().take(3).collect::<Vec<_>>().join("\n");
+        assert_eq!(render(&template, &mut resolver()).unwrap(), "user = \"alice\"\ntoken = \"gh-token\"\ndb = db-pass");
+    }
+
+    #[test]
+    fn trailing_punctuation() {
+        let _vault = vault();
+        let text = "See rspass://github/password. Or rspass://github/username, (rspass://github/password): done";
+
+        assert_eq!(render(text, &mut resolver()).unwrap(), "See gh-token. Or alice, (gh-token): done");
+    }
+
+    #[test]
+    fn lists_every_failure() {
+        let _vault = vault();
+        let template = "a = rspass://gitlab/password\nb = {{ rspass github password }}\nc = {{ rspass \"github\" }}\nd = rspass://github/pin\n";
+
+        let message = format!("{:#}", render(template, &mut resolver()).unwrap_err());
+        let lines: Vec<&str> = message.lines().collect();
+
+        assert_eq!(lines[0], "4 unresolved references:");
+        assert!(lines[1].starts_with("  line 1: rspass://gitlab/password: No entry 'gitlab'"));
+        assert!(lines[2].starts_with("  line 2: {{ rspass github password }}: Arguments have to be in double quotes"));
+        assert!(lines[3].starts_with("  line 3: "));
+        assert!(lines[4].starts_with("  line 4: rspass://github/pin: Unknown field 'pin'"));
+    }
+
+    #[test]
+    fn text_without_references() {
+        let mut resolver = Resolver::new(|_| -> Result<String> { panic!("asked for the master password") });
+
+        assert_eq!(render("rspass:// is the scheme {{ not rspass }}\n", &mut resolver).unwrap(), "rspass:// is the scheme {{ not rspass }}\n");
+    }
+}
@@ -0,0 +1,44 @@
+// Helpers for tests that need a vault on disk
+
+use crate::vault::Vault;
+
+use dirs::home_dir;
+use std::fs;
+use std::sync::{Mutex, MutexGuard, Once};
+
+pub const MASTER_PASSWORD: &str = "correct horse battery staple";
+
+static HOME: Once = Once::new();
+
+// There is one vault per test process, as the vault name is global
+static VAULT: Mutex<()> = Mutex::new(());
+
+// Start with a new empty vault in a home directory of the tests' own. The vault is kept
+// for the test until the guard is dropped.
+pub fn fresh_vault() -> MutexGuard<'static, ()> {
+    HOME.call_once(|| {
+        let home = std::env::temp_dir().join(format!("rspass-test-{}", std::process::id()));
+        fs::create_dir_all(&home).unwrap();
+
+        std::env::set_var("HOME", &home);
+    });
+
+    let guard = VAULT.lock().unwrap_or_else(|e| e.into_inner());
+
+    let _ = fs::remove_dir_all(home_dir().unwrap().join(".rspass"));
+    Vault::create_new(MASTER_PASSWORD).unwrap();
+
+    guard
+}
+
+// Run `f` on the vault and save it
+pub fn change(f: impl FnOnce(&mut Vault)) {
+    let mut vault = Vault::load(MASTER_PASSWORD).unwrap();
+    f(&mut vault);
+    vault.save(MASTER_PASSWORD).unwrap();
+}
+
+// Encrypted attachment files of the vault
+pub fn attachment_files() -> usize {
+    fs::read_dir(home_dir().unwrap().join(".rspass").join("vault.attachments")).map_or(0, |files| files.count())
+}
@@ -1,10 +1,24 @@
 use macroquad::prelude::*;
 use macroquad::ui::{hash, root_ui, widgets};
-use clipboard::{ClipboardContext, ClipboardProvider};
 
+// Some helpers in the shared modules are only used by the CLI
+#[allow(dead_code)]
+mod clip;
+#[allow(dead_code)]
+mod config;
+#[allow(dead_code)]
+mod domain;
+#[allow(dead_code)]
 mod encrypt;
+#[allow(dead_code)]
+mod error;
+#[allow(dead_code)]
+mod otp;
+#[allow(dead_code)]
 mod vault;
 
+use config::Config;
+use otp::OtpKind;
 use vault::Vault;
 
 #[derive(PartialEq)]
@@ -13,7 +27,9 @@ enum MenuState {
     InitScreen,
     SelectMenu,
     AddUpdateScreen,
-    RemoveConfirmation
+    RemoveConfirmation,
+    HistoryScreen,
+    MoveCopyScreen
 }
 
 #[derive(PartialEq)]
@@ -34,6 +50,69 @@ enum ClearOrShow {
     Show
 }
 
+// System clipboard of the GUI process
+struct GuiClipboard(arboard::Clipboard);
+
+impl clip::Clipboard for GuiClipboard {
+    fn get(&mut self) -> anyhow::Result<String> {
+        match self.0.get_text() {
+            Ok(text) => Ok(text),
+            Err(arboard::Error::ContentNotAvailable) => Ok(String::new()),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    // Mark the content as sensitive where the platform supports it, so clipboard
+    // history managers and cloud clipboards skip it
+    fn set(&mut self, text: &str) -> anyhow::Result<()> {
+        let set = self.0.set();
+
+        #[cfg(target_os = "windows")]
+        let set = {
+            use arboard::SetExtWindows;
+            set.exclude_from_history().exclude_from_cloud().exclude_from_monitoring()
+        };
+
+        #[cfg(target_os = "macos")]
+        let set = {
+            use arboard::SetExtApple;
+            set.exclude_from_history()
+        };
+
+        #[cfg(all(unix, not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))))]
+        let set = {
+            use arboard::SetExtLinux;
+            set.exclude_from_history()
+        };
+
+        Ok(set.text(text)?)
+    }
+
+    fn clear(&mut self) -> anyhow::Result<()> {
+        Ok(self.0.clear()?)
+    }
+}
+
+// Copy text to the clipboard, returning the error as text instead of panicking
+fn copy_to_clipboard(clipboard: &mut Option<GuiClipboard>, text: &str) -> Result<(), String> {
+    if clipboard.is_none() {
+        *clipboard = Some(GuiClipboard(arboard::Clipboard::new().map_err(|e| e.to_string())?));
+    }
+
+    match clipboard {
+        Some(clipboard) => clip::Clipboard::set(clipboard, text).map_err(|e| e.to_string()),
+        None => Err("Clipboard is not available".to_string())
+    }
+}
+
+// Clear a pending copied secret now, if the clipboard still holds it
+fn clear_copied_secret(clipboard: &mut Option<GuiClipboard>, pending: &mut Option<(f64, String)>) -> Result<bool, String> {
+    match (pending.take(), clipboard.as_mut()) {
+        (Some((_, fingerprint)), Some(clipboard)) => clip::clear_if_unchanged(clipboard, &fingerprint).map_err(|e| e.to_string()),
+        _ => Ok(false)
+    }
+}
+
 fn conf() -> Conf {
     Conf {
         window_title: "RsPass".to_owned(),
@@ -50,7 +129,9 @@ async fn main() {
     let mut add_or_update = AddOrUpdate::Add;
     let mut clear_or_show = ClearOrShow::Clear;
 
-    let mut clipboard_ctx: ClipboardContext = ClipboardProvider::new().unwrap();
+    let mut clipboard: Option<GuiClipboard> = arboard::Clipboard::new().ok().map(GuiClipboard);
+    let mut clipboard_clear: Option<(f64, String)> = None; // Deadline and fingerprint of the copied secret
+    let clipboard_timeout = Config::load().map(|c| c.clipboard_timeout).unwrap_or(30);
     let mut vault: Option<Vault> = None;
     let mut master_password = String::new();
     let mut username = String::new();
@@ -58,11 +139,33 @@ async fn main() {
     let mut combobox = 0;
     let mut list_of_passwords: Vec<String> = vec!["None".to_string()];
     let mut service_to_remove = String::new();
+    let mut history_service = String::new();
+    let mut move_service = String::new();
+    let mut new_name = String::new();
+    let mut new_folder = String::new();
+    let mut overwrite = false;
+    let mut hotp_code = String::new();
     let mut message = String::new();
 
+    // Give the clipboard timer a chance to clear a copied secret before exiting
+    prevent_quit();
+
     loop {
         clear_background(GRAY);
 
+        if is_quit_requested() {
+            let _ = clear_copied_secret(&mut clipboard, &mut clipboard_clear);
+            break;
+        }
+
+        if clipboard_clear.as_ref().is_some_and(|(deadline, _)| get_time() >= *deadline) {
+            match clear_copied_secret(&mut clipboard, &mut clipboard_clear) {
+                Ok(true) => message = "Success: Clipboard cleared".to_string(),
+                Ok(false) => {}
+                Err(e) => message = format!("Error: Failed to clear the clipboard ({})", e)
+            }
+        }
+
         match menu_state {
             MenuState::LogInMenu => {
                 root_ui().window(hash!(), vec2(50.0, 50.0), vec2(500.0, 500.0), |ui| {
@@ -70,7 +173,7 @@ async fn main() {
                     widgets::InputText::new(hash!()).password(true).size(vec2(260.0, 30.0)).ui(ui, &mut master_password);
 
                     if !message.is_empty() {
-                        ui.label(None, &format!("{}", message));
+                        ui.label(None, &message);
                     }
 
                     if widgets::Button::new("Create Vault").ui(ui) {
@@ -122,7 +225,7 @@ async fn main() {
                     widgets::InputText::new(hash!()).password(true).size(vec2(260.0, 30.0)).ui(ui, &mut master_password);
 
                     if !message.is_empty() {
-                        ui.label(None, &format!("{}", message));
+                        ui.label(None, &message);
                     }
 
                     if widgets::Button::new("Create").ui(ui) {
@@ -156,12 +259,14 @@ async fn main() {
                 root_ui().window(hash!(), vec2(50.0, 50.0), vec2(500.0, 500.0), |ui| {
                     if login_state == LogInState::In {
                         if widgets::Button::new("Log Out").ui(ui) {
+                            let _ = clear_copied_secret(&mut clipboard, &mut clipboard_clear);
                             vault = None;
                             login_state = LogInState::Out;
                             menu_state = MenuState::LogInMenu;
                             master_password.clear();
                             username.clear();
                             password.clear();
+                            hotp_code.clear();
                             clear_or_show = ClearOrShow::Clear;
                             list_of_passwords = vec!["None".to_string()];
                             combobox = 0;
@@ -169,7 +274,11 @@ async fn main() {
                         }
 
                         if !message.is_empty() {
-                            ui.label(None, &format!("{}", message));
+                            ui.label(None, &message);
+                        }
+
+                        if let Some((deadline, _)) = &clipboard_clear {
+                            ui.label(None, &format!("Clipboard clears in {}s", (deadline - get_time()).ceil().max(0.0)));
                         }
 
                         // List of passwords
@@ -178,13 +287,91 @@ async fn main() {
 
                         // Username and Password copy buttons
                         if widgets::Button::new(username.as_str()).ui(ui) {
-                            clipboard_ctx.set_contents(username.clone()).unwrap();
-                            message = format!("Success: Username copied into clipboard");
+                            message = match copy_to_clipboard(&mut clipboard, &username) {
+                                Ok(_) => "Success: Username copied into clipboard".to_string(),
+                                Err(e) => format!("Error: Failed to copy ({})", e)
+                            };
                         }
 
                         if widgets::Button::new(password.as_str()).ui(ui) {
-                            clipboard_ctx.set_contents(password.clone()).unwrap();
-                            message = format!("Success: Password copied into clipboard");
+                            match copy_to_clipboard(&mut clipboard, &password) {
+                                Ok(_) => {
+                                    message = "Success: Password copied into clipboard".to_string();
+
+                                    if clipboard_timeout > 0 {
+                                        clipboard_clear = Some((get_time() + clipboard_timeout as f64, clip::fingerprint(&password)));
+                                    }
+                                }
+
+                                Err(e) => {
+                                    message = format!("Error: Failed to copy ({})", e);
+                                }
+                            }
+                        }
+
+                        // One-time code of the shown service, refreshed every frame
+                        if clear_or_show == ClearOrShow::Show {
+                            let now = otp::unix_now();
+                            let otp = vault.as_ref()
+                                .and_then(|v| v.get_entry(&username))
+                                .and_then(|entry| entry.otp.as_ref())
+                                .map(|otp| (otp.kind, otp.totp(now), otp.seconds_remaining(now).unwrap_or_default()));
+
+                            match otp {
+                                Some((OtpKind::Totp { .. }, Ok(code), remaining)) => {
+                                    let label = format!("{} ({}s)", code, remaining);
+
+                                    if widgets::Button::new(label).ui(ui) {
+                                        match copy_to_clipboard(&mut clipboard, &code) {
+                                            Ok(_) => {
+                                                message = "Success: One-time code copied into clipboard".to_string();
+
+                                                if clipboard_timeout > 0 {
+                                                    clipboard_clear = Some((get_time() + clipboard_timeout as f64, clip::fingerprint(&code)));
+                                                }
+                                            }
+
+                                            Err(e) => {
+                                                message = format!("Error: Failed to copy ({})", e);
+                                            }
+                                        }
+                                    }
+                                }
+
+                                Some((OtpKind::Hotp { .. }, _, _)) => {
+                                    if !hotp_code.is_empty() && widgets::Button::new(hotp_code.as_str()).ui(ui) {
+                                        match copy_to_clipboard(&mut clipboard, &hotp_code) {
+                                            Ok(_) => {
+                                                message = "Success: One-time code copied into clipboard".to_string();
+
+                                                if clipboard_timeout > 0 {
+                                                    clipboard_clear = Some((get_time() + clipboard_timeout as f64, clip::fingerprint(&hotp_code)));
+                                                }
+                                            }
+
+                                            Err(e) => {
+                                                message = format!("Error: Failed to copy ({})", e);
+                                            }
+                                        }
+                                    }
+
+                                    if widgets::Button::new("Next Code").ui(ui) {
+                                        if let Some(ref mut v) = vault {
+                                            match v.next_hotp(&username).and_then(|code| v.save(&master_password).map(|_| code)) {
+                                                Ok(code) => {
+                                                    hotp_code = code;
+                                                }
+
+                                                Err(e) => {
+                                                    message = format!("Error: Failed to generate code ({})", e);
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+
+                                _ => {}
+                            }
                         }
 
                         match clear_or_show {
@@ -213,6 +400,7 @@ async fn main() {
                                     message.clear();
                                     username.clear();
                                     password.clear();
+                                    hotp_code.clear();
                                     clear_or_show = ClearOrShow::Clear;
                                 }
                             }
@@ -253,6 +441,40 @@ async fn main() {
                             }
                         }
 
+                        if widgets::Button::new("History").ui(ui) {
+                            message.clear();
+
+                            if !list_of_passwords.is_empty() && list_of_passwords[0] != "None" {
+                                history_service = list_of_passwords[combobox].clone();
+                                username.clear();
+                                password.clear();
+                                clear_or_show = ClearOrShow::Clear;
+                                menu_state = MenuState::HistoryScreen;
+                            } else {
+                                message = "Error: No passwords to show history for".to_string();
+                            }
+                        }
+
+                        if widgets::Button::new("Move / Copy").ui(ui) {
+                            message.clear();
+
+                            if !list_of_passwords.is_empty() && list_of_passwords[0] != "None" {
+                                move_service = list_of_passwords[combobox].clone();
+                                new_name = move_service.clone();
+                                new_folder = vault.as_ref()
+                                    .and_then(|v| v.get_entry(&move_service))
+                                    .and_then(|entry| entry.folder.clone())
+                                    .unwrap_or_default();
+                                overwrite = false;
+                                username.clear();
+                                password.clear();
+                                clear_or_show = ClearOrShow::Clear;
+                                menu_state = MenuState::MoveCopyScreen;
+                            } else {
+                                message = "Error: No passwords to move or copy".to_string();
+                            }
+                        }
+
                         if widgets::Button::new("Remove").ui(ui) {
                             message.clear();
 
@@ -272,12 +494,14 @@ async fn main() {
                 root_ui().window(hash!(), vec2(50.0, 50.0), vec2(500.0, 500.0), |ui| {
                     if login_state == LogInState::In {
                         if widgets::Button::new("Log Out").ui(ui) {
+                            let _ = clear_copied_secret(&mut clipboard, &mut clipboard_clear);
                             vault = None;
                             login_state = LogInState::Out;
                             menu_state = MenuState::LogInMenu;
                             master_password.clear();
                             username.clear();
                             password.clear();
+                            hotp_code.clear();
                             clear_or_show = ClearOrShow::Clear;
                             list_of_passwords = vec!["None".to_string()];
                             combobox = 0;
@@ -285,7 +509,7 @@ async fn main() {
                         }
 
                         if !message.is_empty() {
-                            ui.label(None, &format!("{}", message));
+                            ui.label(None, &message);
                         }
 
                         // Text fields: Username, Password
@@ -434,6 +658,144 @@ async fn main() {
                     }
                 });
             }
+
+            MenuState::HistoryScreen => {
+                root_ui().window(hash!(), vec2(50.0, 50.0), vec2(500.0, 500.0), |ui| {
+                    ui.label(None, &format!("Previous passwords for '{}'", history_service));
+
+                    if !message.is_empty() {
+                        ui.label(None, &message);
+                    }
+
+                    let mut restore = None;
+
+                    if let Some(entry) = vault.as_ref().and_then(|v| v.get_entry(&history_service)) {
+                        if entry.history.is_empty() {
+                            ui.label(None, "No previous passwords");
+                        }
+
+                        for (index, item) in entry.history.iter().enumerate() {
+                            ui.label(None, &format!("#{} replaced {}", index + 1, item.replaced.format("%Y-%m-%d %H:%M")));
+
+                            if clear_or_show == ClearOrShow::Show {
+                                ui.label(None, &item.password);
+                            }
+
+                            if widgets::Button::new(format!("Restore #{}", index + 1)).ui(ui) {
+                                restore = Some(index + 1);
+                            }
+                        }
+                    }
+
+                    if let (Some(version), Some(ref mut v)) = (restore, &mut vault) {
+                        match v.restore_password(&history_service, version).and_then(|_| v.save(&master_password)) {
+                            Ok(_) => {
+                                message = format!("Success: Version {} restored for '{}'", version, history_service);
+                            }
+
+                            Err(e) => {
+                                message = format!("Error: Failed to restore ({})", e);
+                            }
+                        }
+                    }
+
+                    match clear_or_show {
+                        ClearOrShow::Clear => {
+                            if widgets::Button::new("Show").ui(ui) {
+                                clear_or_show = ClearOrShow::Show;
+                            }
+                        }
+
+                        ClearOrShow::Show => {
+                            if widgets::Button::new("Hide").ui(ui) {
+                                clear_or_show = ClearOrShow::Clear;
+                            }
+                        }
+                    }
+
+                    if widgets::Button::new("Back").ui(ui) {
+                        message.clear();
+                        history_service.clear();
+                        clear_or_show = ClearOrShow::Clear;
+                        menu_state = MenuState::SelectMenu;
+                    }
+                });
+            }
+
+            MenuState::MoveCopyScreen => {
+                root_ui().window(hash!(), vec2(50.0, 50.0), vec2(500.0, 500.0), |ui| {
+                    ui.label(None, &format!("Move or copy '{}'", move_service));
+                    ui.label(None, "Within this vault. For other vaults use 'rspass mv --to-vault'.");
+
+                    if !message.is_empty() {
+                        ui.label(None, &message);
+                    }
+
+                    ui.label(None, "New name:");
+                    widgets::InputText::new(hash!()).size(vec2(260.0, 30.0)).ui(ui, &mut new_name);
+
+                    ui.label(None, "Folder:");
+                    widgets::InputText::new(hash!()).size(vec2(260.0, 30.0)).ui(ui, &mut new_folder);
+
+                    ui.checkbox(hash!(), "Overwrite an existing entry", &mut overwrite);
+
+                    let mut action = None;
+
+                    if widgets::Button::new("Move").ui(ui) {
+                        action = Some(false);
+                    }
+
+                    if widgets::Button::new("Copy").ui(ui) {
+                        action = Some(true);
+                    }
+
+                    if let (Some(keep_source), Some(ref mut v)) = (action, &mut vault) {
+                        let name = new_name.trim().to_string();
+
+                        // Changed on a copy, so the screen only shows what was saved
+                        let mut changed = v.clone();
+
+                        let result = if keep_source && name == move_service {
+                            Err(anyhow::anyhow!("Give the copy a new name"))
+                        } else if keep_source {
+                            changed.copy_entry(&move_service, &name, overwrite)
+                        } else {
+                            changed.move_entry(&move_service, &name, overwrite)
+                        };
+
+                        match result
+                            .and_then(|_| changed.set_details(&name, None, Some(&new_folder)))
+                            .and_then(|_| changed.save(&master_password))
+                        {
+                            Ok(_) => {
+                                *v = changed;
+
+                                let verb = if keep_source { "copied" } else { "moved" };
+                                message = match v.purge_attachments() {
+                                    Ok(_) => format!("Success: '{}' {} to '{}'", move_service, verb, name),
+                                    Err(e) => format!("Success: '{}' {} to '{}', but unused attachment files were kept ({})", move_service, verb, name, e)
+                                };
+
+                                // Update the list of passwords and select the new entry
+                                list_of_passwords = v.list_services().iter().map(|s| s.to_string()).collect();
+                                combobox = list_of_passwords.iter().position(|s| *s == name).unwrap_or_default();
+                                move_service.clear();
+                                menu_state = MenuState::SelectMenu;
+                            }
+
+                            Err(e) => {
+                                message = format!("Error: Failed to {} ({})", if keep_source { "copy" } else { "move" }, e);
+                            }
+                        }
+                    }
+
+                    if widgets::Button::new("Cancel").ui(ui) {
+                        message.clear();
+                        move_service.clear();
+                        menu_state = MenuState::SelectMenu;
+                    }
+                });
+            }
         }
 
         next_frame().await;
@@ -0,0 +1,187 @@
+use crate::domain::{self, SiteUrl};
+use crate::error::{bail_code, ErrorCode};
+use crate::vault::{Entry, EntryKind, UrlMatch, Vault};
+
+use anyhow::Result;
+use regex::Regex;
+use url::Position;
+
+// An entry for the address, with the URL that matched and how closely
+pub struct UrlHit<'a> {
+    pub service: &'a String,
+    pub entry: &'a Entry,
+    pub url: String,
+    pub level: UrlMatch
+}
+
+// Entries with a URL for the address, closest matches first. Entries without URLs are
+// matched by their name when it is a host or URL, such as "github.com", but only on https
+// pages. Notes and SSH keys are left out.
+pub fn find<'a>(vault: &'a Vault, address: &str) -> Result<Vec<UrlHit<'a>>> {
+    let Some(target) = domain::site_url(address) else {
+        bail_code!(ErrorCode::InvalidInput, "Invalid URL '{}'", address);
+    };
+
+    let mut hits = Vec::new();
+
+    for service in vault.list_services() {
+        let Some(entry) = vault.get_entry(service).filter(|entry| entry.kind != EntryKind::Note && entry.kind != EntryKind::SshKey) else {
+            continue;
+        };
+
+        let mode = entry.url_match.unwrap_or_default();
+
+        let urls = match entry.urls.is_empty() {
+            true if mode == UrlMatch::Regex => continue, // A name is not a pattern
+            true if target.url.scheme() != "https" => continue,
+            true => std::slice::from_ref(service),
+            false => entry.urls.as_slice()
+        };
+
+        // The closest of the entry's URLs counts
+        let best = urls.iter()
+            .filter_map(|url| level(url, mode, &target).map(|level| (level, url)))
+            .min_by_key(|(level, _)| *level);
+
+        if let Some((level, url)) = best {
+            hits.push(UrlHit { service, entry, url: url.clone(), level });
+        }
+    }
+
+    // Stable, so entries that match equally stay sorted by name
+    hits.sort_by_key(|hit| hit.level);
+
+    Ok(hits)
+}
+
+// How closely a stored URL matches the address, within what its mode allows. URLs stored
+// with a scheme only match addresses with the same one, so logins for https are not
+// offered to http pages. Patterns have to match the whole address.
+fn level(stored: &str, mode: UrlMatch, target: &SiteUrl) -> Option<UrlMatch> {
+    if mode == UrlMatch::Regex {
+        let pattern = Regex::new(&format!("^(?:{})$", stored)).ok()?;
+        return pattern.is_match(target.url.as_str()).then_some(UrlMatch::Regex);
+    }
+
+    let stored = domain::site_url(stored)?;
+
+    if stored.explicit_scheme && stored.url.scheme() != target.url.scheme() {
+        return None;
+    }
+
+    let (stored, target) = (&stored.url, &target.url);
+
+    if stored[Position::BeforeUsername..Position::AfterQuery] == target[Position::BeforeUsername..Position::AfterQuery] {
+        return Some(UrlMatch::Exact);
+    }
+
+    if mode == UrlMatch::Exact {
+        return None;
+    }
+
+    if stored.host_str() == target.host_str() && stored.port().is_none_or(|port| Some(port) == target.port_or_known_default()) {
+        return Some(UrlMatch::Host);
+    }
+
+    if mode == UrlMatch::Host {
+        return None;
+    }
+
+    let domain = stored.host_str().and_then(domain::registrable_domain);
+
+    (domain.is_some() && domain == target.host_str().and_then(domain::registrable_domain)).then_some(UrlMatch::Domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault() -> Vault {
+        let mut vault = Vault::in_memory();
+
+        for (service, urls, mode) in [
+            ("exact", vec!["https://example.com/login"], UrlMatch::Exact),
+            ("host", vec!["https://example.com"], UrlMatch::Host),
+            ("domain", vec!["example.com"], UrlMatch::Domain),
+            ("pattern", vec![r"https://[a-z]+\.example\.com/.*"], UrlMatch::Regex),
+            ("plain", vec!["http://intranet.example.org"], UrlMatch::Host)
+        ] {
+            vault.add_password(service, "secret").unwrap();
+            vault.set_urls(service, &urls.into_iter().map(String::from).collect::<Vec<_>>(), &[], Some(mode)).unwrap();
+        }
+
+        vault.add_password("github.com", "secret").unwrap();
+        vault.set_note("example.com notes", "https://example.com").unwrap();
+
+        vault
+    }
+
+    fn hits(vault: &Vault, address: &str) -> Vec<(String, UrlMatch)> {
+        find(vault, address).unwrap().into_iter().map(|hit| (hit.service.clone(), hit.level)).collect()
+    }
+
+    #[test]
+    fn levels() {
+        let vault = vault();
+
+        // Equal matches stay sorted by name
+        assert_eq!(hits(&vault, "https://example.com/login"), vec![
+            ("exact".to_string(), UrlMatch::Exact),
+            ("domain".to_string(), UrlMatch::Host),
+            ("host".to_string(), UrlMatch::Host)
+        ]);
+
+        assert_eq!(hits(&vault, "https://www.example.com/"), vec![
+            ("domain".to_string(), UrlMatch::Domain),
+            ("pattern".to_string(), UrlMatch::Regex)
+        ]);
+
+        // Ports only count when the URL has one
+        assert_eq!(hits(&vault, "https://example.com:8443/"), vec![("domain".to_string(), UrlMatch::Host), ("host".to_string(), UrlMatch::Host)]);
+        assert!(hits(&vault, "https://example.org/").is_empty());
+    }
+
+    #[test]
+    fn schemes() {
+        let vault = vault();
+
+        // Bare hosts match any scheme, URLs only their own
+        assert_eq!(hits(&vault, "http://example.com/"), vec![("domain".to_string(), UrlMatch::Exact)]);
+        assert_eq!(hits(&vault, "http://intranet.example.org/wiki"), vec![("plain".to_string(), UrlMatch::Host)]);
+        assert!(hits(&vault, "https://intranet.example.org/").is_empty());
+    }
+
+    #[test]
+    fn public_suffixes() {
+        let mut vault = Vault::in_memory();
+        vault.add_password("bank", "secret").unwrap();
+        vault.set_urls("bank", &["https://bank.co.uk".to_string()], &[], None).unwrap();
+
+        assert_eq!(hits(&vault, "https://login.bank.co.uk/"), vec![("bank".to_string(), UrlMatch::Domain)]);
+        assert!(hits(&vault, "https://other.co.uk/").is_empty());
+        assert!(hits(&vault, "https://bank.co.uk.evil.net/").is_empty());
+    }
+
+    #[test]
+    fn patterns_match_whole_addresses() {
+        let vault = vault();
+
+        assert!(hits(&vault, "https://www.example.com.evil.net/").is_empty());
+        assert!(hits(&vault, "https://evil.net/?https://www.example.com/").is_empty());
+    }
+
+    #[test]
+    fn names_match_https_only() {
+        let vault = vault();
+
+        assert_eq!(hits(&vault, "https://gist.github.com/"), vec![("github.com".to_string(), UrlMatch::Domain)]);
+        assert!(hits(&vault, "http://github.com/").is_empty());
+        assert!(hits(&vault, "https://github.com.evil.net/").is_empty());
+    }
+
+    #[test]
+    fn invalid_address() {
+        let e = find(&vault(), "not a url").err().unwrap();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::InvalidInput);
+    }
+}
@@ -1,22 +1,314 @@
-use crate::encrypt::EncryptedData;
+use crate::config::Config;
+use crate::encrypt::{EncryptedData, EncryptionKey};
+use crate::error::{bail_code, ErrorCode};
+use crate::otp::{OtpKind, OtpSecret};
 
 use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use clap::ValueEnum;
 use dirs::home_dir;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::io::Write;
 use std::path::PathBuf;
-use serde::{Serialize, Deserialize};
+use std::sync::OnceLock;
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
 use zeroize::Zeroize;
 
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HistoryItem {
+    pub password: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub set: Option<DateTime<Utc>>, // When this password was stored
+    pub replaced: DateTime<Utc>
+}
+
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    #[default]
+    Login,
+    Note, // Secure note without a password
+    Registry, // Container registry login, kept by docker-credential
+    #[serde(rename = "ssh-key")]
+    SshKey // OpenSSH private key, used by ssh-agent
+}
+
+// How the URLs of an entry are compared with the address of a site. Each mode also
+// accepts the closer matches before it.
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum UrlMatch {
+    Exact, // Same address, ignoring the fragment
+    Host, // Same host name, and port if the URL has one
+    #[default]
+    Domain, // Same registrable domain, such as github.com for gist.github.com
+    Regex // The URL is a regular expression the address has to match
+}
+
+impl UrlMatch {
+    pub fn name(self) -> &'static str {
+        match self {
+            UrlMatch::Exact => "exact",
+            UrlMatch::Host => "host",
+            UrlMatch::Domain => "domain",
+            UrlMatch::Regex => "regex"
+        }
+    }
+}
+
+// How ssh-agent may use an SSH key
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct SshOptions {
+    #[serde(default)]
+    pub confirm: bool, // Ask before every signature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lifetime: Option<u64> // Seconds the agent offers the key after it starts
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Attachment {
+    pub name: String,
+    pub id: String, // Name of the encrypted blob file
+    pub size: u64,
+    pub sha256: String, // Hash of the plaintext, checked on every read
+    pub key: String, // Hex key the blob is encrypted with
+    pub added: DateTime<Utc>
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Entry {
+    #[serde(default)]
+    pub kind: EntryKind,
+    pub password: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>, // Slash-separated path such as "work/servers"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<DateTime<Utc>>, // Unknown for entries stored before timestamps were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation_days: Option<u32>, // Required rotation interval
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<HistoryItem>, // Previous passwords, newest first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otp: Option<OtpSecret>, // TOTP/HOTP secret for two-factor codes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub custom_fields: BTreeMap<String, String>, // Named values such as access keys
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>, // Secret Service lookup attributes, kept apart from the custom fields
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh: Option<SshOptions>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub urls: Vec<String>, // Sites the entry is for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_match: Option<UrlMatch>, // Defaults to matching the registrable domain
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>
+}
+
+impl Entry {
+    fn new(password: &str) -> Self {
+        let now = Utc::now();
+
+        Entry {
+            password: password.to_string(),
+            created: Some(now),
+            modified: Some(now),
+            ..Default::default()
+        }
+    }
+
+    // Replace the password, keeping at most `history_limit` previous ones
+    fn set_password(&mut self, password: &str, history_limit: usize) {
+        let now = Utc::now();
+        let previous = std::mem::replace(&mut self.password, password.to_string());
+
+        self.history.insert(0, HistoryItem {
+            password: previous,
+            set: self.modified,
+            replaced: now
+        });
+        self.trim_history(history_limit);
+        self.modified = Some(now);
+    }
+
+    fn trim_history(&mut self, history_limit: usize) {
+        for item in self.history.iter_mut().skip(history_limit) {
+            item.password.zeroize();
+        }
+
+        self.history.truncate(history_limit);
+    }
+
+    // Whole days since the password was last changed
+    pub fn age_days(&self, now: DateTime<Utc>) -> Option<i64> {
+        self.modified.map(|modified| (now - modified).num_days())
+    }
+
+    // When the password has to be rotated next, if it carries an interval
+    pub fn rotation_due(&self) -> Option<DateTime<Utc>> {
+        let days = self.rotation_days?;
+
+        // Entries without a timestamp are treated as due right away
+        Some(self.modified.map_or(DateTime::<Utc>::MIN_UTC, |modified| modified + Duration::days(days as i64)))
+    }
+
+    // Text fields `rspass search` looks at besides the service name, flagged when they are free text
+    pub fn search_fields(&self) -> Vec<(&'static str, &str, bool)> {
+        let mut fields = Vec::new();
+
+        if let Some(username) = &self.username {
+            fields.push(("username", username.as_str(), false));
+        }
+
+        if let Some(folder) = &self.folder {
+            fields.push(("folder", folder.as_str(), false));
+        }
+
+        for tag in &self.tags {
+            fields.push(("tag", tag.as_str(), false));
+        }
+
+        for url in &self.urls {
+            fields.push(("url", url.as_str(), false));
+        }
+
+        if let Some(notes) = &self.notes {
+            fields.push(("notes", notes.as_str(), true));
+        }
+
+        for attachment in &self.attachments {
+            fields.push(("attachment", attachment.name.as_str(), false));
+        }
+
+        fields
+    }
+
+    // Copy of the fields `rspass edit` can change
+    pub fn fields(&self) -> EntryFields {
+        EntryFields {
+            password: (self.kind != EntryKind::Note).then(|| self.password.clone()),
+            username: self.username.clone(),
+            folder: self.folder.clone(),
+            tags: self.tags.clone(),
+            rotation_days: self.rotation_days,
+            notes: self.notes.clone(),
+            custom_fields: self.custom_fields.clone(),
+            urls: self.urls.clone(),
+            url_match: self.url_match
+        }
+    }
+}
+
+// Fields of an entry that `rspass edit` puts in a document
+#[derive(Serialize, Deserialize, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct EntryFields {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>, // Left out for notes
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub folder: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub rotation_days: Option<u32>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub custom_fields: BTreeMap<String, String>,
+    #[serde(default)]
+    pub urls: Vec<String>,
+    #[serde(default)]
+    pub url_match: Option<UrlMatch>
+}
+
+impl Zeroize for EntryFields {
+    fn zeroize(&mut self) {
+        self.password.zeroize();
+        self.notes.zeroize();
+        zeroize_fields(&mut self.custom_fields);
+    }
+}
+
+fn zeroize_fields(fields: &mut BTreeMap<String, String>) {
+    for value in fields.values_mut() {
+        value.zeroize();
+    }
+
+    fields.clear();
+}
+
+impl Drop for EntryFields {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl Zeroize for Entry {
+    fn zeroize(&mut self) {
+        self.password.zeroize();
+
+        for item in self.history.iter_mut() {
+            item.password.zeroize();
+        }
+
+        if let Some(otp) = self.otp.as_mut() {
+            otp.zeroize();
+        }
+
+        self.notes.zeroize();
+        zeroize_fields(&mut self.custom_fields);
+
+        for attachment in self.attachments.iter_mut() {
+            attachment.key.zeroize();
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct VaultData {
-    pub entries: HashMap<String, String> // service -> password
+    #[serde(deserialize_with = "deserialize_entries")]
+    pub entries: HashMap<String, Entry> // service -> entry
+}
+
+// Older vaults stored a bare password string per service
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredEntry {
+    Current(Box<Entry>),
+    Legacy(String)
+}
+
+fn deserialize_entries<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<String, Entry>, D::Error> {
+    let stored = HashMap::<String, StoredEntry>::deserialize(deserializer)?;
+
+    Ok(stored.into_iter().map(|(service, entry)| {
+        let entry = match entry {
+            StoredEntry::Current(entry) => *entry,
+            StoredEntry::Legacy(password) => Entry {
+                password,
+                ..Default::default()
+            }
+        };
+
+        (service, entry)
+    }).collect())
 }
 
 impl Zeroize for VaultData {
     fn zeroize(&mut self) {
-        for (_, password) in self.entries.iter_mut() {
-            password.zeroize();
+        for (_, entry) in self.entries.iter_mut() {
+            entry.zeroize();
         }
 
         self.entries.clear();
@@ -29,33 +321,91 @@ impl Drop for VaultData {
     }
 }
 
+// Name of the vault in ~/.rspass/vault.enc
+pub const DEFAULT_VAULT: &str = "default";
+
+// Vault the commands work on, chosen with --vault
+static SELECTED_VAULT: OnceLock<String> = OnceLock::new();
+
+// Choose the vault that `load` and `create_new` open
+pub fn select_vault(name: &str) -> Result<()> {
+    validate_vault_name(name)?;
+    let _ = SELECTED_VAULT.set(name.to_string());
+
+    Ok(())
+}
+
+pub fn selected_vault() -> &'static str {
+    SELECTED_VAULT.get().map_or(DEFAULT_VAULT, |name| name.as_str())
+}
+
+fn validate_field_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() || name.trim() != name {
+        bail_code!(ErrorCode::InvalidInput, "Invalid field name '{}'. Names cannot be empty or start or end with spaces.", name);
+    }
+
+    Ok(())
+}
+
+fn validate_url(url: &str, url_match: UrlMatch) -> Result<()> {
+    if url_match == UrlMatch::Regex {
+        if let Err(e) = regex::Regex::new(url) {
+            bail_code!(ErrorCode::InvalidInput, "Invalid URL pattern '{}': {}", url, e);
+        }
+    } else if crate::domain::site_url(url).is_none() {
+        bail_code!(ErrorCode::InvalidInput, "Invalid URL '{}'. Give an address such as https://example.com/login or a host name.", url);
+    }
+
+    Ok(())
+}
+
+fn validate_vault_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if !valid || name == "vault" || name == "config" {
+        bail_code!(ErrorCode::InvalidInput, "Invalid vault name '{}'. Use letters, digits, '-' and '_'.", name);
+    }
+
+    Ok(())
+}
+
+#[derive(Clone)]
 pub struct Vault {
     data: VaultData,
-    file_path: PathBuf
+    file_path: PathBuf,
+    history_limit: usize
 }
 
 impl Vault {
-    // Get the default vault file path
-    fn get_vault_path() -> Result<PathBuf> {
+    // Get the file path of a vault
+    fn get_vault_path(name: &str) -> Result<PathBuf> {
+        validate_vault_name(name)?;
+
         let home_dir = home_dir().context("Could not find home directory")?;
         
         let vault_dir = home_dir.join(".rspass");
         fs::create_dir_all(&vault_dir).context("Failed to create the directory")?;
         
-        Ok(vault_dir.join("vault.enc"))
+        if name == DEFAULT_VAULT {
+            return Ok(vault_dir.join("vault.enc"));
+        }
+
+        Ok(vault_dir.join(format!("{}.enc", name)))
     }
     
     // Create a new empty vault
     pub fn create_new(master_password: &str) -> Result<()> {
-        let vault_path = Self::get_vault_path()?;
+        let name = selected_vault();
+        let vault_path = Self::get_vault_path(name)?;
         
         if vault_path.exists() {
-            anyhow::bail!("Vault already exists. Use other commands to manage it.");
+            bail_code!(ErrorCode::VaultExists, "Vault already exists. Use other commands to manage it.");
         }
         
         let vault = Vault {
             data: VaultData::default(),
-            file_path: vault_path
+            file_path: vault_path,
+            history_limit: Config::load()?.history_limit
         };
         
         vault.save(master_password)?;
@@ -71,17 +421,32 @@ impl Vault {
         
         let encrypted_json = serde_json::to_vec_pretty(&encrypted_data).context("Failed to serialize encrypted data")?;
         
-        fs::write(&self.file_path, encrypted_json).context("Failed to write vault file")?;
+        // Write a new file and rename it over the old one, so a failed save never leaves a broken vault
+        let temp_path = self.file_path.with_extension("enc.tmp");
+        let mut file = fs::File::create(&temp_path).context("Failed to write vault file")?;
+        file.write_all(&encrypted_json).context("Failed to write vault file")?;
+        file.sync_all().context("Failed to write vault file")?;
+
+        fs::rename(&temp_path, &self.file_path).context("Failed to replace vault file")?;
         
         Ok(())
     }
 
-    // Load vault
+    // Load the selected vault
     pub fn load(master_password: &str) -> Result<Self> {
-        let vault_path = Self::get_vault_path()?;
+        Self::load_named(selected_vault(), master_password)
+    }
+
+    // Load a vault by name
+    pub fn load_named(name: &str, master_password: &str) -> Result<Self> {
+        let vault_path = Self::get_vault_path(name)?;
         
+        if !vault_path.exists() && name == DEFAULT_VAULT {
+            bail_code!(ErrorCode::NoVault, "No vault found. Run 'rspass init' to create one.");
+        }
+
         if !vault_path.exists() {
-            anyhow::bail!("No vault found. Run 'rspass init' to create one.");
+            bail_code!(ErrorCode::NoVault, "No vault named '{}'. Run 'rspass --vault {} init' to create one.", name, name);
         }
         
         let encrypted_content = fs::read(&vault_path).context("Failed to read vault file")?;
@@ -90,33 +455,54 @@ impl Vault {
         
         let decrypted_bytes = encrypted_data.decrypt(master_password).context("Failed to decrypt vault")?;
         
-        let data: VaultData = serde_json::from_slice(&decrypted_bytes).context("Failed to parse decrypted vault data")?;
+        let mut data: VaultData = serde_json::from_slice(&decrypted_bytes).context("Failed to parse decrypted vault data")?;
+
+        let history_limit = Config::load()?.history_limit;
+
+        for entry in data.entries.values_mut() {
+            entry.trim_history(history_limit);
+        }
         
         Ok(Vault {
             data,
-            file_path: vault_path
+            file_path: vault_path,
+            history_limit
         })
     }
 
+    // Empty vault that is never saved, for tests
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Vault { data: VaultData::default(), file_path: PathBuf::new(), history_limit: 10 }
+    }
+
     // Add a password for a service
     pub fn add_password(&mut self, service: &str, password: &str) -> Result<()> {
         if self.data.entries.contains_key(service) {
-            anyhow::bail!("Password for '{}' already exists. Use 'update' to modify it.", service);
+            bail_code!(ErrorCode::AlreadyExists, "Password for '{}' already exists. Use 'update' to modify it.", service);
         }
         
-        self.data.entries.insert(service.to_string(), password.to_string());
+        self.data.entries.insert(service.to_string(), Entry::new(password));
         
         Ok(())
     }
 
     // Get a password for a service
     pub fn get_password(&self, service: &str) -> Option<&String> {
+        self.data.entries.get(service).map(|entry| &entry.password)
+    }
+
+    // Get the full entry for a service
+    pub fn get_entry(&self, service: &str) -> Option<&Entry> {
         self.data.entries.get(service)
     }
 
-    // List all service names
+    // List all service names, sorted
     pub fn list_services(&self) -> Vec<&String> {
-        self.data.entries.keys().collect()
+        let mut services: Vec<&String> = self.data.entries.keys().collect();
+        services.sort();
+
+        services
     }
 
     // Remove a password for a service
@@ -126,12 +512,622 @@ impl Vault {
 
     // Update a password for a service
     pub fn update_password(&mut self, service: &str, password: &str) -> Result<()> {
-        if !self.data.entries.contains_key(service) {
-            anyhow::bail!("No password found for '{}'. Use 'add' to create a new entry.", service);
+        let Some(entry) = self.data.entries.get_mut(service) else {
+            bail_code!(ErrorCode::NotFound, "No password found for '{}'. Use 'add' to create a new entry.", service);
+        };
+
+        entry.set_password(password, self.history_limit);
+
+        Ok(())
+    }
+
+    // Make a previous password current again; version 1 is the most recent one
+    pub fn restore_password(&mut self, service: &str, version: usize) -> Result<()> {
+        let Some(entry) = self.data.entries.get_mut(service) else {
+            bail_code!(ErrorCode::NotFound, "No password found for '{}'", service);
+        };
+
+        if version == 0 || version > entry.history.len() {
+            bail_code!(ErrorCode::NotFound, "'{}' has no version {} in its history", service, version);
+        }
+
+        let mut item = entry.history.remove(version - 1);
+        entry.set_password(&item.password, self.history_limit);
+        item.password.zeroize();
+
+        Ok(())
+    }
+
+    // Set or clear the one-time code secret of a service
+    pub fn set_otp(&mut self, service: &str, otp: Option<OtpSecret>) -> Result<()> {
+        let Some(entry) = self.data.entries.get_mut(service) else {
+            bail_code!(ErrorCode::NotFound, "No password found for '{}'", service);
+        };
+
+        if let Some(mut old) = std::mem::replace(&mut entry.otp, otp) {
+            old.zeroize();
+        }
+
+        Ok(())
+    }
+
+    // Generate the next HOTP code and advance the counter
+    pub fn next_hotp(&mut self, service: &str) -> Result<String> {
+        let Some(otp) = self.data.entries.get_mut(service).and_then(|entry| entry.otp.as_mut()) else {
+            bail_code!(ErrorCode::NotFound, "No one-time code secret stored for '{}'", service);
+        };
+
+        let OtpKind::Hotp { counter } = otp.kind else {
+            bail_code!(ErrorCode::InvalidInput, "'{}' uses time-based codes", service);
+        };
+
+        let code = otp.hotp(counter)?;
+        otp.kind = OtpKind::Hotp { counter: counter + 1 };
+
+        Ok(code)
+    }
+
+    // Add an SSH private key, in OpenSSH format
+    pub fn add_ssh_key(&mut self, service: &str, private_key: &str, options: SshOptions) -> Result<()> {
+        if self.data.entries.contains_key(service) {
+            bail_code!(ErrorCode::AlreadyExists, "An entry named '{}' already exists", service);
+        }
+
+        self.data.entries.insert(service.to_string(), Entry {
+            kind: EntryKind::SshKey,
+            ssh: Some(options),
+            ..Entry::new(private_key)
+        });
+
+        Ok(())
+    }
+
+    // Change how ssh-agent may use a key; a lifetime of 0 removes the limit
+    pub fn set_ssh_options(&mut self, service: &str, confirm: Option<bool>, lifetime: Option<u64>) -> Result<()> {
+        let Some(entry) = self.data.entries.get_mut(service).filter(|entry| entry.kind == EntryKind::SshKey) else {
+            bail_code!(ErrorCode::NotFound, "No SSH key found for '{}'", service);
+        };
+
+        let options = entry.ssh.get_or_insert_with(SshOptions::default);
+
+        if let Some(confirm) = confirm {
+            options.confirm = confirm;
+        }
+
+        if let Some(lifetime) = lifetime {
+            options.lifetime = (lifetime > 0).then_some(lifetime);
+        }
+
+        Ok(())
+    }
+
+    // Store a registry login under its server URL. Returns false when it was already stored.
+    pub fn set_registry(&mut self, server: &str, username: &str, secret: &str) -> Result<bool> {
+        match self.data.entries.get(server) {
+            Some(entry) if entry.kind != EntryKind::Registry => {
+                bail_code!(ErrorCode::AlreadyExists, "'{}' already exists and is not a registry login", server);
+            }
+
+            Some(entry) if entry.password == secret && entry.username.as_deref() == Some(username) => return Ok(false),
+            Some(entry) if entry.password == secret => {}
+            Some(_) => self.update_password(server, secret)?,
+
+            None => {
+                self.data.entries.insert(server.to_string(), Entry {
+                    kind: EntryKind::Registry,
+                    ..Entry::new(secret)
+                });
+            }
+        }
+
+        self.set_details(server, Some(username), None)?;
+
+        Ok(true)
+    }
+
+    // Set the note of a service, creating a secure note entry if there is none
+    pub fn set_note(&mut self, service: &str, text: &str) -> Result<()> {
+        let now = Utc::now();
+        let entry = self.data.entries.entry(service.to_string()).or_insert_with(|| Entry {
+            kind: EntryKind::Note,
+            ..Entry::new("")
+        });
+
+        entry.notes.zeroize();
+        entry.notes = Some(text.to_string());
+        entry.modified = Some(now);
+
+        Ok(())
+    }
+
+    // Directory holding the encrypted attachment blobs of this vault
+    fn attachment_dir(&self) -> PathBuf {
+        self.file_path.with_extension("attachments")
+    }
+
+    // Encrypt data into its own blob and attach it to a service
+    pub fn add_attachment(&mut self, service: &str, name: &str, data: &[u8], max_size: u64) -> Result<()> {
+        if data.len() as u64 > max_size {
+            bail_code!(ErrorCode::InvalidInput, "Attachment is {} bytes, the limit is {} bytes", data.len(), max_size);
+        }
+
+        let dir = self.attachment_dir();
+
+        let Some(entry) = self.data.entries.get_mut(service) else {
+            bail_code!(ErrorCode::NotFound, "No entry found for '{}'", service);
+        };
+
+        if entry.attachments.iter().any(|attachment| attachment.name == name) {
+            bail_code!(ErrorCode::AlreadyExists, "'{}' already has an attachment named '{}'", service, name);
         }
 
-        self.data.entries.insert(service.to_string(), password.to_string());
+        let key = EncryptionKey::generate();
+        let id = hex::encode(rand::random::<[u8; 16]>());
+
+        fs::create_dir_all(&dir).context("Failed to create the attachment directory")?;
+        fs::write(dir.join(format!("{}.enc", id)), key.seal(data)?).context("Failed to write attachment")?;
+
+        entry.attachments.push(Attachment {
+            name: name.to_string(),
+            id,
+            size: data.len() as u64,
+            sha256: hex::encode(Sha256::digest(data)),
+            key: hex::encode(key.as_ref()),
+            added: Utc::now()
+        });
+
+        Ok(())
+    }
+
+    // Decrypt an attachment and check it against the stored hash
+    pub fn get_attachment(&self, service: &str, name: &str) -> Result<Vec<u8>> {
+        let attachment = self.data.entries.get(service)
+            .and_then(|entry| entry.attachments.iter().find(|attachment| attachment.name == name));
+
+        let Some(attachment) = attachment else {
+            bail_code!(ErrorCode::NotFound, "'{}' has no attachment named '{}'", service, name);
+        };
+
+        let sealed = fs::read(self.attachment_dir().join(format!("{}.enc", attachment.id)))
+            .context("Failed to read attachment")?;
+
+        let mut key_bytes = hex::decode(&attachment.key).unwrap_or_default();
+        let data = EncryptionKey::from_bytes(&key_bytes).and_then(|key| key.open(&sealed));
+        key_bytes.zeroize();
+
+        let Ok(data) = data else {
+            bail_code!(ErrorCode::Corrupted, "Attachment '{}' is corrupted", name);
+        };
+
+        if data.len() as u64 != attachment.size || hex::encode(Sha256::digest(&data)) != attachment.sha256 {
+            bail_code!(ErrorCode::Corrupted, "Attachment '{}' failed the integrity check", name);
+        }
+
+        Ok(data)
+    }
+
+    // Set a custom field of an entry, replacing its value if it has one
+    pub fn set_custom_field(&mut self, service: &str, name: &str, value: &str) -> Result<()> {
+        validate_field_name(name)?;
+
+        let Some(entry) = self.data.entries.get_mut(service) else {
+            bail_code!(ErrorCode::NotFound, "No entry found for '{}'", service);
+        };
+
+        if let Some(old) = entry.custom_fields.insert(name.to_string(), value.to_string()).as_mut() {
+            old.zeroize();
+        }
 
         Ok(())
     }
+
+    // Replace the Secret Service attributes of an entry
+    pub fn set_attributes(&mut self, service: &str, attributes: BTreeMap<String, String>) -> Result<()> {
+        let Some(entry) = self.data.entries.get_mut(service) else {
+            bail_code!(ErrorCode::NotFound, "No entry found for '{}'", service);
+        };
+
+        entry.attributes = attributes;
+
+        Ok(())
+    }
+
+    // Remove a custom field of an entry
+    pub fn remove_custom_field(&mut self, service: &str, name: &str) -> Result<()> {
+        let Some(entry) = self.data.entries.get_mut(service) else {
+            bail_code!(ErrorCode::NotFound, "No entry found for '{}'", service);
+        };
+
+        let Some(mut value) = entry.custom_fields.remove(name) else {
+            bail_code!(ErrorCode::NotFound, "'{}' has no field named '{}'", service, name);
+        };
+
+        value.zeroize();
+
+        Ok(())
+    }
+
+    // Detach an attachment; its blob is deleted by `purge_attachments` after saving
+    pub fn remove_attachment(&mut self, service: &str, name: &str) -> Result<()> {
+        let Some(entry) = self.data.entries.get_mut(service) else {
+            bail_code!(ErrorCode::NotFound, "No entry found for '{}'", service);
+        };
+
+        let Some(index) = entry.attachments.iter().position(|attachment| attachment.name == name) else {
+            bail_code!(ErrorCode::NotFound, "'{}' has no attachment named '{}'", service, name);
+        };
+
+        entry.attachments.remove(index).key.zeroize();
+
+        Ok(())
+    }
+
+    // Delete blobs no entry refers to anymore, so only call it once the vault is saved
+    pub fn purge_attachments(&self) -> Result<usize> {
+        let dir = self.attachment_dir();
+
+        if !dir.exists() {
+            return Ok(0);
+        }
+
+        let referenced: Vec<String> = self.data.entries.values()
+            .flat_map(|entry| entry.attachments.iter().map(|attachment| format!("{}.enc", attachment.id)))
+            .collect();
+
+        let mut removed = 0;
+
+        for file in fs::read_dir(&dir).context("Failed to read the attachment directory")? {
+            let path = file?.path();
+            let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+
+            if name.ends_with(".enc") && !referenced.contains(&name) {
+                fs::remove_file(&path).context("Failed to delete attachment")?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    // Set or clear the rotation interval of a service
+    pub fn set_rotation(&mut self, service: &str, days: Option<u32>) -> Result<()> {
+        let Some(entry) = self.data.entries.get_mut(service) else {
+            bail_code!(ErrorCode::NotFound, "No password found for '{}'", service);
+        };
+
+        entry.rotation_days = days;
+
+        Ok(())
+    }
+
+    // Set or clear (with an empty value) the username and folder
+    pub fn set_details(&mut self, service: &str, username: Option<&str>, folder: Option<&str>) -> Result<()> {
+        let Some(entry) = self.data.entries.get_mut(service) else {
+            bail_code!(ErrorCode::NotFound, "No password found for '{}'", service);
+        };
+
+        if let Some(username) = username {
+            entry.username = (!username.is_empty()).then(|| username.to_string());
+        }
+
+        if let Some(folder) = folder {
+            let folder = folder.trim_matches('/');
+            entry.folder = (!folder.is_empty()).then(|| folder.to_string());
+        }
+
+        Ok(())
+    }
+
+    // Copy an entry under a new name, keeping its history and timestamps
+    pub fn copy_entry(&mut self, from: &str, to: &str, force: bool) -> Result<()> {
+        let Some(entry) = self.data.entries.get(from) else {
+            bail_code!(ErrorCode::NotFound, "No password found for '{}'", from);
+        };
+
+        let entry = entry.clone();
+        self.insert_entry(to, entry, force)
+    }
+
+    // Rename an entry, keeping its history and timestamps
+    pub fn move_entry(&mut self, from: &str, to: &str, force: bool) -> Result<()> {
+        if from == to {
+            return Ok(());
+        }
+
+        if !self.data.entries.contains_key(from) {
+            bail_code!(ErrorCode::NotFound, "No password found for '{}'", from);
+        }
+
+        // Checked before the entry is taken out, so a bad name never loses it
+        self.check_target(to, force)?;
+
+        let entry = self.data.entries.remove(from).unwrap_or_default();
+        self.insert_entry(to, entry, true)
+    }
+
+    // Copy an entry into another vault, together with its attachment files
+    pub fn copy_entry_to(&self, from: &str, target: &mut Vault, to: &str, force: bool) -> Result<()> {
+        let Some(entry) = self.data.entries.get(from) else {
+            bail_code!(ErrorCode::NotFound, "No password found for '{}'", from);
+        };
+
+        target.check_target(to, force)?;
+
+        if !entry.attachments.is_empty() {
+            let target_dir = target.attachment_dir();
+            fs::create_dir_all(&target_dir).context("Failed to create the attachment directory")?;
+
+            for attachment in &entry.attachments {
+                let file = format!("{}.enc", attachment.id);
+
+                fs::copy(self.attachment_dir().join(&file), target_dir.join(&file))
+                    .with_context(|| format!("Failed to copy attachment '{}'", attachment.name))?;
+            }
+        }
+
+        target.insert_entry(to, entry.clone(), true)
+    }
+
+    // Whether an entry can be stored under `service`
+    fn check_target(&self, service: &str, force: bool) -> Result<()> {
+        if service.is_empty() {
+            bail_code!(ErrorCode::InvalidInput, "Service name cannot be empty");
+        }
+
+        if !force && self.data.entries.contains_key(service) {
+            bail_code!(ErrorCode::AlreadyExists, "'{}' already exists. Use --force to overwrite it.", service);
+        }
+
+        Ok(())
+    }
+
+    fn insert_entry(&mut self, service: &str, entry: Entry, force: bool) -> Result<()> {
+        self.check_target(service, force)?;
+
+        if let Some(mut replaced) = self.data.entries.insert(service.to_string(), entry) {
+            replaced.zeroize();
+        }
+
+        Ok(())
+    }
+
+    // Replace the editable fields of an entry. Nothing changes unless all of them are valid.
+    pub fn apply_fields(&mut self, service: &str, fields: &EntryFields) -> Result<()> {
+        let Some(entry) = self.data.entries.get_mut(service) else {
+            bail_code!(ErrorCode::NotFound, "No password found for '{}'", service);
+        };
+
+        let notes = fields.notes.as_deref().filter(|notes| !notes.trim().is_empty());
+
+        match (entry.kind, fields.password.as_deref()) {
+            (EntryKind::Login | EntryKind::Registry | EntryKind::SshKey, None | Some("")) => bail_code!(ErrorCode::InvalidInput, "password cannot be empty"),
+            (EntryKind::Note, Some(_)) => bail_code!(ErrorCode::InvalidInput, "'{}' is a note and has no password", service),
+            (EntryKind::Note, None) if notes.is_none() => bail_code!(ErrorCode::InvalidInput, "Note cannot be empty"),
+            _ => {}
+        }
+
+        if fields.rotation_days == Some(0) {
+            bail_code!(ErrorCode::InvalidInput, "rotation_days has to be at least 1");
+        }
+
+        for name in fields.custom_fields.keys() {
+            validate_field_name(name)?;
+        }
+
+        for url in &fields.urls {
+            validate_url(url, fields.url_match.unwrap_or_default())?;
+        }
+
+        if let Some(password) = fields.password.as_deref().filter(|password| *password != entry.password) {
+            entry.set_password(password, self.history_limit);
+        }
+
+        if notes != entry.notes.as_deref() {
+            entry.notes.zeroize();
+            entry.notes = notes.map(|notes| notes.to_string());
+
+            if entry.kind == EntryKind::Note {
+                entry.modified = Some(Utc::now());
+            }
+        }
+
+        if fields.custom_fields != entry.custom_fields {
+            zeroize_fields(&mut entry.custom_fields);
+            entry.custom_fields = fields.custom_fields.clone();
+        }
+
+        entry.rotation_days = fields.rotation_days;
+        entry.url_match = fields.url_match;
+        entry.urls.clear();
+
+        for url in fields.urls.iter().filter(|url| !url.is_empty()) {
+            if !entry.urls.contains(url) {
+                entry.urls.push(url.clone());
+            }
+        }
+        entry.tags.clear();
+
+        self.set_details(service, Some(fields.username.as_deref().unwrap_or_default()), Some(fields.folder.as_deref().unwrap_or_default()))?;
+        self.set_tags(service, &fields.tags, &[])
+    }
+
+    // Add and remove URLs, in the order they were added, and change how they are matched
+    pub fn set_urls(&mut self, service: &str, add: &[String], remove: &[String], url_match: Option<UrlMatch>) -> Result<()> {
+        let Some(entry) = self.data.entries.get_mut(service) else {
+            bail_code!(ErrorCode::NotFound, "No password found for '{}'", service);
+        };
+
+        for url in remove {
+            if !entry.urls.contains(url) {
+                bail_code!(ErrorCode::NotFound, "'{}' has no URL '{}'", service, url);
+            }
+        }
+
+        let url_match = url_match.or(entry.url_match);
+        let mut urls: Vec<String> = entry.urls.iter().filter(|url| !remove.contains(url)).cloned().collect();
+
+        for url in add.iter().filter(|url| !url.is_empty()) {
+            if !urls.contains(url) {
+                urls.push(url.clone());
+            }
+        }
+
+        // Stored URLs have to suit the mode, also when only the mode changed
+        for url in &urls {
+            validate_url(url, url_match.unwrap_or_default())?;
+        }
+
+        entry.url_match = url_match;
+        entry.urls = urls;
+
+        Ok(())
+    }
+
+    // Add and remove tags, keeping them sorted and unique
+    pub fn set_tags(&mut self, service: &str, add: &[String], remove: &[String]) -> Result<()> {
+        let Some(entry) = self.data.entries.get_mut(service) else {
+            bail_code!(ErrorCode::NotFound, "No password found for '{}'", service);
+        };
+
+        entry.tags.retain(|tag| !remove.contains(tag));
+        entry.tags.extend(add.iter().filter(|tag| !tag.is_empty()).cloned());
+        entry.tags.sort();
+        entry.tags.dedup();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault() -> Vault {
+        let mut vault = Vault::in_memory();
+        vault.add_password("github", "secret").unwrap();
+        vault.add_password("gitlab", "other").unwrap();
+
+        vault
+    }
+
+    #[test]
+    fn attachment_errors() {
+        let dir = std::env::temp_dir().join(format!("rspass-attachments-{}", std::process::id()));
+        let mut vault = Vault { file_path: dir.join("vault.enc"), ..Vault::in_memory() };
+        vault.add_password("github", "secret").unwrap();
+        vault.add_attachment("github", "codes.txt", b"recovery codes", 1024).unwrap();
+
+        assert_eq!(vault.get_attachment("github", "codes.txt").unwrap(), b"recovery codes");
+
+        for (service, name) in [("github", "other.txt"), ("gitlab", "codes.txt")] {
+            let e = vault.get_attachment(service, name).unwrap_err();
+            assert_eq!(ErrorCode::of(&e), ErrorCode::NotFound);
+        }
+
+        // Changed, then missing blob
+        let id = vault.get_entry("github").unwrap().attachments[0].id.clone();
+        let path = vault.attachment_dir().join(format!("{}.enc", id));
+        let mut sealed = fs::read(&path).unwrap();
+        *sealed.last_mut().unwrap() ^= 1;
+        fs::write(&path, &sealed).unwrap();
+
+        let e = vault.get_attachment("github", "codes.txt").unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::Corrupted);
+
+        fs::remove_file(&path).unwrap();
+        let e = vault.get_attachment("github", "codes.txt").unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::Io);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn set_urls_keeps_entry_on_error() {
+        let mut vault = vault();
+        let pattern = r"https://[a-z]+\.github\.com/.*".to_string();
+        vault.set_urls("github", std::slice::from_ref(&pattern), &[], Some(UrlMatch::Regex)).unwrap();
+
+        // The stored pattern is no address, and the new URL no valid pattern
+        let e = vault.set_urls("github", &[], &[], Some(UrlMatch::Host)).unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::InvalidInput);
+
+        let e = vault.set_urls("github", &["https://(".to_string()], std::slice::from_ref(&pattern), None).unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::InvalidInput);
+
+        let entry = vault.get_entry("github").unwrap();
+        assert_eq!(entry.urls, vec![pattern.clone()]);
+        assert_eq!(entry.url_match, Some(UrlMatch::Regex));
+
+        vault.set_urls("github", &["https://api.github.com".to_string()], &[pattern], Some(UrlMatch::Host)).unwrap();
+
+        let entry = vault.get_entry("github").unwrap();
+        assert_eq!(entry.urls, vec!["https://api.github.com".to_string()]);
+        assert_eq!(entry.url_match, Some(UrlMatch::Host));
+    }
+
+    #[test]
+    fn move_entry_renames() {
+        let mut vault = vault();
+        vault.move_entry("github", "github.com", false).unwrap();
+
+        assert!(vault.get_entry("github").is_none());
+        assert_eq!(vault.get_password("github.com").unwrap(), "secret");
+    }
+
+    #[test]
+    fn move_entry_to_empty_name_keeps_entry() {
+        let mut vault = vault();
+        let e = vault.move_entry("github", "", false).unwrap_err();
+
+        assert_eq!(ErrorCode::of(&e), ErrorCode::InvalidInput);
+        assert_eq!(vault.get_password("github").unwrap(), "secret");
+        assert_eq!(vault.list_services().len(), 2);
+
+        // Forcing does not make an empty name valid
+        assert!(vault.move_entry("github", "", true).is_err());
+        assert_eq!(vault.get_password("github").unwrap(), "secret");
+    }
+
+    #[test]
+    fn move_entry_onto_existing_needs_force() {
+        let mut vault = vault();
+        let e = vault.move_entry("github", "gitlab", false).unwrap_err();
+
+        assert_eq!(ErrorCode::of(&e), ErrorCode::AlreadyExists);
+        assert_eq!(vault.get_password("github").unwrap(), "secret");
+        assert_eq!(vault.get_password("gitlab").unwrap(), "other");
+
+        vault.move_entry("github", "gitlab", true).unwrap();
+        assert!(vault.get_entry("github").is_none());
+        assert_eq!(vault.get_password("gitlab").unwrap(), "secret");
+    }
+
+    #[test]
+    fn move_missing_entry() {
+        let mut vault = vault();
+        let e = vault.move_entry("bitbucket", "bitbucket.org", false).unwrap_err();
+
+        assert_eq!(ErrorCode::of(&e), ErrorCode::NotFound);
+        assert!(vault.get_entry("bitbucket.org").is_none());
+    }
+
+    #[test]
+    fn search_fields_include_urls() {
+        let mut vault = vault();
+        vault.set_urls("github", &["https://github.com/login".to_string(), "gist.github.com".to_string()], &[], None).unwrap();
+
+        let fields = vault.get_entry("github").unwrap().search_fields();
+
+        assert!(fields.contains(&("url", "https://github.com/login", false)));
+        assert!(fields.contains(&("url", "gist.github.com", false)));
+    }
+
+    #[test]
+    fn copy_entry_to_empty_name() {
+        let mut vault = vault();
+        let e = vault.copy_entry("github", "", false).unwrap_err();
+
+        assert_eq!(ErrorCode::of(&e), ErrorCode::InvalidInput);
+        assert_eq!(vault.list_services().len(), 2);
+    }
 }